@@ -0,0 +1,25 @@
+#version 450
+
+layout(set = 1, binding = 1) uniform sampler2D albedo;
+
+layout(location = 0) in vec3 normal;
+layout(location = 1) in vec2 tex_coord;
+layout(location = 2) in vec4 color;
+// the tile's corner and size in the atlas
+layout(location = 3) flat in vec4 tile;
+
+layout(location = 0) out vec4 out_color;
+
+// Light and ambient occlusion are baked into the vertex colour; this only
+// sets sides apart so flat ground doesn't lose its shape.
+const vec3 SUN = vec3(0.27, 0.89, 0.36);
+
+void main() {
+    vec4 texel = texture(albedo, tile.xy + fract(tex_coord) * tile.zw);
+    // cutout blocks
+    if (texel.a < 0.1) {
+        discard;
+    }
+    float facing = 0.75 + 0.25 * dot(normalize(normal), SUN);
+    out_color = vec4(texel.rgb * color.rgb * facing, texel.a * color.a);
+}
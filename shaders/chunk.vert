@@ -0,0 +1,32 @@
+#version 450
+
+// Chunk meshes: texture coordinates count blocks along the face, and each
+// vertex carries the atlas tile of its block to repeat across the quad.
+
+layout(std140, set = 0, binding = 0) uniform Projview {
+    mat4 proj;
+    mat4 view;
+};
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 tex_coord;
+layout(location = 3) in vec4 color;
+layout(location = 4) in vec4 tile;
+// per instance
+layout(location = 5) in mat4 model;
+layout(location = 9) in vec4 tint;
+
+layout(location = 0) out vec3 out_normal;
+layout(location = 1) out vec2 out_tex_coord;
+layout(location = 2) out vec4 out_color;
+layout(location = 3) flat out vec4 out_tile;
+
+void main() {
+    vec4 world_position = model * vec4(position, 1.0);
+    out_normal = mat3(model) * normal;
+    out_tex_coord = tex_coord;
+    out_color = color * tint;
+    out_tile = tile;
+    gl_Position = proj * view * world_position;
+}
@@ -1,7 +1,5 @@
 use system::ConstantRotation;
 
-use std::collections::HashMap;
-
 use amethyst::{SimpleState, StateData, GameData};
 use amethyst::core::{Transform, Parent};
 //use amethyst::renderer::palette;
@@ -13,77 +11,71 @@ use amethyst::utils::auto_fov::AutoFov;
 use specs::{World, Builder};
 //use cgmath::{vec3, Deg};
 //use cgmath::prelude::*;
-use noise::{
-    NoiseModule,
-    Add,
-    Multiply,
-    Constant,
-    Perlin,
-    ScalePoint,
-    Seedable,
-};
-use rayon::prelude::*;
 use rand;
 
-use voxel::{ChunkIndex, ChunkData, ChunkQuads, VoxelWorld};
+use voxel::{BlockRegistry, ChunkData, ChunkGenerator, ChunkLoader, ChunkStore, TerrainConfig, TerrainGenerator, TextureAtlas, VoxelWorld, WorldLight};
+
+/// The terrain generator of the world in `store`, picking a seed for new
+/// worlds.
+pub fn world_generator(store: &ChunkStore, registry: &BlockRegistry) -> Box<TerrainGenerator> {
+    let seed = match store.seed() {
+        Ok(Some(seed)) => seed,
+        Ok(None) => {
+            let seed = rand::random();
+            if let Err(e) = store.set_seed(seed) {
+                eprintln!("Failed to save world seed: {}", e);
+            }
+            seed
+        },
+        Err(e) => {
+            eprintln!("Failed to read world seed: {}", e);
+            rand::random()
+        },
+    };
+
+    // new worlds get the default terrain, which is then kept with the world
+    let terrain = match store.terrain_config() {
+        Ok(Some(config)) => config,
+        Ok(None) => {
+            let config = TerrainConfig::default();
+            if let Err(e) = store.set_terrain_config(&config) {
+                eprintln!("Failed to save terrain config: {}", e);
+            }
+            config
+        },
+        Err(e) => panic!("Failed to read terrain config: {}", e),
+    };
+    terrain.build(seed, registry)
+        .unwrap_or_else(|e| panic!("Failed to set up terrain generator: {}", e))
+}
 
 /// Initial state
 pub struct PhantomInit;
 
 impl SimpleState for PhantomInit {
     fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
-        // make some noise...
-        let noise = Multiply::new(
-            Constant::new(8.),
-            Add::new(
-                Constant::new(0.5),
-                Multiply::new(
-                    Constant::new(0.5),
-                    ScalePoint::new(Perlin::new().set_seed(rand::random())).set_scale(0.5),
-                )
-            )
-        );
+        let registry = BlockRegistry::load("resources/blocks.ron")
+            .unwrap_or_else(|e| panic!("Failed to load the block registry: {}", e));
+        let atlas = TextureAtlas::build(&registry, "resources/textures", 16);
+
+        let store = ChunkStore::open("world").expect("Failed to open the world directory");
+        let generator = world_generator(&store, &registry);
+
+        // worlds can ask for smooth terrain in a mesher.ron
+        let mesher = store.mesher().unwrap_or_else(|e| {
+            eprintln!("Failed to read world mesher: {}", e);
+            None
+        });
+        data.world.add_resource(mesher.unwrap_or_default());
 
         data.world.add_resource(VoxelWorld::new());
+        data.world.add_resource(WorldLight::new());
+        data.world.add_resource(store);
+        data.world.add_resource(registry);
+        data.world.add_resource(atlas);
 
-        const SIZE_X: i32 = 12;
-        const SIZE_Z: i32 = 12;
-
-        let datas: HashMap<i32, HashMap<i32, ChunkData>> = ((-SIZE_X)..(SIZE_X)).into_par_iter().map(|chunk_x| {
-            (chunk_x, ((-SIZE_Z)..(SIZE_Z)).into_par_iter().map(|chunk_z| {
-                let mut chunk_data = ChunkData::default();
-                for x in 0..16 {
-                    for z in 0..16 {
-                        let height = (noise.get([
-                            ((x as i32 + chunk_x*16) as f32)/16.,
-                            ((z as i32 + chunk_z*16) as f32)/16.,
-                        ])).round() as i64;
-                        if height < 0 {
-                            panic!("height < 0");
-                        }
-                        if height >= 16 {
-                            println!("{}", height);
-                            panic!();
-                        }
-                        for y in 0..height {
-                            chunk_data.set_voxel((x, y as usize, z), 1);
-                        }
-                    }
-                }
-                (chunk_z, chunk_data)
-            }).collect::<HashMap<_, _>>())
-        }).collect();
-
-        for x in (-SIZE_X)..(SIZE_X) {
-            for z in (-SIZE_Z)..(SIZE_Z) {
-                data.world.create_entity()
-                    .with::<ChunkIndex>((x, 0, z).into())
-                    .with(datas[&x][&z])
-                    .with(Transform::default())
-                    .with(ChunkQuads::default())
-                    .build();
-            }
-        }
+        // chunks are generated as ChunkStreamingSystem asks for them
+        data.world.add_resource(ChunkGenerator::new(generator));
 
 //         let mut chunk_data = ChunkData::default();
 //         for x in 0..16 {
@@ -140,7 +132,7 @@ impl SimpleState for PhantomInit {
         let camera_target = data.world.create_entity()
             .with({
                 Transform::default()
-                    .set_translation_xyz(8., 8., 0.)
+                    .set_translation_xyz(8., 192., 0.)
                     .clone()
             })
             .with(ConstantRotation)
@@ -165,6 +157,11 @@ impl SimpleState for PhantomInit {
                 let mut f = AutoFov::default();
                 f
             })
+            .with(ChunkLoader {
+                radius: 12,
+                unload_radius: 14,
+                vertical_radius: 1,
+            })
             .build();
 
         // add a directional light for clarity
@@ -192,4 +189,13 @@ impl SimpleState for PhantomInit {
             }))
             .build();
     }
+
+    fn on_stop(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        let voxel_world = data.world.read_resource::<VoxelWorld>();
+        let chunk_datas = data.world.read_storage::<ChunkData>();
+        let mut store = data.world.write_resource::<ChunkStore>();
+        if let Err(e) = store.save_dirty(&voxel_world, &chunk_datas) {
+            eprintln!("Failed to save chunks: {}", e);
+        }
+    }
 }
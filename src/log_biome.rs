@@ -0,0 +1,38 @@
+use voxel::{ChunkGenerator, ChunkLoader};
+
+use amethyst::core::Transform;
+use specs::{
+    Join,
+    ReadStorage,
+    System,
+};
+use shred::ReadExpect;
+
+/// Logs the biome under the first `ChunkLoader` whenever it changes.
+#[derive(Default)]
+pub struct LogBiome {
+    last: Option<String>,
+}
+
+impl<'a> System<'a> for LogBiome {
+    type SystemData = (
+        ReadStorage<'a, ChunkLoader>,
+        ReadStorage<'a, Transform>,
+        ReadExpect<'a, ChunkGenerator>,
+    );
+
+    fn run(&mut self, (loaders, transforms, generator): Self::SystemData) {
+        let m = match (&loaders, &transforms).join().next() {
+            Some((_, transform)) => transform.global_matrix(),
+            None => return,
+        };
+        let biome = generator.biome_at(m[(0, 3)].floor() as i32, m[(2, 3)].floor() as i32)
+            .map(|biome| biome.name.clone());
+        if biome != self.last {
+            if let Some(ref name) = biome {
+                println!("Entered {}", name);
+            }
+            self.last = biome;
+        }
+    }
+}
@@ -4,11 +4,18 @@ extern crate itertools;
 // extern crate nalgebra;
 extern crate rayon;
 extern crate amethyst;
-extern crate genmesh;
 extern crate hibitset;
 extern crate noise;
 extern crate fnv;
 extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate ron;
+extern crate image;
+extern crate flate2;
+#[macro_use]
+extern crate lazy_static;
 
 pub use amethyst::shred as shred;
 pub use amethyst::shrev as shrev;
@@ -19,13 +26,15 @@ mod voxel;
 mod app;
 mod system;
 mod log_fps;
+mod log_biome;
+mod tools;
 
 use std::time::Duration;
 
 use amethyst::{Application, GameDataBuilder};
 use amethyst::core::frame_limiter::FrameRateLimitStrategy;
 //use amethyst::renderer::{RenderSystem, Pipeline, Stage, DisplayConfig, DrawShaded, PosNormTex};
-use amethyst::renderer::{RenderingBundle, plugins::RenderShaded3D, plugins::RenderSkybox, plugins::RenderToWindow, types::DefaultBackend, bundle::Target};
+use amethyst::renderer::{RenderingBundle, plugins::RenderSkybox, plugins::RenderToWindow, types::DefaultBackend, bundle::Target};
 use amethyst::renderer::palette::rgb::Srgb;
 use amethyst::window::DisplayConfig;
 
@@ -49,12 +58,13 @@ fn run() -> amethyst::Result<()> {
         .with_bundle(amethyst::core::transform::TransformBundle::new())?
         .with_bundle(amethyst::utils::fps_counter::FpsCounterBundle::default())?
         .with(system::IntervalSystem::wrap(log_fps::LogFps, Duration::from_secs(1)), "debug_log_fps", &[])
+        .with(system::IntervalSystem::wrap(log_biome::LogBiome::default(), Duration::from_secs(1)), "debug_log_biome", &[])
         .with(system::ConstantRotationSystem::default(), "constant_rotation_system", &[])
         .with(amethyst::utils::auto_fov::AutoFovSystem::default(), "auto_fov", &[])
         .with_bundle(
             RenderingBundle::<DefaultBackend>::new()
                 .with_plugin(RenderToWindow::from_config(display_config))
-                .with_plugin(RenderShaded3D::default())
+                .with_plugin(voxel::RenderChunks::default())
                 .with_plugin(RenderSkybox::with_colors(
                     Srgb::new(0.82, 0.51, 0.50),
                     Srgb::new(0.18, 0.11, 0.85),
@@ -70,6 +80,16 @@ fn run() -> amethyst::Result<()> {
 }
 
 fn main() {
+    // any arguments run a command line tool instead of the game
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = tools::run(&args) {
+            eprintln!("{}", e);
+            ::std::process::exit(1);
+        }
+        return;
+    }
+
     if let Err(e) = run() {
         eprintln!("Fatal error: {}\n\n {:?}", e, e);
         ::std::process::exit(1);
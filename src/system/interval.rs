@@ -2,6 +2,7 @@ use std::time::Duration;
 
 use amethyst::core::timing::Stopwatch;
 use specs::System;
+use shred::Resources;
 
 /// Executes an inner system in a fixed interval.
 pub struct IntervalSystem<S> {
@@ -43,4 +44,8 @@ impl<'a, S> System<'a> for IntervalSystem<S> where S: System<'a> {
             }
         }
     }
+
+    fn setup(&mut self, res: &mut Resources) {
+        self.inner.setup(res);
+    }
 }
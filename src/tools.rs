@@ -0,0 +1,246 @@
+//! Command line tools that work on a world directory without opening a
+//! window:
+//!
+//! - `--export <world dir> <x,y,z> <obj|ply|glb|vox> <out file>` writes the
+//!   mesh, or the voxels as a `.vox`, of one region of the world.
+//! - `--import <world dir> <model> <x,y,z> [<key>=<block> ...]` pastes a
+//!   model into the world at `x,y,z`.
+//! - `--convert <model> <obj|ply|glb> <out file>` writes a model's mesh.
+//!
+//! Models are MagicaVoxel `.vox` files, whose palette colours become the
+//! block closest in colour, or Sponge `.schem` and MCEdit `.schematic` files,
+//! whose blocks are mapped by `resources/schematic_blocks.ron`. Extra
+//! `<key>=<block>` arguments override a colour index or Minecraft block name.
+
+use app::world_generator;
+use voxel::{
+    BlockRegistry,
+    ChunkData,
+    ChunkGenerator,
+    ChunkStore,
+    MeshExport,
+    Schematic,
+    SchematicMapping,
+    VoxFile,
+    VoxPaletteMap,
+    Voxel,
+    AIR,
+    CHUNK_SIZE,
+    world_to_chunk,
+};
+use voxel::region::REGION_SIZE;
+
+use std::error::Error;
+use std::path::Path;
+
+use fnv::FnvHashMap;
+
+const USAGE: &'static str = "usage:
+    voxld --export <world dir> <x,y,z> <obj|ply|glb|vox> <out file>
+    voxld --import <world dir> <model> <x,y,z> [<key>=<block> ...]
+    voxld --convert <model> <obj|ply|glb> <out file>
+models are .vox, .schem or .schematic files";
+
+/// Runs the tool named by the first argument.
+pub fn run(args: &[String]) -> Result<(), Box<Error>> {
+    let registry = BlockRegistry::load("resources/blocks.ron")?;
+    match args.first().map(|arg| arg.as_str()) {
+        Some("--export") if args.len() == 5 => export(&args[1], parse_coords(&args[2])?, &args[3], &args[4], &registry),
+        Some("--import") if args.len() >= 4 => import(&args[1], &args[2], parse_coords(&args[3])?, &args[4..], &registry),
+        Some("--convert") if args.len() == 4 => convert(&args[1], &args[2], &args[3], &registry),
+        _ => Err(USAGE.into()),
+    }
+}
+
+fn export(root: &str, region: (i32, i32, i32), format: &str, out: &str, registry: &BlockRegistry) -> Result<(), Box<Error>> {
+    let mut world = ToolWorld::open(root, registry)?;
+    let mut indices = Vec::new();
+    for x in 0..REGION_SIZE {
+        for y in 0..REGION_SIZE {
+            for z in 0..REGION_SIZE {
+                indices.push((region.0 * REGION_SIZE + x, region.1 * REGION_SIZE + y, region.2 * REGION_SIZE + z));
+            }
+        }
+    }
+    world.load(indices)?;
+
+    if format == "vox" {
+        let size = REGION_SIZE * CHUNK_SIZE as i32;
+        let min = (region.0 * size, region.1 * size, region.2 * size);
+        let max = (min.0 + size - 1, min.1 + size - 1, min.2 + size - 1);
+        let chunks = &world.chunks;
+        let file = VoxFile::from_world(registry, min, max, |position| {
+            let (index, local) = world_to_chunk(position);
+            chunks.get(&index).map(|data| data.get_voxel(local))
+        })?;
+        file.save(out)?;
+        return Ok(());
+    }
+    save_mesh(&MeshExport::from_chunks(&world.chunks, registry), format, out)
+}
+
+fn import(root: &str, path: &str, origin: (i32, i32, i32), overrides: &[String], registry: &BlockRegistry) -> Result<(), Box<Error>> {
+    let model = Model::load(path, overrides, registry)?;
+    let mut blocks = Vec::new();
+    model.place(origin, |position, voxel| blocks.push((position, voxel)));
+
+    let mut world = ToolWorld::open(root, registry)?;
+    world.load(blocks.iter().map(|&(position, _)| world_to_chunk(position).0))?;
+    for (position, voxel) in blocks {
+        let (index, local) = world_to_chunk(position);
+        if let Some(data) = world.chunks.get_mut(&index) {
+            data.set_voxel(local, voxel);
+        }
+    }
+    world.save()
+}
+
+fn convert(path: &str, format: &str, out: &str, registry: &BlockRegistry) -> Result<(), Box<Error>> {
+    let model = Model::load(path, &[], registry)?;
+    let chunks = match model {
+        Model::Vox(ref file, ref mapping) => file.to_chunks(mapping, (0, 0, 0)),
+        Model::Schematic(ref schematic) => schematic.to_chunks((0, 0, 0)),
+    };
+    save_mesh(&MeshExport::from_chunks(&chunks, registry), format, out)
+}
+
+fn save_mesh(mesh: &MeshExport, format: &str, out: &str) -> Result<(), Box<Error>> {
+    match format {
+        "obj" => mesh.save_obj(out)?,
+        "ply" => mesh.save_ply(out)?,
+        "glb" => mesh.save_glb(out)?,
+        other => return Err(format!("unknown mesh format {:?}", other).into()),
+    }
+    Ok(())
+}
+
+/// A model file with its blocks mapped.
+enum Model {
+    Vox(VoxFile, VoxPaletteMap),
+    Schematic(Schematic),
+}
+
+impl Model {
+    fn load(path: &str, overrides: &[String], registry: &BlockRegistry) -> Result<Model, Box<Error>> {
+        // the key can hold `=` itself, as in block states
+        let mut mapped = Vec::new();
+        for arg in overrides.iter() {
+            let mut parts = arg.rsplitn(2, '=');
+            let (block, key) = match (parts.next(), parts.next()) {
+                (Some(block), Some(key)) => (block, key),
+                _ => return Err(format!("expected <key>=<block> but got {:?}", arg).into()),
+            };
+            let voxel = registry.voxel(block).ok_or_else(|| format!("unknown block {:?}", block))?;
+            mapped.push((key, voxel));
+        }
+
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("vox") => {
+                let file = VoxFile::load(path)?;
+                let mut mapping = VoxPaletteMap::from_registry(registry, &file.palette);
+                for (colour, voxel) in mapped {
+                    mapping.set(colour.parse()?, voxel);
+                }
+                Ok(Model::Vox(file, mapping))
+            },
+            Some("schem") | Some("schematic") => {
+                let mut mapping = SchematicMapping::load("resources/schematic_blocks.ron", registry)?;
+                for (name, voxel) in mapped {
+                    mapping.insert(name, voxel);
+                }
+                let schematic = Schematic::load(path, &mapping)?;
+                if !schematic.unmapped.is_empty() {
+                    let mut unmapped: Vec<_> = schematic.unmapped.iter().collect();
+                    unmapped.sort();
+                    eprintln!("Leaving out blocks with no mapping: {:?}", unmapped);
+                }
+                Ok(Model::Schematic(schematic))
+            },
+            _ => Err(format!("{:?} isn't a .vox, .schem or .schematic file", path).into()),
+        }
+    }
+
+    /// Calls `place` for each of the model's blocks, with its origin at
+    /// `origin`.
+    fn place<F>(&self, origin: (i32, i32, i32), place: F)
+    where F: FnMut((i32, i32, i32), Voxel)
+    {
+        match *self {
+            Model::Vox(ref file, ref mapping) => file.place(mapping, origin, place),
+            Model::Schematic(ref schematic) => schematic.place(origin, place),
+        }
+    }
+}
+
+/// A world's chunks, loaded or generated the way the game would.
+struct ToolWorld {
+    store: ChunkStore,
+    generator: ChunkGenerator,
+    chunks: FnvHashMap<(i32, i32, i32), ChunkData>,
+}
+
+impl ToolWorld {
+    fn open(root: &str, registry: &BlockRegistry) -> Result<Self, Box<Error>> {
+        let store = ChunkStore::open(root)?;
+        let generator = ChunkGenerator::new(world_generator(&store, registry));
+        Ok(ToolWorld { store, generator, chunks: FnvHashMap::default() })
+    }
+
+    /// Loads the given chunks, generating the ones that were never saved
+    /// along with the features that start in them.
+    fn load<I>(&mut self, indices: I) -> Result<(), Box<Error>>
+    where I: IntoIterator<Item=(i32, i32, i32)>
+    {
+        let mut features = Vec::new();
+        for index in indices {
+            if self.chunks.contains_key(&index) {
+                continue;
+            }
+            let mut data = match self.store.load_chunk(index)? {
+                Some(data) => data,
+                None => {
+                    features.extend(self.generator.features(index));
+                    self.generator.generate(index)
+                },
+            };
+            for (local, voxel) in self.store.take_pending(index) {
+                if data.get_voxel(local) == AIR {
+                    data.set_voxel(local, voxel);
+                }
+            }
+            self.chunks.insert(index, data);
+        }
+        // features never cut into what's already there, as in the game
+        for (position, voxel) in features {
+            let (index, local) = world_to_chunk(position);
+            match self.chunks.get_mut(&index) {
+                Some(data) => {
+                    if data.get_voxel(local) == AIR {
+                        data.set_voxel(local, voxel);
+                    }
+                },
+                None => self.store.queue_block(position, voxel),
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves every loaded chunk, and the feature blocks left waiting for
+    /// chunks that weren't.
+    fn save(&mut self) -> Result<(), Box<Error>> {
+        self.store.save_chunks(self.chunks.iter().map(|(&index, data)| (index, data)))?;
+        self.store.save_pending()?;
+        Ok(())
+    }
+}
+
+/// Parses `x,y,z`.
+fn parse_coords(arg: &str) -> Result<(i32, i32, i32), Box<Error>> {
+    let parts = arg.split(',')
+        .map(|part| part.trim().parse::<i32>())
+        .collect::<Result<Vec<_>, _>>()?;
+    match parts.as_slice() {
+        &[x, y, z] => Ok((x, y, z)),
+        _ => Err(format!("expected x,y,z but got {:?}", arg).into()),
+    }
+}
@@ -0,0 +1,197 @@
+use super::{VoxelFace, Side, SIDES};
+use super::block::BlockRegistry;
+
+use std::path::Path;
+
+use fnv::FnvHashMap;
+use image::{self, FilterType, RgbaImage};
+
+/// A resource holding every block texture packed into one image, and which
+/// tile each block face uses.
+///
+/// Built once at startup from `<dir>/<texture name>.png` for each texture the
+/// block registry names. Blocks without a texture, or whose texture fails to
+/// load, get a tile filled with their registry colour instead.
+#[derive(Clone)]
+pub struct TextureAtlas {
+    tile_size: u32,
+    tiles_per_row: u32,
+    width: u32,
+    height: u32,
+    /// RGBA8, row-major
+    pixels: Vec<u8>,
+    tiles: FnvHashMap<(u16, Side), u32>,
+    /// plain white, for ids the registry doesn't know about
+    fallback_tile: u32,
+}
+
+impl TextureAtlas {
+    pub fn build<P: AsRef<Path>>(registry: &BlockRegistry, dir: P, tile_size: u32) -> Self {
+        let dir = dir.as_ref();
+
+        // tile images in atlas order, keyed by texture name (or block id, for colour tiles)
+        let mut images: Vec<RgbaImage> = Vec::new();
+        let mut named: FnvHashMap<String, Option<u32>> = FnvHashMap::default();
+        let mut coloured: FnvHashMap<u16, u32> = FnvHashMap::default();
+        let mut tiles = FnvHashMap::default();
+
+        for def in registry.iter() {
+            for side in SIDES.iter() {
+                let texture_tile = def.textures.texture(*side).and_then(|name| {
+                    if let Some(tile) = named.get(name) {
+                        return *tile;
+                    }
+                    let path = dir.join(name).with_extension("png");
+                    let tile = match image::open(&path) {
+                        Ok(img) => {
+                            let img = image::imageops::resize(&img.to_rgba(), tile_size, tile_size, FilterType::Nearest);
+                            images.push(img);
+                            Some(images.len() as u32 - 1)
+                        },
+                        Err(e) => {
+                            eprintln!("Failed to load block texture {:?}, using the block colour: {}", path, e);
+                            None
+                        },
+                    };
+                    named.insert(name.to_string(), tile);
+                    tile
+                });
+
+                let tile = texture_tile.unwrap_or_else(|| {
+                    *coloured.entry(def.id).or_insert_with(|| {
+                        let c = def.color;
+                        let pixel = image::Rgba([
+                            (c[0] * 255.) as u8,
+                            (c[1] * 255.) as u8,
+                            (c[2] * 255.) as u8,
+                            (c[3] * 255.) as u8,
+                        ]);
+                        images.push(RgbaImage::from_pixel(tile_size, tile_size, pixel));
+                        images.len() as u32 - 1
+                    })
+                });
+                tiles.insert((def.id, *side), tile);
+            }
+        }
+
+        images.push(RgbaImage::from_pixel(tile_size, tile_size, image::Rgba([255, 255, 255, 255])));
+        let fallback_tile = images.len() as u32 - 1;
+
+        let count = images.len() as u32;
+        let tiles_per_row = (count as f32).sqrt().ceil() as u32;
+        let rows = (count + tiles_per_row - 1) / tiles_per_row;
+        let width = tiles_per_row * tile_size;
+        let height = rows * tile_size;
+
+        let mut atlas = RgbaImage::new(width, height);
+        for (i, img) in images.iter().enumerate() {
+            let i = i as u32;
+            image::imageops::replace(&mut atlas, img, (i % tiles_per_row) * tile_size, (i / tiles_per_row) * tile_size);
+        }
+
+        TextureAtlas {
+            tile_size,
+            tiles_per_row,
+            width,
+            height,
+            pixels: atlas.into_raw(),
+            tiles,
+            fallback_tile,
+        }
+    }
+
+    /// The tile showing the given voxel face.
+    #[inline]
+    pub fn tile(&self, face: VoxelFace) -> u32 {
+        self.tiles.get(&(face.voxel.id, face.side))
+            .cloned()
+            .unwrap_or(self.fallback_tile)
+    }
+
+    /// The texture coordinates of a tile's corners, `(min, max)`. Inset by half
+    /// a texel so neighbouring tiles never bleed in.
+    pub fn tile_uv(&self, tile: u32) -> ([f32; 2], [f32; 2]) {
+        let x = ((tile % self.tiles_per_row) * self.tile_size) as f32;
+        let y = ((tile / self.tiles_per_row) * self.tile_size) as f32;
+        let size = self.tile_size as f32;
+        let (w, h) = (self.width as f32, self.height as f32);
+        (
+            [(x + 0.5) / w, (y + 0.5) / h],
+            [(x + size - 0.5) / w, (y + size - 0.5) / h],
+        )
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// The atlas image as row-major RGBA8.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Voxel;
+
+    fn atlas() -> (BlockRegistry, TextureAtlas) {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let registry = BlockRegistry::load(root.join("resources/blocks.ron")).unwrap();
+        let atlas = TextureAtlas::build(&registry, root.join("resources/textures"), 16);
+        (registry, atlas)
+    }
+
+    /// The atlas pixel a texture coordinate lands on.
+    fn texel(atlas: &TextureAtlas, uv: [f32; 2]) -> [u8; 4] {
+        let (width, height) = atlas.size();
+        let x = (uv[0] * width as f32) as usize;
+        let y = (uv[1] * height as f32) as usize;
+        let i = (y * width as usize + x) * 4;
+        let p = &atlas.pixels()[i..i + 4];
+        [p[0], p[1], p[2], p[3]]
+    }
+
+    #[test]
+    fn tile_uvs() {
+        let (registry, atlas) = atlas();
+        let grass = registry.voxel("grass").unwrap();
+        let dirt = registry.voxel("dirt").unwrap();
+        assert!(atlas.tile(grass.face(Side::Top)) != atlas.tile(grass.face(Side::North)));
+        assert_eq!(atlas.tile(grass.face(Side::Bottom)), atlas.tile(dirt.face(Side::Top)));
+
+        let mut rects: Vec<([f32; 2], [f32; 2])> = Vec::new();
+        for def in registry.iter() {
+            for side in SIDES.iter() {
+                let (min, max) = atlas.tile_uv(atlas.tile(Voxel::new(def.id).face(*side)));
+                for axis in 0..2 {
+                    assert!(0. < min[axis] && min[axis] < max[axis] && max[axis] < 1.);
+                }
+                if !rects.contains(&(min, max)) {
+                    rects.push((min, max));
+                }
+            }
+        }
+        for (i, a) in rects.iter().enumerate() {
+            for b in &rects[i + 1..] {
+                let apart = a.1[0] < b.0[0] || b.1[0] < a.0[0] || a.1[1] < b.0[1] || b.1[1] < a.0[1];
+                assert!(apart, "tiles {:?} and {:?} overlap", a, b);
+            }
+        }
+
+        // grass_side.png is green along its top and dirt below
+        let (min, max) = atlas.tile_uv(atlas.tile(grass.face(Side::North)));
+        let (top, bottom) = (texel(&atlas, min), texel(&atlas, max));
+        assert!(top[1] > top[0], "{:?}", top);
+        assert!(bottom[0] > bottom[1], "{:?}", bottom);
+    }
+
+    #[test]
+    fn unknown_blocks_are_white() {
+        let (_, atlas) = atlas();
+        let (min, max) = atlas.tile_uv(atlas.tile(Voxel::new(999).face(Side::Top)));
+        assert_eq!(texel(&atlas, min), [255, 255, 255, 255]);
+        assert_eq!(texel(&atlas, max), [255, 255, 255, 255]);
+    }
+}
@@ -0,0 +1,214 @@
+use super::{Voxel, Side, AIR};
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use fnv::FnvHashMap;
+use ron;
+
+/// The properties of one block type, as listed in the block file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockDef {
+    pub id: u16,
+    pub name: String,
+    /// How much of what's behind the block shows through it.
+    #[serde(default)]
+    pub transparency: Transparency,
+    /// Whether the block stops movement, rays and projectiles.
+    #[serde(default = "default_true")]
+    pub solid: bool,
+    /// Light emitted by the block, from 0 to 15.
+    #[serde(default)]
+    pub emissive: u8,
+    #[serde(default)]
+    pub textures: BlockTextures,
+    #[serde(default = "default_color")]
+    pub color: [f32; 4],
+}
+
+/// How a block lets the view through, which decides the faces kept against
+/// it and the pass it's drawn in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Transparency {
+    /// Hides the faces of blocks behind it.
+    Opaque,
+    /// See-through only where its texture is fully transparent, like leaves.
+    /// Drawn with the opaque mesh, relying on the material's alpha cutoff.
+    Cutout,
+    /// Blends with what's behind it, like water or tinted glass. Drawn in a
+    /// separate, sorted mesh.
+    Translucent,
+}
+
+impl Default for Transparency {
+    fn default() -> Self {
+        Transparency::Opaque
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_color() -> [f32; 4] {
+    [1., 1., 1., 1.]
+}
+
+/// Texture names for each side of a block, in the block's own orientation.
+///
+/// The most specific entry wins: a named side, then `side` for the four
+/// horizontal sides, then `all`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BlockTextures {
+    #[serde(default)]
+    pub all: Option<String>,
+    #[serde(default)]
+    pub side: Option<String>,
+    #[serde(default)]
+    pub top: Option<String>,
+    #[serde(default)]
+    pub bottom: Option<String>,
+    #[serde(default)]
+    pub north: Option<String>,
+    #[serde(default)]
+    pub south: Option<String>,
+    #[serde(default)]
+    pub east: Option<String>,
+    #[serde(default)]
+    pub west: Option<String>,
+}
+
+impl BlockTextures {
+    pub fn texture(&self, side: Side) -> Option<&str> {
+        let specific = match side {
+            Side::Top => &self.top,
+            Side::Bottom => &self.bottom,
+            Side::North => &self.north,
+            Side::South => &self.south,
+            Side::East => &self.east,
+            Side::West => &self.west,
+        };
+        let horizontal = match side {
+            Side::Top | Side::Bottom => &None,
+            _ => &self.side,
+        };
+        specific.as_ref()
+            .or(horizontal.as_ref())
+            .or(self.all.as_ref())
+            .map(|name| name.as_str())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct BlockFile {
+    blocks: Vec<BlockDef>,
+}
+
+#[derive(Debug)]
+pub enum BlockRegistryError {
+    Io(io::Error),
+    Parse(ron::de::Error),
+    DuplicateId(u16),
+    DuplicateName(String),
+}
+
+impl fmt::Display for BlockRegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlockRegistryError::Io(ref e) => write!(f, "failed to read block file: {}", e),
+            BlockRegistryError::Parse(ref e) => write!(f, "failed to parse block file: {}", e),
+            BlockRegistryError::DuplicateId(id) => write!(f, "block id {} is defined twice", id),
+            BlockRegistryError::DuplicateName(ref name) => write!(f, "block name {:?} is defined twice", name),
+        }
+    }
+}
+
+impl Error for BlockRegistryError {}
+
+/// A resource describing every block type, indexed by block id.
+///
+/// Ids missing from the registry behave like a plain opaque, solid block,
+/// and `AIR` is always empty no matter what the file says.
+#[derive(Clone, Debug, Default)]
+pub struct BlockRegistry {
+    blocks: Vec<Option<BlockDef>>,
+    by_name: FnvHashMap<String, u16>,
+}
+
+impl BlockRegistry {
+    /// Loads the registry from a RON block file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, BlockRegistryError> {
+        let contents = fs::read_to_string(path).map_err(BlockRegistryError::Io)?;
+        let file: BlockFile = ron::de::from_str(&contents).map_err(BlockRegistryError::Parse)?;
+        BlockRegistry::from_defs(file.blocks)
+    }
+
+    pub fn from_defs(defs: Vec<BlockDef>) -> Result<Self, BlockRegistryError> {
+        let mut registry = BlockRegistry::default();
+        for def in defs {
+            let id = def.id as usize;
+            if registry.blocks.len() <= id {
+                registry.blocks.resize(id + 1, None);
+            }
+            if registry.blocks[id].is_some() {
+                return Err(BlockRegistryError::DuplicateId(def.id));
+            }
+            if registry.by_name.insert(def.name.clone(), def.id).is_some() {
+                return Err(BlockRegistryError::DuplicateName(def.name));
+            }
+            registry.blocks[id] = Some(def);
+        }
+        Ok(registry)
+    }
+
+    #[inline]
+    pub fn get(&self, id: u16) -> Option<&BlockDef> {
+        self.blocks.get(id as usize).and_then(|def| def.as_ref())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&BlockDef> {
+        self.blocks.iter().filter_map(|def| def.as_ref())
+    }
+
+    /// Looks up a block id by name.
+    pub fn id(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).cloned()
+    }
+
+    /// The default-state voxel for the named block.
+    pub fn voxel(&self, name: &str) -> Option<Voxel> {
+        self.id(name).map(Voxel::new)
+    }
+
+    /// `None` for `AIR`, which is empty rather than transparent.
+    #[inline]
+    pub fn transparency(&self, voxel: Voxel) -> Option<Transparency> {
+        if voxel == AIR {
+            return None;
+        }
+        Some(self.get(voxel.id).map(|def| def.transparency).unwrap_or(Transparency::Opaque))
+    }
+
+    #[inline]
+    pub fn is_opaque(&self, voxel: Voxel) -> bool {
+        self.transparency(voxel) == Some(Transparency::Opaque)
+    }
+
+    #[inline]
+    pub fn is_translucent(&self, voxel: Voxel) -> bool {
+        self.transparency(voxel) == Some(Transparency::Translucent)
+    }
+
+    #[inline]
+    pub fn is_solid(&self, voxel: Voxel) -> bool {
+        voxel != AIR && self.get(voxel.id).map(|def| def.solid).unwrap_or(true)
+    }
+
+    #[inline]
+    pub fn emissive(&self, voxel: Voxel) -> u8 {
+        self.get(voxel.id).map(|def| def.emissive).unwrap_or(0)
+    }
+}
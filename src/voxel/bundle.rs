@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use amethyst::core::bundle::SystemBundle;
 use amethyst::Error as AmethystError;
 use specs::{
@@ -15,7 +17,13 @@ use super::{
     Bookkeeper,
     MeshFaceSystem,
     ChunkMaterialSystem,
+    ChunkDirtySystem,
+    ChunkSaveSystem,
+    ChunkStreamingSystem,
+    LightSystem,
+    ChunkLodSystem,
 };
+use system::IntervalSystem;
 
 pub struct VoxelBundle;
 
@@ -29,8 +37,25 @@ impl<'a, 'b> SystemBundle<'a, 'b> for VoxelBundle {
 //        world.register::<ChunkQuads>();
         dispatcher.add(Bookkeeper, "voxel_world_bookkeeper", &[]);
         dispatcher.add(ChunkIndexPositionSystem, "chunk_index_position_system", &[]);
-        dispatcher.add(MeshFaceSystem::default(), "chunk_mesh_face_system", &[]);
+        dispatcher.add(ChunkDirtySystem::default(), "chunk_dirty_system", &[]);
+        dispatcher.add(
+            ChunkStreamingSystem::default(),
+            "chunk_streaming_system",
+            &["voxel_world_bookkeeper", "chunk_dirty_system"],
+        );
+        dispatcher.add(LightSystem::default(), "chunk_light_system", &["voxel_world_bookkeeper", "chunk_streaming_system"]);
+        dispatcher.add(ChunkLodSystem::default(), "chunk_lod_system", &["chunk_streaming_system"]);
+        dispatcher.add(
+            MeshFaceSystem::default(),
+            "chunk_mesh_face_system",
+            &["voxel_world_bookkeeper", "chunk_streaming_system", "chunk_light_system", "chunk_lod_system"],
+        );
         dispatcher.add(ChunkMaterialSystem::default(), "chunk_material_system", &[]);
+        dispatcher.add(
+            IntervalSystem::wrap(ChunkSaveSystem, Duration::from_secs(10)),
+            "chunk_save_system",
+            &["chunk_dirty_system"],
+        );
         Ok(())
     }
 }
@@ -1,4 +1,4 @@
-use super::{Axis, Side};
+use super::{Axis, Face, Side};
 
 use specs::{
     Component,
@@ -6,45 +6,176 @@ use specs::{
     HashMapStorage,
 };
 
-pub type Voxel = u8;
+/// A voxel: a 16-bit block id plus 16 bits of block state.
+///
+/// The low two state bits are the block's orientation, i.e. which axis its
+/// top and bottom faces point along. The rest of the state is up to the block
+/// (growth stage, fluid level and so on).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Voxel {
+    pub id: u16,
+    pub state: u16,
+}
+
+/// The empty voxel.
+pub const AIR: Voxel = Voxel { id: 0, state: 0 };
+
+const ORIENTATION_MASK: u16 = 0b11;
+
+impl Voxel {
+    pub const fn new(id: u16) -> Voxel {
+        Voxel { id, state: 0 }
+    }
+
+    pub fn with_state(self, state: u16) -> Voxel {
+        Voxel { state, ..self }
+    }
+
+    /// The axis this block's top and bottom faces point along.
+    pub fn orientation(self) -> Axis {
+        match self.state & ORIENTATION_MASK {
+            1 => Axis::X,
+            2 => Axis::Z,
+            _ => Axis::Y,
+        }
+    }
+
+    pub fn with_orientation(self, axis: Axis) -> Voxel {
+        let bits = match axis {
+            Axis::Y => 0,
+            Axis::X => 1,
+            Axis::Z => 2,
+        };
+        self.with_state((self.state & !ORIENTATION_MASK) | bits)
+    }
+
+    /// The face of this voxel that shows on the given side, once its
+    /// orientation is taken into account.
+    pub fn face(self, side: Side) -> VoxelFace {
+        let (axis, face): (Axis, Face) = side.into();
+        // turning the block swaps its up axis with the one it now points along
+        let local_axis = match (self.orientation(), axis) {
+            (Axis::X, Axis::X) => Axis::Y,
+            (Axis::X, Axis::Y) => Axis::X,
+            (Axis::Z, Axis::Z) => Axis::Y,
+            (Axis::Z, Axis::Y) => Axis::Z,
+            (_, axis) => axis,
+        };
+        VoxelFace {
+            voxel: self,
+            side: (local_axis, face).into(),
+        }
+    }
+}
+
+/// One face of a voxel: the voxel, and which of its own sides is showing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VoxelFace {
+    pub voxel: Voxel,
+    pub side: Side,
+}
 
-// provides for ChunkData to be exactly 1 page (4096 bytes)
 pub const CHUNK_SIZE: usize = 16;
 pub const CHUNK_SIZE_FLOAT: f32 = CHUNK_SIZE as f32;
 
-#[derive(Clone, Copy, Debug, Default)]
+/// Number of voxels in a chunk.
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+/// The voxels of a single chunk.
+///
+/// Uniform chunks (all air, all stone) store a single voxel. Anything else
+/// keeps a palette of the distinct voxels in the chunk and bit-packed
+/// indices into it, widening from 1 to 16 bits per voxel as the palette grows.
+#[derive(Clone, Debug)]
 pub struct ChunkData {
-    pub data: [[[Voxel; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
+    storage: VoxelStorage,
+}
+
+#[derive(Clone, Debug)]
+enum VoxelStorage {
+    Uniform(Voxel),
+    Paletted(PalettedVoxels),
+}
+
+impl Default for ChunkData {
+    fn default() -> Self {
+        ChunkData::filled(AIR)
+    }
 }
 
-#[allow(unused)]
 fn in_range<V: PartialOrd>(low: V, high: V, value: V) -> bool {
     value >= low && value < high
 }
 
+#[inline(always)]
+fn linear_index(index: (usize, usize, usize)) -> usize {
+    (index.2 * CHUNK_SIZE + index.1) * CHUNK_SIZE + index.0
+}
+
 impl ChunkData {
+    /// A chunk where every voxel is `voxel`.
+    pub fn filled(voxel: Voxel) -> Self {
+        ChunkData {
+            storage: VoxelStorage::Uniform(voxel),
+        }
+    }
+
+    /// The voxel filling the whole chunk, if it is uniform.
+    pub fn uniform_voxel(&self) -> Option<Voxel> {
+        match self.storage {
+            VoxelStorage::Uniform(voxel) => Some(voxel),
+            VoxelStorage::Paletted(_) => None,
+        }
+    }
+
     #[inline(always)]
     pub fn get_voxel(&self, index: (usize, usize, usize)) -> Voxel {
-        self.data[index.2][index.1][index.0]
+        match self.storage {
+            VoxelStorage::Uniform(voxel) => voxel,
+            VoxelStorage::Paletted(ref p) => p.palette[p.get_index(linear_index(index))],
+        }
+    }
+
+    /// Like `get_voxel`, but takes signed coordinates and returns `None`
+    /// outside of the chunk instead of panicking.
+    #[inline]
+    pub fn try_get_voxel(&self, index: (i32, i32, i32)) -> Option<Voxel> {
+        let size = CHUNK_SIZE as i32;
+        if in_range(0, size, index.0) && in_range(0, size, index.1) && in_range(0, size, index.2) {
+            Some(self.get_voxel((index.0 as usize, index.1 as usize, index.2 as usize)))
+        } else {
+            None
+        }
     }
 
     #[inline]
     #[allow(unused)]
-    pub fn get_voxel_face(&self, index: (usize, usize, usize), _side: Side) -> Voxel {
-        // TODO change this to allow for different faces on each side of a voxel...
-        self.get_voxel(index)
+    pub fn get_voxel_face(&self, index: (usize, usize, usize), side: Side) -> VoxelFace {
+        self.get_voxel(index).face(side)
     }
 
-    #[inline(always)]
+    #[inline]
     pub fn set_voxel(&mut self, index: (usize, usize, usize), value: Voxel) {
-        self.data[index.2][index.1][index.0] = value;
+        let i = linear_index(index);
+        let replacement = match self.storage {
+            VoxelStorage::Uniform(voxel) if voxel == value => return,
+            VoxelStorage::Uniform(voxel) => {
+                let mut paletted = PalettedVoxels::filled(voxel);
+                paletted.set(i, value);
+                Some(VoxelStorage::Paletted(paletted))
+            },
+            VoxelStorage::Paletted(ref mut p) => p.set(i, value).map(VoxelStorage::Uniform),
+        };
+        if let Some(storage) = replacement {
+            self.storage = storage;
+        }
     }
 
     /// Get an iterator over a cross section of the chunk's data.
     #[allow(unused)]
     pub fn cross_section<'s>(&'s self, axis: Axis, depth: usize) -> CrossSectionIter<'s> {
         CrossSectionIter {
-            data: &self.data,
+            data: self,
             axis: axis,
             depth: depth,
             row: 0,
@@ -52,15 +183,139 @@ impl ChunkData {
     }
 }
 
+#[derive(Clone, Debug)]
+struct PalettedVoxels {
+    palette: Vec<Voxel>,
+    /// How many voxels use each palette entry. Entries at zero are reused.
+    counts: Vec<u16>,
+    /// Bits per packed index: 1, 2, 4, 8 or 16, so indices never straddle words.
+    bits: usize,
+    words: Vec<u64>,
+}
+
+impl PalettedVoxels {
+    fn filled(voxel: Voxel) -> Self {
+        PalettedVoxels {
+            palette: vec![voxel],
+            counts: vec![CHUNK_VOLUME as u16],
+            bits: 1,
+            words: vec![0; CHUNK_VOLUME / 64],
+        }
+    }
+
+    #[inline(always)]
+    fn get_index(&self, i: usize) -> usize {
+        let per_word = 64 / self.bits;
+        let shift = (i % per_word) * self.bits;
+        ((self.words[i / per_word] >> shift) & ((1 << self.bits) - 1)) as usize
+    }
+
+    #[inline(always)]
+    fn set_index(&mut self, i: usize, palette_index: usize) {
+        let per_word = 64 / self.bits;
+        let shift = (i % per_word) * self.bits;
+        let mask = ((1u64 << self.bits) - 1) << shift;
+        let word = &mut self.words[i / per_word];
+        *word = (*word & !mask) | ((palette_index as u64) << shift);
+    }
+
+    /// Sets a voxel, returning the voxel filling the whole chunk if the
+    /// storage has become uniform.
+    fn set(&mut self, i: usize, value: Voxel) -> Option<Voxel> {
+        let old = self.get_index(i);
+        if self.palette[old] == value {
+            return None;
+        }
+
+        let new = match self.palette.iter().position(|v| *v == value) {
+            Some(new) => new,
+            None => {
+                match self.counts.iter().position(|c| *c == 0) {
+                    Some(free) => {
+                        self.palette[free] = value;
+                        free
+                    },
+                    None => {
+                        self.palette.push(value);
+                        self.counts.push(0);
+                        if self.palette.len() > 1 << self.bits {
+                            let bits = self.bits * 2;
+                            self.repack(bits);
+                        }
+                        self.palette.len() - 1
+                    },
+                }
+            },
+        };
+
+        self.counts[old] -= 1;
+        self.counts[new] += 1;
+        self.set_index(i, new);
+
+        if self.counts[new] as usize == CHUNK_VOLUME {
+            return Some(value);
+        }
+        if self.counts[old] == 0 {
+            self.shrink();
+        }
+        None
+    }
+
+    /// Drops unused palette entries once the rest fit in a quarter of the
+    /// bits, leaving slack so a chunk edited back and forth doesn't thrash.
+    fn shrink(&mut self) {
+        if self.bits == 1 {
+            return;
+        }
+        let used = self.counts.iter().filter(|c| **c > 0).count();
+        if used > 1 << (self.bits / 4).max(1) {
+            return;
+        }
+
+        let mut remap = vec![0; self.palette.len()];
+        let mut palette = Vec::with_capacity(used);
+        let mut counts = Vec::with_capacity(used);
+        for (i, (voxel, count)) in self.palette.iter().zip(self.counts.iter()).enumerate() {
+            if *count > 0 {
+                remap[i] = palette.len();
+                palette.push(*voxel);
+                counts.push(*count);
+            }
+        }
+
+        let indices: Vec<usize> = (0..CHUNK_VOLUME).map(|i| remap[self.get_index(i)]).collect();
+        self.palette = palette;
+        self.counts = counts;
+        let mut bits = 1;
+        while 1 << bits < self.palette.len() {
+            bits *= 2;
+        }
+        self.write_indices(bits, &indices);
+    }
+
+    fn repack(&mut self, bits: usize) {
+        let indices: Vec<usize> = (0..CHUNK_VOLUME).map(|i| self.get_index(i)).collect();
+        self.write_indices(bits, &indices);
+    }
+
+    fn write_indices(&mut self, bits: usize, indices: &[usize]) {
+        self.bits = bits;
+        self.words = vec![0; CHUNK_VOLUME * bits / 64];
+        for (i, index) in indices.iter().enumerate() {
+            self.set_index(i, *index);
+        }
+    }
+}
+
 pub struct CrossSectionIter<'a> {
-    data: &'a [[[u8; 16]; 16]; 16],
+    data: &'a ChunkData,
     axis: Axis,
     depth: usize,
     row: usize,
 }
 
 pub struct RowIter<'a> {
-    data: &'a [[[u8; 16]; 16]; 16],
+    data: &'a ChunkData,
     axis: Axis,
     depth: usize,
     row: usize,
@@ -72,7 +327,7 @@ impl<'a> Iterator for CrossSectionIter<'a> {
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        if self.row >= 16 {
+        if self.row >= CHUNK_SIZE {
             return None;
         }
 
@@ -89,20 +344,21 @@ impl<'a> Iterator for CrossSectionIter<'a> {
 }
 
 impl<'a> Iterator for RowIter<'a> {
-    type Item = &'a Voxel;
+    type Item = Voxel;
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        if self.column >= 16 {
+        if self.column >= CHUNK_SIZE {
             return None;
         }
 
         self.column += 1;
 
+        let column = self.column - 1;
         match self.axis {
-            Axis::X => Some(&self.data[self.depth][self.row][self.column - 1]),
-            Axis::Y => Some(&self.data[self.row][self.depth][self.column - 1]),
-            Axis::Z => Some(&self.data[self.row][self.column - 1][self.depth]),
+            Axis::X => Some(self.data.get_voxel((column, self.row, self.depth))),
+            Axis::Y => Some(self.data.get_voxel((column, self.depth, self.row))),
+            Axis::Z => Some(self.data.get_voxel((self.depth, column, self.row))),
         }
     }
 }
@@ -111,3 +367,58 @@ impl Component for ChunkData {
     // the flag is used to reconstruct the mesh
     type Storage = FlaggedStorage<Self, HashMapStorage<Self>>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indices() -> impl Iterator<Item=(usize, usize, usize)> {
+        (0..CHUNK_SIZE).flat_map(|z| (0..CHUNK_SIZE).flat_map(move |y| (0..CHUNK_SIZE).map(move |x| (x, y, z))))
+    }
+
+    /// A voxel from `distinct` kinds, spread so every kind is used.
+    fn pattern(index: (usize, usize, usize), distinct: u16) -> Voxel {
+        Voxel::new((linear_index(index) % distinct as usize) as u16 + 1)
+    }
+
+    #[test]
+    fn paletted_round_trip() {
+        for &distinct in &[2, 3, 5, 17, 257] {
+            let mut data = ChunkData::default();
+            for index in indices() {
+                data.set_voxel(index, pattern(index, distinct));
+            }
+            assert_eq!(data.uniform_voxel(), None);
+            for index in indices() {
+                assert_eq!(data.get_voxel(index), pattern(index, distinct), "{} voxels at {:?}", distinct, index);
+            }
+
+            let stone = Voxel::new(1);
+            for index in indices() {
+                data.set_voxel(index, stone);
+            }
+            assert_eq!(data.uniform_voxel(), Some(stone), "{} voxels", distinct);
+            assert_eq!(data.get_voxel((3, 7, 11)), stone);
+        }
+    }
+
+    #[test]
+    fn paletted_reuses_freed_entries() {
+        let mut data = ChunkData::default();
+        for id in 1..300 {
+            data.set_voxel((0, 0, 0), Voxel::new(id));
+            data.set_voxel((1, 0, 0), Voxel::new(id).with_state(1));
+        }
+        assert_eq!(data.get_voxel((0, 0, 0)), Voxel::new(299));
+        assert_eq!(data.get_voxel((1, 0, 0)), Voxel::new(299).with_state(1));
+        assert_eq!(data.get_voxel((2, 0, 0)), AIR);
+        match data.storage {
+            VoxelStorage::Paletted(ref p) => assert!(p.palette.len() <= 4, "{} palette entries", p.palette.len()),
+            VoxelStorage::Uniform(_) => panic!("three voxels stored as uniform"),
+        }
+
+        data.set_voxel((0, 0, 0), AIR);
+        data.set_voxel((1, 0, 0), AIR);
+        assert_eq!(data.uniform_voxel(), Some(AIR));
+    }
+}
@@ -1,15 +1,13 @@
 use super::{ChunkData, ChunkIndex};
-
-use std::borrow::Cow;
+use super::mesh::TranslucentChunkMesh;
+use super::super::atlas::TextureAtlas;
 
 use amethyst::assets::{AssetStorage, Loader, Handle};
 use amethyst::renderer::{Material, MaterialDefaults, Texture};
 use amethyst::renderer::types::TextureData;
 use amethyst::renderer::rendy::texture::{TextureBuilder};
-use amethyst::renderer::rendy::texture::image::TextureKind;
-use amethyst::renderer::rendy::texture::pixel::Rgba8Uint;
-use amethyst::renderer::loaders::load_from_srgb;
-use amethyst::renderer::palette::rgb::Srgb;
+use amethyst::renderer::rendy::hal::format::Format;
+use amethyst::renderer::rendy::hal::image::{Kind, ViewKind, SamplerInfo, Filter, WrapMode};
 use specs::{
     System,
     Entities,
@@ -19,10 +17,12 @@ use specs::{
 };
 use shred::{Fetch, ReadExpect};
 
+/// Gives every chunk, and every chunk's translucent mesh, the block texture
+/// atlas as its material.
 #[derive(Clone, Debug, Default)]
 pub struct ChunkMaterialSystem {
-    white_texture: Option<Handle<Texture>>,
-    white_material: Option<Handle<Material>>,
+    atlas_texture: Option<Handle<Texture>>,
+    atlas_material: Option<Handle<Material>>,
 }
 
 impl<'a> System<'a> for ChunkMaterialSystem {
@@ -32,8 +32,10 @@ impl<'a> System<'a> for ChunkMaterialSystem {
         ReadExpect<'a, AssetStorage<Texture>>,
         ReadExpect<'a, AssetStorage<Material>>,
         ReadExpect<'a, Loader>,
+        ReadExpect<'a, TextureAtlas>,
         ReadStorage<'a, ChunkData>,
         ReadStorage<'a, ChunkIndex>,
+        ReadStorage<'a, TranslucentChunkMesh>,
         WriteStorage<'a, Handle<Material>>,
     );
 
@@ -43,36 +45,48 @@ impl<'a> System<'a> for ChunkMaterialSystem {
         texture_storage,
         material_storage,
         loader,
+        atlas,
         chunk_datas,
         chunk_indices,
+        translucent_meshes,
         mut materials,
     ): Self::SystemData) {
-        if self.white_texture.is_none() {
+        if self.atlas_texture.is_none() {
+            let (width, height) = atlas.size();
+            let builder = TextureBuilder::new()
+                .with_kind(Kind::D2(width, height, 1, 1))
+                .with_view_kind(ViewKind::D2)
+                .with_data_width(width)
+                .with_data_height(height)
+                // nearest filtering keeps the blocky look and stops tiles bleeding together
+                .with_sampler_info(SamplerInfo::new(Filter::Nearest, WrapMode::Clamp))
+                .with_raw_data(atlas.pixels().to_vec(), Format::Rgba8Srgb);
             let handle = loader.load_from_data(
-                TextureData(
-                    load_from_srgb(Srgb::new(1., 1., 1.))
-                ),
+                TextureData(builder),
                 (),
                 &texture_storage,
             );
-            self.white_texture = Some(handle);
+            self.atlas_texture = Some(handle);
         }
-        let white_texture = self.white_texture.clone().unwrap();
-        if self.white_material.is_none() {
+        let atlas_texture = self.atlas_texture.clone().unwrap();
+        if self.atlas_material.is_none() {
             let handle = loader.load_from_data(
                 Material {
-                    albedo: white_texture.clone(),
+                    albedo: atlas_texture.clone(),
                     ..material_defaults.0.clone()
                 },
                 (),
                 &material_storage
             );
-            self.white_material = Some(handle);
+            self.atlas_material = Some(handle);
         }
-        let white_material = self.white_material.clone().unwrap();
+        let atlas_material = self.atlas_material.clone().unwrap();
 
         for (entity, _, _chunk_index, _) in (&*entities, &chunk_datas, &chunk_indices, !materials.mask().clone()).join() {
-            materials.insert(entity, white_material.clone());
+            materials.insert(entity, atlas_material.clone());
+        }
+        for (entity, _, _) in (&*entities, &translucent_meshes, !materials.mask().clone()).join() {
+            materials.insert(entity, atlas_material.clone());
         }
     }
 }
@@ -1,65 +1,219 @@
 //! The meshing algorithm used here is derived from
 //! https://0fps.net/2012/06/30/meshing-in-a-minecraft-game/
 
-use super::{ChunkData, ChunkIndex, Axis, Side, Face};
-use super::data::{CHUNK_SIZE, Voxel};
-use super::super::world_slice::WorldSlice;
+use super::{ChunkData, ChunkIndex, Axis, Side, Face, SIDES};
+use super::data::{CHUNK_SIZE, VoxelFace, AIR};
+use super::super::world_slice::{ChunkSnapshot, VoxelSource};
+use super::super::block::BlockRegistry;
+use super::super::atlas::TextureAtlas;
 use super::super::VoxelWorld;
+use super::super::light::{WorldLight, MAX_LIGHT};
+use super::super::lod::{ChunkLod, LodSlice, border_skirts};
+use super::surface_nets::surface_nets_quads;
 
-use std::ops::Deref;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use fnv::{FnvHashMap, FnvHashSet, FnvHasher};
 
 use amethyst::assets::{AssetStorage, Loader, Handle};
-use amethyst::renderer::{Mesh, rendy::mesh::TexCoord, rendy::mesh::Normal, rendy::mesh::MeshBuilder};
+use amethyst::renderer::{Mesh, rendy::mesh::TexCoord, rendy::mesh::Normal, rendy::mesh::Position, rendy::mesh::MeshBuilder, rendy::mesh::Color};
+use amethyst::renderer::rendy::mesh::{AsVertex, VertexFormat};
 use amethyst::renderer::rendy::hal::Primitive;
+use amethyst::renderer::rendy::hal::format::Format;
+use amethyst::renderer::types::MeshData;
+use amethyst::renderer::transparent::Transparent;
 use amethyst::renderer::visibility::BoundingSphere;
+use amethyst::core::{Parent, Transform};
 use specs::{
+    Entity,
     Entities,
+    world::EntitiesRes,
     System,
     ReadStorage,
     WriteStorage,
-    storage::MaskedStorage,
     ReaderId,
     storage::ComponentEvent,
     Component,
+    world::Index,
     HashMapStorage,
+    FlaggedStorage,
+    NullStorage,
     Join,
-    ParJoin,
     BitSet,
     SystemData,
 };
 use shred::{
     Fetch,
+    Read,
     ReadExpect,
+    WriteExpect,
     Resources,
     SetupHandler,
 };
-use rayon::prelude::*;
-use cgmath::Vector3;
+use rayon::{self, ThreadPool, ThreadPoolBuilder};
+use cgmath::{Point3, Vector3};
+
+/// One greedy-merged quad of a chunk mesh.
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkQuad {
+    /// Corners in winding order. `corners[0] -> corners[1]` runs along the
+    /// quad's width and `corners[0] -> corners[3]` along its height.
+    pub corners: [Vector3<f32>; 4],
+    pub side: Side,
+    /// The voxel face every cell of the quad shows.
+    pub face: VoxelFace,
+    /// Ambient occlusion level of each corner, 0 (darkest) to 3 (open).
+    pub ao: [u8; 4],
+    /// Light at each corner, sky light in the high nibble and block light in
+    /// the low.
+    pub light: [u8; 4],
+    /// Normals of each corner of a smooth quad. Blocky quads are flat and
+    /// face their side.
+    pub normals: Option<[Vector3<f32>; 4]>,
+}
 
 #[derive(Clone, Debug, Default)]
 pub struct ChunkQuads {
-    // (position relative to side, size, side)
-    // quads: Vec<(Vector3<f32>, Vector2<f32>, Side)>,
-    quads: Vec<([Vector3<f32>; 4], Side)>,
+    quads: Vec<ChunkQuad>,
+    /// Faces of translucent blocks, kept apart so they can be drawn blended.
+    translucent: Vec<ChunkQuad>,
+
+    /// Hashes of the six border planes of the data these quads were built
+    /// from, in `SIDES` order. Used to tell which neighbours need remeshing.
+    border_hashes: Option<[u64; 6]>,
+
+    /// The level of detail the quads were built at.
+    lod: ChunkLod,
+}
+
+impl ChunkQuads {
+    /// Faces of opaque and cutout blocks.
+    pub fn quads(&self) -> &[ChunkQuad] {
+        &self.quads
+    }
+
+    pub fn translucent_quads(&self) -> &[ChunkQuad] {
+        &self.translucent
+    }
+
+    pub fn lod(&self) -> ChunkLod {
+        self.lod
+    }
 }
 
 impl Component for ChunkQuads {
     type Storage = HashMapStorage<Self>;
 }
 
+/// How a chunk's opaque blocks are meshed. As a resource it picks the
+/// world's mesher; as a component it overrides it for one chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChunkMesher {
+    /// Greedy-merged cube faces.
+    Blocky,
+    /// A smooth surface through the opaque blocks, with Surface Nets. Blocks
+    /// that aren't opaque are still meshed blocky.
+    SurfaceNets,
+}
+
+impl Default for ChunkMesher {
+    fn default() -> Self {
+        ChunkMesher::Blocky
+    }
+}
+
+impl Component for ChunkMesher {
+    // the flag tells the mesher to rebuild the chunk
+    type Storage = FlaggedStorage<Self, HashMapStorage<Self>>;
+}
+
+/// Marks the child entity that draws a chunk's translucent faces.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TranslucentChunkMesh;
+
+impl Component for TranslucentChunkMesh {
+    type Storage = NullStorage<Self>;
+}
+
+/// A chunk meshed on a worker thread, waiting to be uploaded.
+struct MeshedChunk {
+    entity: Entity,
+    /// The chunk's generation when the job was submitted.
+    generation: u64,
+    quads: ChunkQuads,
+    opaque: Option<MeshData>,
+    translucent: Option<MeshData>,
+}
+
 /// Meshes chunks from ChunkData into collections.
 /// Starts by turning contiguous faces into polygons and then
 /// triangulating those into meshes.
+///
+/// Meshing runs on a pool of worker threads, each job working from a
+/// snapshot of the chunk and its neighbours, so big edits don't stall the
+/// frame. Finished meshes are uploaded a few per frame. A chunk changing
+/// again while its job runs bumps its generation, and the job's result is
+/// thrown away.
 pub struct MeshFaceSystem {
     reader_id: Option<ReaderId<ComponentEvent>>,
-    _unused: (),
+    lod_reader_id: Option<ReaderId<ComponentEvent>>,
+    mesher_reader_id: Option<ReaderId<ComponentEvent>>,
+
+    /// Chunk index of every entity that has been meshed, so the neighbours of
+    /// a removed chunk can still be found after its components are gone.
+    chunk_positions: FnvHashMap<Index, (i32, i32, i32)>,
+
+    /// The translucent mesh entity of each chunk that has one.
+    translucent_children: FnvHashMap<Index, Entity>,
+
+    /// The most meshes uploaded in one frame.
+    pub upload_budget: usize,
+    /// The most meshing jobs running at once. Each holds copies of 27
+    /// chunks.
+    pub max_jobs: usize,
+    /// Worker threads, or 0 for one per core.
+    pub workers: usize,
+
+    pool: Option<ThreadPool>,
+    sender: Sender<MeshedChunk>,
+    receiver: Receiver<MeshedChunk>,
+    /// Counts each chunk's changes, to tell stale results from current ones.
+    generations: FnvHashMap<Index, u64>,
+    /// Chunks that changed since their last job was submitted.
+    waiting: BitSet,
+    /// Chunks with a job running. At most one each, so results arrive in order.
+    in_flight: FnvHashSet<Index>,
+    /// Results waiting for upload budget.
+    ready: VecDeque<MeshedChunk>,
+    /// Shared with jobs; copied from the resources on first use.
+    registry: Option<Arc<BlockRegistry>>,
+    atlas: Option<Arc<TextureAtlas>>,
 }
 
 impl Default for MeshFaceSystem {
     fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
         MeshFaceSystem {
             reader_id: None,
-            _unused: (),
+            lod_reader_id: None,
+            mesher_reader_id: None,
+            chunk_positions: FnvHashMap::default(),
+            translucent_children: FnvHashMap::default(),
+            upload_budget: 8,
+            max_jobs: 64,
+            workers: 0,
+            pool: None,
+            sender,
+            receiver,
+            generations: FnvHashMap::default(),
+            waiting: BitSet::new(),
+            in_flight: FnvHashSet::default(),
+            ready: VecDeque::new(),
+            registry: None,
+            atlas: None,
         }
     }
 }
@@ -70,11 +224,21 @@ impl<'a> System<'a> for MeshFaceSystem {
         ReadStorage<'a, ChunkIndex>,
         WriteStorage<'a, ChunkData>,
         WriteStorage<'a, ChunkQuads>,
+        ReadStorage<'a, ChunkLod>,
+        ReadStorage<'a, ChunkMesher>,
+        Read<'a, ChunkMesher>,
         WriteStorage<'a, BoundingSphere>,
         WriteStorage<'a, Handle<Mesh>>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, Parent>,
+        WriteStorage<'a, Transparent>,
+        WriteStorage<'a, TranslucentChunkMesh>,
         ReadExpect<'a, Loader>,
         ReadExpect<'a, AssetStorage<Mesh>>,
-        ReadExpect<'a, VoxelWorld>
+        ReadExpect<'a, VoxelWorld>,
+        WriteExpect<'a, WorldLight>,
+        ReadExpect<'a, BlockRegistry>,
+        ReadExpect<'a, TextureAtlas>,
     );
 
     fn run(
@@ -84,139 +248,485 @@ impl<'a> System<'a> for MeshFaceSystem {
             chunk_indices,
             mut chunk_datas,
             mut chunk_meshes,
+            chunk_lods,
+            chunk_meshers,
+            world_mesher,
             mut bounding_spheres,
             mut mesh_handles,
+            mut transforms,
+            mut parents,
+            mut transparents,
+            mut translucent_meshes,
             loader,
             mesh_storage,
             voxel_world,
+            mut world_light,
+            registry,
+            atlas,
         ): Self::SystemData
     ) {
-        use amethyst::renderer::types::MeshData;
-        use amethyst::renderer::rendy::mesh::PosNormTex;
-
         // Handle incoming chunk data change events
         let change_events = chunk_datas.channel().read(self.reader_id.as_mut().unwrap());
         let mut dirty_chunk_datas = BitSet::new();
+        let mut removed_chunks = Vec::new();
         for event in change_events {
             match event {
                 ComponentEvent::Modified(id) | ComponentEvent::Inserted(id) => {
                     dirty_chunk_datas.add(*id);
                 },
-                _ => (),
+                ComponentEvent::Removed(id) => {
+                    if let Some(index) = self.chunk_positions.remove(id) {
+                        removed_chunks.push(index);
+                    }
+                    if let Some(child) = self.translucent_children.remove(id) {
+                        let _ = entities.delete(child);
+                    }
+                    // any job still running is discarded when it comes back
+                    self.generations.remove(id);
+                    self.waiting.remove(*id);
+                },
             }
         }
 
-        {
-            let chunk_datas = &chunk_datas;
-            let world = &voxel_world;
-            (&dirty_chunk_datas, &mut chunk_meshes, &chunk_indices)
-                .par_join()
-                .for_each(|(_, chunk_mesh, index)| {
-                    let world_slice = WorldSlice::new(
-                        chunk_datas,
-                        world,
-                        (*index).into(),
-                    );
-                    quads_from_data(&world_slice, chunk_mesh)
-                });
+        // Face culling and ambient occlusion look across chunk borders, so any
+        // change to a border plane (or a chunk appearing or vanishing) dirties
+        // the neighbours touching that plane, including along edges and corners.
+        let mut dirty_neighbours = BitSet::new();
+        for (entity, _, chunk_data, chunk_index, chunk_quads) in (&*entities, &dirty_chunk_datas, &chunk_datas, &chunk_indices, &mut chunk_meshes).join() {
+            let index: (i32, i32, i32) = (*chunk_index).into();
+            self.chunk_positions.insert(entity.id(), index);
+
+            let hashes = border_hashes(chunk_data);
+            let mut changed = [false; 6];
+            for i in 0..SIDES.len() {
+                changed[i] = chunk_quads.border_hashes
+                    .map(|old| old[i] != hashes[i])
+                    .unwrap_or(true);
+            }
+            chunk_quads.border_hashes = Some(hashes);
+
+            for offset in moore_offsets() {
+                if touches_changed_planes(offset, &changed) {
+                    let neighbour_index = (index.0 + offset.0, index.1 + offset.1, index.2 + offset.2);
+                    if let Some(neighbour) = voxel_world.get_entity(neighbour_index) {
+                        dirty_neighbours.add(neighbour.id());
+                    }
+                }
+            }
         }
-        
-        // load the meshes into the asset registry
-        for (entity, chunk_quads, _) in (&*entities, &chunk_meshes, &dirty_chunk_datas).join() {
-            // No reusing indices, I suppose.
-            use genmesh::*;
-
-            if chunk_quads.quads.is_empty() {
-                // no mesh! it's all air
-                mesh_handles.remove(entity);
-                continue;
+        for index in removed_chunks {
+            for offset in moore_offsets() {
+                let neighbour_index = (index.0 + offset.0, index.1 + offset.1, index.2 + offset.2);
+                if let Some(neighbour) = voxel_world.get_entity(neighbour_index) {
+                    dirty_neighbours.add(neighbour.id());
+                }
+            }
+        }
+        for id in (&dirty_neighbours).join() {
+            dirty_chunk_datas.add(id);
+        }
+        // Chunks changing level remesh, as do the chunks beside them, whose
+        // faces against them depend on whether their levels match.
+        for event in chunk_lods.channel().read(self.lod_reader_id.as_mut().unwrap()) {
+            if let ComponentEvent::Modified(id) | ComponentEvent::Inserted(id) = event {
+                dirty_chunk_datas.add(*id);
+                if let Some(index) = self.chunk_positions.get(id) {
+                    for side in SIDES.iter() {
+                        let (x, y, z) = side.offset();
+                        if let Some(neighbour) = voxel_world.get_entity((index.0 + x, index.1 + y, index.2 + z)) {
+                            dirty_chunk_datas.add(neighbour.id());
+                        }
+                    }
+                }
             }
+        }
+        for event in chunk_meshers.channel().read(self.mesher_reader_id.as_mut().unwrap()) {
+            match event {
+                ComponentEvent::Modified(id) | ComponentEvent::Inserted(id) | ComponentEvent::Removed(id) => {
+                    dirty_chunk_datas.add(*id);
+                },
+            }
+        }
+        // and so does light changing
+        for index in world_light.take_changed() {
+            if let Some(entity) = voxel_world.get_entity(index) {
+                dirty_chunk_datas.add(entity.id());
+            }
+        }
+
+        // anything already meshed or being meshed for these is now stale
+        for id in (&dirty_chunk_datas).join() {
+            *self.generations.entry(id).or_insert(0) += 1;
+            self.waiting.add(id);
+        }
 
-            let verts: Vec<PosNormTex> = chunk_quads.quads.iter()
-                .map(|q| {
-                    let normal: amethyst::renderer::rendy::mesh::Normal = match q.1 {
-                        Side::Bottom => Normal([0., -1., 0.]),
-                        Side::Top => Normal([0., 1., 0.]),
-                        Side::East => Normal([1., 0., 0.]),
-                        Side::West => Normal([-1., 0., 0.]),
-                        Side::North => Normal([0., 0., 1.]),
-                        Side::South => Normal([0., 0., -1.]),
-                    };
-                    // let tangent: [f32; 3] = match q.1 {
-                    //     Side::Bottom => [-1., 0., 0.],
-                    //     Side::Top => [1., 0., 0.],
-                    //     Side::East => [0., 1., 0.],
-                    //     Side::West => [0., -1., 0.],
-                    //     Side::North => [0., 1., 0.],
-                    //     Side::South => [0., -1., 0.],
-                    // };
-
-                    Quad::new(
-                        PosNormTex {
-                            position: q.0[0].into(),
-                            normal,
-                            // tangent,
-                            tex_coord: TexCoord([0., 0.]),
-                        },
-                        PosNormTex {
-                            position: q.0[1].into(),
-                            normal,
-                            // tangent,
-                            tex_coord: TexCoord([0., 1.]),
-                        },
-                        PosNormTex {
-                            position: q.0[2].into(),
-                            normal,
-                            // tangent,
-                            tex_coord: TexCoord([1., 1.]),
-                        },
-                        PosNormTex {
-                            position: q.0[3].into(),
-                            normal,
-                            // tangent,
-                            tex_coord: TexCoord([1., 0.]),
-                        },
-                    )
-                })
-                .triangulate()
-                .vertices()
-                .collect();
-            let positions = verts.iter().map(|v| { v.position }).collect::<Vec<_>>();
-            let normals = verts.iter().map(|v| { v.normal }).collect::<Vec<_>>();
-            let tex_coords = verts.iter().map(|v| { v.tex_coord }).collect::<Vec<_>>();
-
-            let mesh_handle = loader.load_from_data(
-                MeshBuilder::new()
-                    .with_vertices(positions)
-                    .with_vertices(normals)
-                    .with_vertices(tex_coords)
-                    .with_prim_type(Primitive::TriangleList)
-                    .into()
-                ,
-                (),
-                &*mesh_storage
-            );
-
-            mesh_handles.insert(entity, mesh_handle);
-            bounding_spheres.insert(entity, BoundingSphere {
-                radius: 22.7f32,
-                ..Default::default()
+        // collect finished jobs
+        while let Ok(meshed) = self.receiver.try_recv() {
+            self.in_flight.remove(&meshed.entity.id());
+            if is_current(&entities, &self.generations, &meshed) {
+                self.ready.push_back(meshed);
+            }
+        }
+
+        // snapshot waiting chunks and hand them to the workers
+        let registry = self.registry.get_or_insert_with(|| Arc::new((*registry).clone())).clone();
+        let atlas = self.atlas.get_or_insert_with(|| Arc::new((*atlas).clone())).clone();
+        let world_mesher = *world_mesher;
+        let mut submitted = Vec::new();
+        for (entity, _, chunk_index, _, lod, mesher) in (&*entities, &self.waiting, &chunk_indices, &chunk_meshes, chunk_lods.maybe(), chunk_meshers.maybe()).join() {
+            if self.in_flight.len() >= self.max_jobs {
+                break;
+            }
+            if self.in_flight.contains(&entity.id()) {
+                continue;
+            }
+            let index: (i32, i32, i32) = (*chunk_index).into();
+            let lod = lod.cloned().unwrap_or_default();
+            let mesher = mesher.cloned().unwrap_or(world_mesher);
+            let skirts = border_skirts(lod, |side| {
+                let (x, y, z) = side.offset();
+                voxel_world.get_entity((index.0 + x, index.1 + y, index.2 + z))
+                    .map(|neighbour| chunk_lods.get(neighbour).cloned().unwrap_or_default())
             });
+            let snapshot = ChunkSnapshot::new(&chunk_datas, &voxel_world, &world_light, index);
+            let generation = self.generations.get(&entity.id()).cloned().unwrap_or(0);
+            let registry = registry.clone();
+            let atlas = atlas.clone();
+            let sender = self.sender.clone();
+            let job = move || {
+                let mut quads = ChunkQuads::default();
+                lod_quads_from_data(&snapshot, &registry, &mut quads, lod, skirts, mesher);
+                let opaque = if quads.quads.is_empty() {
+                    None
+                } else {
+                    Some(mesh_data(&quads.quads, &atlas, lod))
+                };
+                let translucent = if quads.translucent.is_empty() {
+                    None
+                } else {
+                    Some(mesh_data(&quads.translucent, &atlas, lod))
+                };
+                // fails only once the system is gone
+                let _ = sender.send(MeshedChunk { entity, generation, quads, opaque, translucent });
+            };
+            match self.pool {
+                Some(ref pool) => pool.spawn(job),
+                None => rayon::spawn(job),
+            }
+            self.in_flight.insert(entity.id());
+            submitted.push(entity.id());
+        }
+        for id in submitted {
+            self.waiting.remove(id);
         }
 
-        // all meshed up, unset dirty bits
-//        (&mut chunk_datas).open().1.clear_flags();
+        // load the meshes into the asset registry, a few a frame
+        let mut uploaded = 0;
+        while uploaded < self.upload_budget {
+            let meshed = match self.ready.pop_front() {
+                Some(meshed) => meshed,
+                None => break,
+            };
+            if !is_current(&entities, &self.generations, &meshed) {
+                continue;
+            }
+            let entity = meshed.entity;
+            match chunk_meshes.get_mut(entity) {
+                Some(chunk_quads) => {
+                    chunk_quads.quads = meshed.quads.quads;
+                    chunk_quads.translucent = meshed.quads.translucent;
+                    chunk_quads.lod = meshed.quads.lod;
+                },
+                None => continue,
+            }
+            uploaded += 1;
+
+            match meshed.opaque {
+                Some(opaque) => {
+                    let mesh_handle = loader.load_from_data(opaque, (), &*mesh_storage);
+                    mesh_handles.insert(entity, mesh_handle);
+                    bounding_spheres.insert(entity, BoundingSphere {
+                        radius: 22.7f32,
+                        ..Default::default()
+                    });
+                },
+                None => {
+                    // no mesh! it's all air
+                    mesh_handles.remove(entity);
+                },
+            }
+
+            // Translucent faces live on a child entity so the blended pass
+            // picks them up and sorts them separately.
+            let translucent = match meshed.translucent {
+                Some(translucent) => translucent,
+                None => {
+                    if let Some(child) = self.translucent_children.remove(&entity.id()) {
+                        let _ = entities.delete(child);
+                    }
+                    continue;
+                },
+            };
+            let child = match self.translucent_children.get(&entity.id()) {
+                Some(&child) if entities.is_alive(child) => child,
+                _ => {
+                    let child = entities.create();
+                    let _ = transforms.insert(child, Transform::default());
+                    let _ = parents.insert(child, Parent { entity });
+                    let _ = transparents.insert(child, Transparent);
+                    let _ = translucent_meshes.insert(child, TranslucentChunkMesh);
+                    self.translucent_children.insert(entity.id(), child);
+                    child
+                },
+            };
+            let mesh_handle = loader.load_from_data(translucent, (), &*mesh_storage);
+            let _ = mesh_handles.insert(child, mesh_handle);
+            // centred on the chunk, since the blended pass sorts by it
+            let half = CHUNK_SIZE as f32 / 2.;
+            let _ = bounding_spheres.insert(child, BoundingSphere::new(Point3::new(half, half, half), 22.7f32));
+        }
     }
 
     fn setup(&mut self, res: &mut Resources) {
         <Self::SystemData as SystemData>::setup(res);
-        self.reader_id = Some(WriteStorage::<ChunkData>::fetch(res).register_reader())
+        self.reader_id = Some(WriteStorage::<ChunkData>::fetch(res).register_reader());
+        self.lod_reader_id = Some(WriteStorage::<ChunkLod>::fetch(res).register_reader());
+        self.mesher_reader_id = Some(WriteStorage::<ChunkMesher>::fetch(res).register_reader());
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(self.workers)
+            .thread_name(|i| format!("chunk-mesher-{}", i))
+            .build();
+        match pool {
+            Ok(pool) => self.pool = Some(pool),
+            Err(e) => eprintln!("Failed to start the chunk meshing threads, meshing on the global pool: {}", e),
+        }
+    }
+}
+
+/// Whether a meshing result is for a live chunk that hasn't changed since.
+fn is_current(entities: &EntitiesRes, generations: &FnvHashMap<Index, u64>, meshed: &MeshedChunk) -> bool {
+    entities.is_alive(meshed.entity) && generations.get(&meshed.entity.id()) == Some(&meshed.generation)
+}
+
+/// Builds the mesh for a set of quads, with the buffers the chunk pass
+/// expects.
+fn mesh_data(quads: &[ChunkQuad], atlas: &TextureAtlas, lod: ChunkLod) -> MeshData {
+    let buffers = ChunkMeshBuffers::from_quads(quads, atlas, lod.scale() as f32);
+    let builder = MeshBuilder::new()
+        .with_vertices(buffers.positions())
+        .with_vertices(buffers.normals())
+        .with_vertices(buffers.tex_coords())
+        .with_vertices(buffers.colors())
+        .with_vertices(buffers.tiles());
+    let builder = if buffers.vertices.len() <= u16::max_value() as usize + 1 {
+        builder.with_indices(buffers.indices.iter().map(|&i| i as u16).collect::<Vec<u16>>())
+    } else {
+        builder.with_indices(buffers.indices)
+    };
+    builder
+        .with_prim_type(Primitive::TriangleList)
+        .into()
+}
+
+/// Brightness for each ambient occlusion level.
+const AO_CURVE: [f32; 4] = [0.4, 0.6, 0.8, 1.0];
+
+/// Brightness of a corner's light, each level a fifth darker than the one
+/// above, so unlit caves end up nearly black.
+fn light_brightness(light: u8) -> f32 {
+    let level = (light >> 4).max(light & 0xf);
+    0.8f32.powi((MAX_LIGHT - level) as i32)
+}
+
+/// One vertex of a chunk mesh, before it is split into GPU buffers.
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkVertex {
+    pub position: Position,
+    pub normal: Normal,
+    /// Texture coordinates in cells, repeating the tile once per cell.
+    pub tex_coord: TexCoord,
+    pub color: Color,
+    pub tile: AtlasTile,
+}
+
+/// A block's tile in the texture atlas, as its corner and size in texture
+/// coordinates, `[u, v, width, height]`.
+#[repr(C, align(4))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct AtlasTile(pub [f32; 4]);
+
+impl AsVertex for AtlasTile {
+    fn vertex() -> VertexFormat {
+        VertexFormat::new((Format::Rgba32Sfloat, "tile"))
     }
 }
 
+/// The vertices of a chunk mesh and the indices of its triangles. Quads
+/// have four corners each, but corners alike in every attribute are only
+/// stored once.
+#[derive(Clone, Debug, Default)]
+pub struct ChunkMeshBuffers {
+    pub vertices: Vec<ChunkVertex>,
+    /// Six per quad.
+    pub indices: Vec<u32>,
+    /// The index of each distinct vertex, by its attributes' bits.
+    shared: FnvHashMap<[u32; 16], u32>,
+}
+
+impl ChunkMeshBuffers {
+    /// `cell` is the size of one textured cell in voxels: 1, or the scale of
+    /// the level of detail the quads were built at.
+    pub fn from_quads(quads: &[ChunkQuad], atlas: &TextureAtlas, cell: f32) -> Self {
+        let mut buffers = ChunkMeshBuffers::default();
+        for quad in quads.iter() {
+            buffers.add_quad(quad, atlas, cell);
+        }
+        buffers
+    }
+
+    /// Adds a merged quad as a single quad. Its texture coordinates count
+    /// cells (voxels, or downsampled cells `cell` voxels across) along the
+    /// face's plane, and every vertex carries its block's atlas tile, so the
+    /// chunk pass can repeat the tile across the quad.
+    ///
+    /// Ambient occlusion and light go in the vertex colour. Only faces whose
+    /// corners are all shaded alike merge, so spreading the corners' shading
+    /// over a merged quad matches shading each face on its own.
+    pub fn add_quad(&mut self, quad: &ChunkQuad, atlas: &TextureAtlas, cell: f32) {
+        let (uv_min, uv_max) = atlas.tile_uv(atlas.tile(quad.face));
+        let tile = AtlasTile([uv_min[0], uv_min[1], uv_max[0] - uv_min[0], uv_max[1] - uv_min[1]]);
+        let shade = |corner: usize| {
+            let b = AO_CURVE[quad.ao[corner] as usize] * light_brightness(quad.light[corner]);
+            Color([b, b, b, 1.])
+        };
+        let flat = side_normal(quad.side);
+        let normal = |corner: usize| match quad.normals {
+            Some(ref normals) => Normal(normals[corner].into()),
+            None => flat,
+        };
+        // rows and columns of the face's plane, as the greedy mesher walks them
+        let axis = quad.side.axis();
+        let tex_coord = |position: Vector3<f32>| {
+            let (row, col) = match axis {
+                Axis::X => (position.y, position.z),
+                Axis::Y => (position.z, position.x),
+                Axis::Z => (position.y, position.x),
+            };
+            TexCoord([row / cell, col / cell])
+        };
+
+        // Split along whichever diagonal keeps the darker corners from
+        // smearing across the whole quad.
+        let diagonal: [u32; 6] = if quad.ao[0] + quad.ao[2] < quad.ao[1] + quad.ao[3] {
+            [1, 2, 3, 1, 3, 0]
+        } else {
+            [0, 1, 2, 0, 2, 3]
+        };
+
+        let mut corners = [0u32; 4];
+        for (corner, &position) in quad.corners.iter().enumerate() {
+            corners[corner] = self.vertex(ChunkVertex {
+                position: Position(position.into()),
+                normal: normal(corner),
+                tex_coord: tex_coord(position),
+                color: shade(corner),
+                tile,
+            });
+        }
+        self.indices.extend(diagonal.iter().map(|&i| corners[i as usize]));
+    }
+
+    /// The index of a vertex, adding it unless an identical one is already
+    /// in the buffer.
+    fn vertex(&mut self, vertex: ChunkVertex) -> u32 {
+        let (p, n, t, c, a) = (vertex.position.0, vertex.normal.0, vertex.tex_coord.0, vertex.color.0, vertex.tile.0);
+        let key = [
+            p[0].to_bits(), p[1].to_bits(), p[2].to_bits(),
+            n[0].to_bits(), n[1].to_bits(), n[2].to_bits(),
+            t[0].to_bits(), t[1].to_bits(),
+            c[0].to_bits(), c[1].to_bits(), c[2].to_bits(), c[3].to_bits(),
+            a[0].to_bits(), a[1].to_bits(), a[2].to_bits(), a[3].to_bits(),
+        ];
+        let vertices = &mut self.vertices;
+        *self.shared.entry(key).or_insert_with(|| {
+            vertices.push(vertex);
+            vertices.len() as u32 - 1
+        })
+    }
+
+    pub fn positions(&self) -> Vec<Position> {
+        self.vertices.iter().map(|v| v.position).collect()
+    }
+
+    pub fn normals(&self) -> Vec<Normal> {
+        self.vertices.iter().map(|v| v.normal).collect()
+    }
+
+    pub fn tex_coords(&self) -> Vec<TexCoord> {
+        self.vertices.iter().map(|v| v.tex_coord).collect()
+    }
+
+    pub fn colors(&self) -> Vec<Color> {
+        self.vertices.iter().map(|v| v.color).collect()
+    }
+
+    pub fn tiles(&self) -> Vec<AtlasTile> {
+        self.vertices.iter().map(|v| v.tile).collect()
+    }
+}
+
+fn side_normal(side: Side) -> Normal {
+    match side {
+        Side::Bottom => Normal([0., -1., 0.]),
+        Side::Top => Normal([0., 1., 0.]),
+        Side::East => Normal([1., 0., 0.]),
+        Side::West => Normal([-1., 0., 0.]),
+        Side::North => Normal([0., 0., 1.]),
+        Side::South => Normal([0., 0., -1.]),
+    }
+}
+
+/// Offsets to the 26 chunks around a chunk.
+fn moore_offsets() -> impl Iterator<Item=(i32, i32, i32)> {
+    (-1..2).flat_map(|x| (-1..2).flat_map(move |y| (-1..2).map(move |z| (x, y, z))))
+        .filter(|&offset| offset != (0, 0, 0))
+}
+
+/// Whether the neighbour at `offset` touches only border planes that changed.
+/// `changed` is in `SIDES` order.
+fn touches_changed_planes(offset: (i32, i32, i32), changed: &[bool; 6]) -> bool {
+    SIDES.iter().enumerate().all(|(i, side)| {
+        let (x, y, z) = side.offset();
+        let touches = (x != 0 && x == offset.0) || (y != 0 && y == offset.1) || (z != 0 && z == offset.2);
+        !touches || changed[i]
+    })
+}
+
 static FACES: &'static [Face] = &[Face::Front, Face::Back];
 static AXES: &'static [Axis] = &[Axis::X, Axis::Y, Axis::Z];
 
+/// Hashes each border plane of a chunk, in `SIDES` order.
+fn border_hashes(data: &ChunkData) -> [u64; 6] {
+    let mut hashes = [0u64; 6];
+    for (i, side) in SIDES.iter().enumerate() {
+        let (axis, face): (Axis, Face) = (*side).into();
+        let depth = match face {
+            Face::Front => CHUNK_SIZE - 1,
+            Face::Back => 0,
+        };
+
+        let mut hasher = FnvHasher::default();
+        for r in 0..CHUNK_SIZE {
+            for c in 0..CHUNK_SIZE {
+                data.get_voxel(get_rcd_xyz(axis, r, c, depth)).hash(&mut hasher);
+            }
+        }
+        hashes[i] = hasher.finish();
+    }
+    hashes
+}
+
 /// Given an axis, row, column and depth, give the x, y, z index into the chunk array.
 #[inline(always)]
 fn get_rcd_xyz<N>(axis: Axis, row: N, col: N, depth: N) -> (N, N, N) {
@@ -240,98 +750,198 @@ fn get_rcd_xyz_array<N>(axis: Axis, row: N, col: N, depth: N) -> [N; 3] {
     }
 }
 
-fn quads_from_data<'a, 'b: 'a, T>(data: &WorldSlice<'a, 'b, T>, mesh: &mut ChunkQuads)
-where T: Deref<Target=MaskedStorage<ChunkData>>
-{
+/// Greedy-meshes the chunk at the centre of `data` into `mesh`.
+pub fn quads_from_data<S: VoxelSource>(data: &S, registry: &BlockRegistry, mesh: &mut ChunkQuads) {
+    lod_quads_from_data(data, registry, mesh, ChunkLod::default(), [false; 6], ChunkMesher::Blocky)
+}
+
+/// Greedy-meshes the chunk at the centre of `data` into `mesh` at a level of
+/// detail. Faces on the borders flagged in `skirts`, in `SIDES` order, are
+/// kept even where the neighbour hides them, to close off the mesh against
+/// neighbours at another level. Smooth meshes get no skirts.
+pub fn lod_quads_from_data<S: VoxelSource>(
+    data: &S,
+    registry: &BlockRegistry,
+    mesh: &mut ChunkQuads,
+    lod: ChunkLod,
+    skirts: [bool; 6],
+    mesher: ChunkMesher,
+) {
+    mesh.lod = lod;
+    if lod.0 == 0 {
+        mesh_quads(data, registry, mesh, CHUNK_SIZE, skirts, mesher);
+    } else {
+        let slice = LodSlice::new(data, lod, registry);
+        mesh_quads(&slice, registry, mesh, slice.size(), skirts, mesher);
+    }
+}
+
+fn mesh_quads<S: VoxelSource>(
+    data: &S,
+    registry: &BlockRegistry,
+    mesh: &mut ChunkQuads,
+    size: usize,
+    skirts: [bool; 6],
+    mesher: ChunkMesher,
+) {
+    match mesher {
+        ChunkMesher::Blocky => greedy_quads(data, registry, mesh, size, skirts, false),
+        ChunkMesher::SurfaceNets => {
+            greedy_quads(data, registry, mesh, size, skirts, true);
+            surface_nets_quads(data, registry, size, &mut mesh.quads);
+        },
+    }
+}
+
+/// Greedy-meshes `size` cells along each side, scaling them up to fill the
+/// chunk, leaving out opaque blocks when `skip_opaque` is set.
+fn greedy_quads<S: VoxelSource>(
+    data: &S,
+    registry: &BlockRegistry,
+    mesh: &mut ChunkQuads,
+    size: usize,
+    skirts: [bool; 6],
+    skip_opaque: bool,
+) {
     mesh.quads.clear();
+    mesh.translucent.clear();
+    let scale = (CHUNK_SIZE / size) as f32;
+
+    // skip the empty sky and the buried rock that make up most of a tall world
+    if let Some(voxel) = data.chunk((0, 0, 0)).and_then(|chunk| chunk.uniform_voxel()) {
+        if voxel == AIR {
+            return;
+        }
+        let buried = registry.is_opaque(voxel) && SIDES.iter().all(|side| {
+            data.chunk(side.offset())
+                .and_then(|chunk| chunk.uniform_voxel())
+                .map(|voxel| registry.is_opaque(voxel))
+                .unwrap_or(false)
+        });
+        if buried {
+            return;
+        }
+    }
 
     for face in FACES.into_iter() {
         for axis in AXES.into_iter() {
             let side: Side = (*axis, *face).into();
+            let skirt = skirts[SIDES.iter().position(|s| *s == side).unwrap()];
 
-            for depth in (-1)..(CHUNK_SIZE as isize) {
-                let mut slice: [[Option<Voxel>; CHUNK_SIZE]; CHUNK_SIZE] = [[None; CHUNK_SIZE]; CHUNK_SIZE];
+            for depth in (-1)..(size as isize) {
+                // faces to draw, with their corners' ambient occlusion so faces
+                // that would be shaded differently don't get merged
+                let mut slice: [[Option<(VoxelFace, [u8; 4], [u8; 4])>; CHUNK_SIZE]; CHUNK_SIZE] = [[None; CHUNK_SIZE]; CHUNK_SIZE];
+                // the layer the faces look out into
+                let open_depth = (if *face == Face::Back { depth } else { depth + 1 }) as i32;
+                let open_outside = open_depth < 0 || open_depth >= size as i32;
 
                 // set the culled slice
-                for r in 0..CHUNK_SIZE {
-                    for c in 0..CHUNK_SIZE {
-                        // let face_1 = if depth >= 0 && depth < CHUNK_SIZE as isize {
-                        //     Some(data.get_voxel_face(get_rcd_xyz(*axis, r as i32, c as i32, depth as usize), side))
-                        // } else {
-                        //     None
-                        // };
+                for r in 0..size {
+                    for c in 0..size {
                         let face_1 = data.get_voxel_face(get_rcd_xyz(*axis, r as i32, c as i32, depth as i32), side);
-
-
-                        // let face_2 = if depth >= -1 && depth < CHUNK_SIZE as isize - 1 {
-                        //     Some(data.get_voxel_face(get_rcd_xyz(*axis, r, c, (depth + 1) as usize), side))
-                        // } else {
-                        //     None
-                        // };
                         let face_2 = data.get_voxel_face(get_rcd_xyz(*axis, r as i32, c as i32, (depth + 1) as i32), side);
 
-                        slice[r][c] = if face_1.is_some() && face_2.is_some() && face_1 == face_2 {
-                            None
-                        } else {
-                            if *face == Face::Back { face_2 } else { face_1 }
+                        // the face that would be drawn, and the voxel it would be drawn against;
+                        // faces are hidden by opaque blocks and between blocks of the same kind,
+                        // so water surfaces and glass panes show but their insides don't
+                        let (shown, behind) = if *face == Face::Back { (face_2, face_1) } else { (face_1, face_2) };
+                        slice[r][c] = match shown {
+                            Some(shown) if shown.voxel != AIR && !(skip_opaque && registry.is_opaque(shown.voxel)) => match behind {
+                                Some(_) if skirt && open_outside => Some((
+                                    shown,
+                                    face_ao(data, registry, *axis, r as i32, c as i32, open_depth),
+                                    face_light(data, registry, *axis, r as i32, c as i32, open_depth),
+                                )),
+                                Some(behind) if behind.voxel.id == shown.voxel.id || registry.is_opaque(behind.voxel) => None,
+                                _ => Some((
+                                    shown,
+                                    face_ao(data, registry, *axis, r as i32, c as i32, open_depth),
+                                    face_light(data, registry, *axis, r as i32, c as i32, open_depth),
+                                )),
+                            },
+                            _ => None,
                         };
                     }
                 }
 
                 // the part where we use the slice to produce quads
-                for r in 0..CHUNK_SIZE {
+                for r in 0..size {
                     let mut c = 0;
-                    while c < CHUNK_SIZE {
+                    while c < size {
                         if slice[r][c].is_some() {
                             let starting_voxel = slice[r][c];
+                            // A face shaded unevenly keeps a quad to itself, as a
+                            // merged quad would stretch its shading over the lot.
+                            let even = starting_voxel
+                                .map(|(_, ao, light)| ao.iter().all(|&a| a == ao[0]) && light.iter().all(|&l| l == light[0]))
+                                .unwrap_or(true);
 
                             // Find the span on the row (at least 1)
-                            let width: usize = slice[r]
-                                .iter()
-                                .enumerate()
-                                .skip(1 + c)
-                                .skip_while(|&(_, voxel)| { *voxel == starting_voxel })
-                                .next()
-                                .map(|(w, _)| { w })
-                                .unwrap_or(CHUNK_SIZE) - c;
+                            let width: usize = if !even {
+                                1
+                            } else {
+                                slice[r]
+                                    .iter()
+                                    .enumerate()
+                                    .skip(1 + c)
+                                    .skip_while(|&(_, voxel)| { *voxel == starting_voxel })
+                                    .next()
+                                    .map(|(w, _)| { w })
+                                    .unwrap_or(size) - c
+                            };
                             
                             // How far down does this span go? (It's at least 1)
-                            let height: usize = slice
-                                .iter()
-                                .enumerate()
-                                .skip(1 + r)
-                                .skip_while(|&(_, row)| {
-                                    for c2 in (c)..(c + width) {
-                                        if row[c2] != starting_voxel {
-                                            return false
+                            let height: usize = if !even {
+                                1
+                            } else {
+                                slice
+                                    .iter()
+                                    .enumerate()
+                                    .skip(1 + r)
+                                    .skip_while(|&(_, row)| {
+                                        for c2 in (c)..(c + width) {
+                                            if row[c2] != starting_voxel {
+                                                return false
+                                            }
                                         }
-                                    }
-                                    true
-                                })
-                                .next()
-                                .map(|(h, _)| { h })
-                                .unwrap_or(CHUNK_SIZE) - r;
+                                        true
+                                    })
+                                    .next()
+                                    .map(|(h, _)| { h })
+                                    .unwrap_or(size) - r
+                            };
                             
                             // Make a quad
-                            if is_opaque(starting_voxel.unwrap()) {
-                                let mut verts: [Vector3<f32>; 4] = 
-                                    [
-                                        get_rcd_xyz_array(*axis, (r) as f32, (c) as f32, depth as f32 + 1.).into(),
-                                        get_rcd_xyz_array(*axis, (r) as f32, (c + width) as f32, depth as f32 + 1.).into(),
-                                        get_rcd_xyz_array(*axis, (r + height) as f32, (c + width) as f32, depth as f32 + 1.).into(),
-                                        get_rcd_xyz_array(*axis, (r + height) as f32, (c) as f32, depth as f32 + 1.).into(),
-                                    ];
-                                match side {
-                                    Side::East | Side::Top | Side::South => {
-                                        verts.reverse();
-                                    },
-                                    _ => {}
-                                }
-                                
-                                mesh.quads.push((
-                                    verts,
-                                    side
-                                ));
+                            let (starting_face, mut ao, mut light) = starting_voxel.unwrap();
+                            let mut verts: [Vector3<f32>; 4] = 
+                                [
+                                    get_rcd_xyz_array(*axis, (r) as f32 * scale, (c) as f32 * scale, (depth as f32 + 1.) * scale).into(),
+                                    get_rcd_xyz_array(*axis, (r) as f32 * scale, (c + width) as f32 * scale, (depth as f32 + 1.) * scale).into(),
+                                    get_rcd_xyz_array(*axis, (r + height) as f32 * scale, (c + width) as f32 * scale, (depth as f32 + 1.) * scale).into(),
+                                    get_rcd_xyz_array(*axis, (r + height) as f32 * scale, (c) as f32 * scale, (depth as f32 + 1.) * scale).into(),
+                                ];
+                            match side {
+                                Side::East | Side::Top | Side::South => {
+                                    verts.reverse();
+                                    ao.reverse();
+                                    light.reverse();
+                                },
+                                _ => {}
+                            }
+                            
+                            let quad = ChunkQuad {
+                                corners: verts,
+                                side,
+                                face: starting_face,
+                                ao,
+                                light,
+                                normals: None,
+                            };
+                            if registry.is_translucent(starting_face.voxel) {
+                                mesh.translucent.push(quad);
+                            } else {
+                                mesh.quads.push(quad);
                             }
 
                             // clear out the mask for the range
@@ -339,7 +949,7 @@ where T: Deref<Target=MaskedStorage<ChunkData>>
 
                             // Increment c by the width we jumped
                             c += width;
-                            debug_assert!(c <= CHUNK_SIZE); // should not have created a quad past the chunk size
+                            debug_assert!(c <= size); // should not have created a quad past the chunk size
                         } else {
                             c += 1;
                         }
@@ -350,7 +960,147 @@ where T: Deref<Target=MaskedStorage<ChunkData>>
     }
 }
 
-#[inline(always)]
-fn is_opaque(v: Voxel) -> bool {
-    v != 0
+/// Ambient occlusion for the corners of the face at row `r`, column `c`,
+/// from the voxels around it in the layer the face looks out into. Corners are
+/// in the same order as an unreversed quad's vertices.
+fn face_ao<S: VoxelSource>(data: &S, registry: &BlockRegistry, axis: Axis, r: i32, c: i32, open_depth: i32) -> [u8; 4] {
+    let mut occluders = [[false; 3]; 3];
+    for dr in 0..3 {
+        for dc in 0..3 {
+            if dr == 1 && dc == 1 {
+                continue;
+            }
+            occluders[dr][dc] = data.get_voxel(get_rcd_xyz(axis, r + dr as i32 - 1, c + dc as i32 - 1, open_depth))
+                .map(|voxel| registry.is_opaque(voxel))
+                .unwrap_or(false);
+        }
+    }
+
+    let corner = |dr: usize, dc: usize| {
+        let edge_r = occluders[dr][1];
+        let edge_c = occluders[1][dc];
+        if edge_r && edge_c {
+            0
+        } else {
+            3 - (edge_r as u8 + edge_c as u8 + occluders[dr][dc] as u8)
+        }
+    };
+    [corner(0, 0), corner(0, 2), corner(2, 2), corner(2, 0)]
+}
+
+/// Smooth light for the corners of the face at row `r`, column `c`: each
+/// corner averages the open voxels touching it in the layer the face looks
+/// out into. Corners are in the same order as `face_ao`'s.
+fn face_light<S: VoxelSource>(data: &S, registry: &BlockRegistry, axis: Axis, r: i32, c: i32, open_depth: i32) -> [u8; 4] {
+    let mut samples = [[None; 3]; 3];
+    for dr in 0..3 {
+        for dc in 0..3 {
+            let index = get_rcd_xyz(axis, r + dr as i32 - 1, c + dc as i32 - 1, open_depth);
+            let open = data.get_voxel(index).map(|voxel| !registry.is_opaque(voxel)).unwrap_or(false);
+            if open {
+                samples[dr][dc] = data.get_light(index);
+            }
+        }
+    }
+    let centre = match samples[1][1] {
+        Some(light) => light,
+        // unlit sources, or a face against an unloaded chunk
+        None => MAX_LIGHT << 4,
+    };
+
+    let corner = |dr: usize, dc: usize| {
+        // the diagonal only counts if light can get round to it
+        let diagonal = if samples[dr][1].is_some() || samples[1][dc].is_some() { samples[dr][dc] } else { None };
+        let (mut sky, mut block, mut count) = ((centre >> 4) as u32, (centre & 0xf) as u32, 1);
+        for light in [samples[dr][1], samples[1][dc], diagonal].iter().filter_map(|l| *l) {
+            sky += (light >> 4) as u32;
+            block += (light & 0xf) as u32;
+            count += 1;
+        }
+        (((sky + count / 2) / count) << 4 | (block + count / 2) / count) as u8
+    };
+    [corner(0, 0), corner(0, 2), corner(2, 2), corner(2, 0)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::world_slice::ChunkMapSlice;
+    use super::super::data::Voxel;
+
+    use std::path::Path;
+
+    fn registry() -> BlockRegistry {
+        BlockRegistry::load(Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/blocks.ron")).unwrap()
+    }
+
+    /// Meshes a chunk of air holding the given blocks, with air all around it.
+    fn mesh(registry: &BlockRegistry, blocks: &[((usize, usize, usize), &str)]) -> ChunkQuads {
+        let mut chunks = FnvHashMap::default();
+        let mut data = ChunkData::default();
+        for &(local, name) in blocks {
+            data.set_voxel(local, registry.voxel(name).unwrap());
+        }
+        chunks.insert((0, 0, 0), data);
+        for side in SIDES.iter() {
+            chunks.insert(side.offset(), ChunkData::default());
+        }
+        let mut mesh = ChunkQuads::default();
+        quads_from_data(&ChunkMapSlice::new(&chunks, (0, 0, 0)), registry, &mut mesh);
+        mesh
+    }
+
+    fn area(quads: &[ChunkQuad], voxel: Voxel, side: Side) -> f32 {
+        quads.iter()
+            .filter(|quad| quad.face.voxel == voxel && quad.side == side)
+            .map(|quad| (quad.corners[1] - quad.corners[0]).norm() * (quad.corners[3] - quad.corners[0]).norm())
+            .sum()
+    }
+
+    #[test]
+    fn glass_against_stone() {
+        let registry = registry();
+        let (glass, stone) = (registry.voxel("glass").unwrap(), registry.voxel("stone").unwrap());
+        let mesh = mesh(&registry, &[((5, 5, 5), "glass"), ((6, 5, 5), "stone")]);
+
+        // the stone hides the glass face against it, but shows through the glass
+        assert_eq!(area(mesh.translucent_quads(), glass, Side::East), 0.);
+        for side in SIDES.iter().filter(|side| **side != Side::East) {
+            assert_eq!(area(mesh.translucent_quads(), glass, *side), 1.);
+        }
+        for side in SIDES.iter() {
+            assert_eq!(area(mesh.quads(), stone, *side), 1.);
+        }
+        assert!(mesh.quads().iter().all(|quad| quad.face.voxel == stone));
+    }
+
+    #[test]
+    fn glass_against_glass() {
+        let registry = registry();
+        let glass = registry.voxel("glass").unwrap();
+        let mesh = mesh(&registry, &[((5, 5, 5), "glass"), ((6, 5, 5), "glass")]);
+
+        // only the outside of the pair, without the faces between them
+        assert!(mesh.quads().is_empty());
+        assert_eq!(area(mesh.translucent_quads(), glass, Side::East), 1.);
+        assert_eq!(area(mesh.translucent_quads(), glass, Side::West), 1.);
+        for side in &[Side::North, Side::South, Side::Top, Side::Bottom] {
+            assert_eq!(area(mesh.translucent_quads(), glass, *side), 2.);
+        }
+    }
+
+    #[test]
+    fn water_is_translucent() {
+        let registry = registry();
+        let (water, stone) = (registry.voxel("water").unwrap(), registry.voxel("stone").unwrap());
+        let mesh = mesh(&registry, &[((5, 5, 5), "water"), ((5, 6, 5), "water"), ((5, 4, 5), "stone")]);
+
+        assert!(mesh.quads().iter().all(|quad| quad.face.voxel == stone));
+        assert!(mesh.translucent_quads().iter().all(|quad| quad.face.voxel == water));
+        // the stone's top shows through the water, which has no bottom against it
+        assert_eq!(area(mesh.quads(), stone, Side::Top), 1.);
+        assert_eq!(area(mesh.translucent_quads(), water, Side::Bottom), 0.);
+        assert_eq!(area(mesh.translucent_quads(), water, Side::Top), 1.);
+        assert_eq!(area(mesh.translucent_quads(), water, Side::East), 2.);
+    }
 }
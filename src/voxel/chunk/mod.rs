@@ -1,6 +1,8 @@
 pub mod data;
 pub mod mesh;
 pub mod material;
+pub mod pass;
+pub mod surface_nets;
 
 use self::data::ChunkData;
 
@@ -62,20 +64,20 @@ impl<'a> System<'a> for ChunkIndexPositionSystem {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Axis {
     X,
     Y,
     Z,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Face {
     Front,
     Back,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Side {
     North,
     South,
@@ -85,6 +87,48 @@ pub enum Side {
     Bottom,
 }
 
+pub static SIDES: &'static [Side] = &[
+    Side::North,
+    Side::South,
+    Side::East,
+    Side::West,
+    Side::Top,
+    Side::Bottom,
+];
+
+impl Side {
+    /// The offset to the neighbouring cell on this side, for voxels and chunks alike.
+    pub fn offset(self) -> (i32, i32, i32) {
+        use self::Side::*;
+
+        match self {
+            North => (0, 0, 1),
+            South => (0, 0, -1),
+            East => (1, 0, 0),
+            West => (-1, 0, 0),
+            Top => (0, 1, 0),
+            Bottom => (0, -1, 0),
+        }
+    }
+
+    /// The axis this side faces along.
+    pub fn axis(self) -> Axis {
+        use self::Side::*;
+
+        match self {
+            East | West => Axis::X,
+            Top | Bottom => Axis::Y,
+            North | South => Axis::Z,
+        }
+    }
+
+    /// The index of the neighbouring cell on this side.
+    pub fn neighbour(self, index: (i32, i32, i32)) -> (i32, i32, i32) {
+        let (x, y, z) = self.offset();
+        (index.0 + x, index.1 + y, index.2 + z)
+    }
+}
+
 impl From<(Axis, Face)> for Side {
     fn from(coord: (Axis, Face)) -> Side {
         use self::Axis::*;
@@ -0,0 +1,70 @@
+//! The render pass chunk meshes are drawn with: the 3D base pass, with
+//! shaders that repeat each block's atlas tile across a merged quad and
+//! shade it by the light and ambient occlusion baked into the vertex colour.
+//!
+//! The shaders are in `shaders/`; after editing them, recompile with
+//! `glslangValidator -V shaders/chunk.vert -o shaders/compiled/chunk.vert.spv`
+//! and likewise for `chunk.frag`.
+
+use super::mesh::AtlasTile;
+
+use amethyst::renderer::pass::Base3DPassDef;
+use amethyst::renderer::plugins::RenderBase3D;
+use amethyst::renderer::mtl::{TexAlbedo, TexEmission};
+use amethyst::renderer::rendy::mesh::{AsVertex, Color, Normal, Position, TexCoord, VertexFormat};
+use amethyst::renderer::rendy::shader::SpirvShader;
+use amethyst::renderer::rendy::hal::pso::ShaderStageFlags;
+
+lazy_static! {
+    static ref CHUNK_VERTEX: SpirvShader = SpirvShader::new(
+        include_bytes!("../../../shaders/compiled/chunk.vert.spv").to_vec(),
+        ShaderStageFlags::VERTEX,
+        "main",
+    );
+    static ref CHUNK_FRAGMENT: SpirvShader = SpirvShader::new(
+        include_bytes!("../../../shaders/compiled/chunk.frag.spv").to_vec(),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    );
+}
+
+/// Draws meshes with the vertex buffers `ChunkMeshBuffers` builds: position,
+/// normal, texture coordinates in cells, colour and atlas tile.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChunkPassDef;
+
+impl Base3DPassDef for ChunkPassDef {
+    const NAME: &'static str = "Chunk";
+    // only the albedo is sampled, but the material layout is the shaded pass's
+    type TextureSet = (TexAlbedo, TexEmission);
+
+    fn vertex_shader() -> &'static SpirvShader {
+        &CHUNK_VERTEX
+    }
+
+    // chunks are never skinned
+    fn vertex_skinned_shader() -> &'static SpirvShader {
+        &CHUNK_VERTEX
+    }
+
+    fn fragment_shader() -> &'static SpirvShader {
+        &CHUNK_FRAGMENT
+    }
+
+    fn base_format() -> Vec<VertexFormat> {
+        vec![
+            Position::vertex(),
+            Normal::vertex(),
+            TexCoord::vertex(),
+            Color::vertex(),
+            AtlasTile::vertex(),
+        ]
+    }
+
+    fn skinned_format() -> Vec<VertexFormat> {
+        Self::base_format()
+    }
+}
+
+/// Renders chunks, opaque and translucent, with `ChunkPassDef`.
+pub type RenderChunks = RenderBase3D<ChunkPassDef>;
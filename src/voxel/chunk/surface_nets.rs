@@ -0,0 +1,351 @@
+//! Smooth meshing of opaque blocks with Surface Nets, as described in
+//! https://0fps.net/2012/07/12/smooth-voxel-terrain-part-2/
+//!
+//! Every voxel is a density sample at its centre: the share of opaque voxels
+//! in the 3x3x3 block around it. Each cell between eight samples that the
+//! surface passes through gets one vertex, where its edges cross the surface
+//! on average, and each voxel edge between an opaque and a see-through voxel
+//! becomes a quad joining the vertices of the four cells around it.
+//!
+//! A chunk makes the quads for the edges leaving its own voxels in the
+//! positive directions, so every edge belongs to exactly one chunk, and the
+//! cell vertices only depend on the voxels around them, so chunks meet
+//! without seams.
+
+use super::{Axis, Face, Side};
+use super::data::AIR;
+use super::mesh::ChunkQuad;
+use super::super::world_slice::VoxelSource;
+use super::super::block::BlockRegistry;
+use super::super::light::MAX_LIGHT;
+
+use cgmath::Vector3;
+
+/// The density of the surface.
+const ISO: f32 = 0.5;
+
+/// Samples of a chunk and the two layers around it.
+struct Samples {
+    /// Samples from `-MARGIN` to `size + MARGIN` exclusive along each axis.
+    span: i32,
+    opaque: Vec<bool>,
+    density: Vec<f32>,
+}
+
+const MARGIN: i32 = 2;
+
+impl Samples {
+    fn new<S: VoxelSource>(data: &S, registry: &BlockRegistry, size: i32) -> Self {
+        let span = size + MARGIN * 2;
+        let volume = (span * span * span) as usize;
+        let mut samples = Samples {
+            span,
+            opaque: vec![false; volume],
+            density: vec![0.; volume],
+        };
+        for z in -MARGIN..(size + MARGIN) {
+            for y in -MARGIN..(size + MARGIN) {
+                for x in -MARGIN..(size + MARGIN) {
+                    let i = samples.index((x, y, z));
+                    // unloaded voxels count as empty, so the surface closes off against them
+                    samples.opaque[i] = data.get_voxel((x, y, z)).map(|voxel| registry.is_opaque(voxel)).unwrap_or(false);
+                }
+            }
+        }
+        for z in (1 - MARGIN)..(size + MARGIN - 1) {
+            for y in (1 - MARGIN)..(size + MARGIN - 1) {
+                for x in (1 - MARGIN)..(size + MARGIN - 1) {
+                    let mut sum = 0;
+                    for dz in -1..2 {
+                        for dy in -1..2 {
+                            for dx in -1..2 {
+                                sum += samples.opaque[samples.index((x + dx, y + dy, z + dz))] as u32;
+                            }
+                        }
+                    }
+                    let i = samples.index((x, y, z));
+                    samples.density[i] = sum as f32 / 27.;
+                }
+            }
+        }
+        samples
+    }
+
+    #[inline(always)]
+    fn index(&self, pos: (i32, i32, i32)) -> usize {
+        (((pos.2 + MARGIN) * self.span + pos.1 + MARGIN) * self.span + pos.0 + MARGIN) as usize
+    }
+
+    #[inline(always)]
+    fn opaque(&self, pos: (i32, i32, i32)) -> bool {
+        self.opaque[self.index(pos)]
+    }
+
+    #[inline(always)]
+    fn density(&self, pos: (i32, i32, i32)) -> f32 {
+        self.density[self.index(pos)]
+    }
+}
+
+/// The vertex of one cell, in cell units from the chunk's origin.
+#[derive(Clone, Copy, Debug)]
+struct CellVertex {
+    position: Vector3<f32>,
+    normal: Vector3<f32>,
+    light: u8,
+}
+
+/// Meshes the opaque voxels of `size` cells along each side of the chunk at
+/// the centre of `data` into smooth quads, scaled up to fill the chunk.
+pub fn surface_nets_quads<S: VoxelSource>(data: &S, registry: &BlockRegistry, size: usize, quads: &mut Vec<ChunkQuad>) {
+    // nothing to do unless the voxels this chunk's edges join aren't all alike
+    let uniform = (0..8).map(|i| data.chunk((i & 1, (i >> 1) & 1, (i >> 2) & 1)))
+        .map(|chunk| chunk.and_then(|chunk| chunk.uniform_voxel()).map(|voxel| registry.is_opaque(voxel)))
+        .collect::<Vec<_>>();
+    if uniform.iter().all(|opaque| *opaque == Some(false)) || uniform.iter().all(|opaque| *opaque == Some(true)) {
+        return;
+    }
+
+    let size = size as i32;
+    let scale = super::data::CHUNK_SIZE as f32 / size as f32;
+    let samples = Samples::new(data, registry, size);
+
+    // cells from -1 to size - 1, each named by its lowest corner
+    let cell_span = size + 1;
+    let cell_index = |pos: (i32, i32, i32)| (((pos.2 + 1) * cell_span + pos.1 + 1) * cell_span + pos.0 + 1) as usize;
+    let mut cells: Vec<Option<CellVertex>> = vec![None; (cell_span * cell_span * cell_span) as usize];
+    for z in -1..size {
+        for y in -1..size {
+            for x in -1..size {
+                cells[cell_index((x, y, z))] = cell_vertex(data, &samples, (x, y, z));
+            }
+        }
+    }
+
+    for &axis in [Axis::X, Axis::Y, Axis::Z].iter() {
+        let (step, u, v) = match axis {
+            Axis::X => ((1, 0, 0), (0, 1, 0), (0, 0, 1)),
+            Axis::Y => ((0, 1, 0), (0, 0, 1), (1, 0, 0)),
+            Axis::Z => ((0, 0, 1), (1, 0, 0), (0, 1, 0)),
+        };
+        for z in 0..size {
+            for y in 0..size {
+                for x in 0..size {
+                    let p = (x, y, z);
+                    let q = (x + step.0, y + step.1, z + step.2);
+                    let (p_opaque, q_opaque) = (samples.opaque(p), samples.opaque(q));
+                    if p_opaque == q_opaque {
+                        continue;
+                    }
+
+                    // the four cells sharing the edge, wound so the quad faces
+                    // away from the opaque voxel
+                    let mut corners = [None; 4];
+                    for (k, &(a, b)) in [(0, 0), (1, 0), (1, 1), (0, 1)].iter().enumerate() {
+                        let cell = (
+                            x + (a - 1) * u.0 + (b - 1) * v.0,
+                            y + (a - 1) * u.1 + (b - 1) * v.1,
+                            z + (a - 1) * u.2 + (b - 1) * v.2,
+                        );
+                        corners[k] = cells[cell_index(cell)];
+                    }
+                    let mut corners = match (corners[0], corners[1], corners[2], corners[3]) {
+                        (Some(a), Some(b), Some(c), Some(d)) => [a, b, c, d],
+                        _ => continue,
+                    };
+                    let (solid, face) = if p_opaque { (p, Face::Front) } else { (q, Face::Back) };
+                    if !p_opaque {
+                        corners.reverse();
+                    }
+
+                    let side: Side = (axis, face).into();
+                    let voxel = data.get_voxel(solid).unwrap_or(AIR);
+                    quads.push(ChunkQuad {
+                        corners: [
+                            corners[0].position * scale,
+                            corners[1].position * scale,
+                            corners[2].position * scale,
+                            corners[3].position * scale,
+                        ],
+                        side,
+                        face: voxel.face(side),
+                        ao: [3; 4],
+                        light: [corners[0].light, corners[1].light, corners[2].light, corners[3].light],
+                        normals: Some([corners[0].normal, corners[1].normal, corners[2].normal, corners[3].normal]),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// The vertex of the cell whose lowest corner is the voxel at `cell`, if
+/// the surface passes through it.
+fn cell_vertex<S: VoxelSource>(data: &S, samples: &Samples, cell: (i32, i32, i32)) -> Option<CellVertex> {
+    let corner = |i: i32| (cell.0 + (i & 1), cell.1 + ((i >> 1) & 1), cell.2 + ((i >> 2) & 1));
+    let opaque: Vec<bool> = (0..8).map(|i| samples.opaque(corner(i))).collect();
+    if opaque.iter().all(|o| *o) || opaque.iter().all(|o| !*o) {
+        return None;
+    }
+    let density: Vec<f32> = (0..8).map(|i| samples.density(corner(i))).collect();
+
+    // average where the twelve edges of the cell cross the surface
+    let mut sum = Vector3::new(0., 0., 0.);
+    let mut crossings = 0;
+    for a in 0..8 {
+        for &bit in [1, 2, 4].iter() {
+            let b = a | bit;
+            if a & bit != 0 || opaque[a as usize] == opaque[b as usize] {
+                continue;
+            }
+            let (da, db) = (density[a as usize], density[b as usize]);
+            let t = if (db - da).abs() > 1e-6 { ((ISO - da) / (db - da)).max(0.).min(1.) } else { 0.5 };
+            let pa = Vector3::new((a & 1) as f32, ((a >> 1) & 1) as f32, ((a >> 2) & 1) as f32);
+            let pb = Vector3::new((b & 1) as f32, ((b >> 1) & 1) as f32, ((b >> 2) & 1) as f32);
+            sum += pa + (pb - pa) * t;
+            crossings += 1;
+        }
+    }
+    let offset = sum / crossings as f32;
+    // sample centres sit half a voxel in from the voxel's corner
+    let position = Vector3::new(cell.0 as f32 + 0.5, cell.1 as f32 + 0.5, cell.2 as f32 + 0.5) + offset;
+
+    // density rises into the ground, so the normal is down its gradient
+    let d = |x: usize, y: usize, z: usize| density[x | (y << 1) | (z << 2)];
+    let gradient = Vector3::new(
+        d(1, 0, 0) - d(0, 0, 0) + d(1, 1, 0) - d(0, 1, 0) + d(1, 0, 1) - d(0, 0, 1) + d(1, 1, 1) - d(0, 1, 1),
+        d(0, 1, 0) - d(0, 0, 0) + d(1, 1, 0) - d(1, 0, 0) + d(0, 1, 1) - d(0, 0, 1) + d(1, 1, 1) - d(1, 0, 1),
+        d(0, 0, 1) - d(0, 0, 0) + d(1, 0, 1) - d(1, 0, 0) + d(0, 1, 1) - d(0, 1, 0) + d(1, 1, 1) - d(1, 1, 0),
+    );
+    let normal = if gradient.norm() > 1e-6 { -gradient.normalize() } else { Vector3::new(0., 1., 0.) };
+
+    // the brightest of the open voxels around the vertex
+    let mut light = None;
+    for i in 0..8 {
+        if opaque[i as usize] {
+            continue;
+        }
+        if let Some(l) = data.get_light(corner(i)) {
+            let brighter = light.map(|best: u8| (l >> 4).max(l & 0xf) > (best >> 4).max(best & 0xf)).unwrap_or(true);
+            if brighter {
+                light = Some(l);
+            }
+        }
+    }
+
+    Some(CellVertex {
+        position,
+        normal,
+        light: light.unwrap_or(MAX_LIGHT << 4),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::data::{ChunkData, Voxel, CHUNK_SIZE};
+    use super::super::super::world_slice::ChunkMapSlice;
+
+    use fnv::FnvHashMap;
+    use std::path::Path;
+
+    fn registry() -> BlockRegistry {
+        BlockRegistry::load(Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/blocks.ron")).unwrap()
+    }
+
+    /// The chunks from `min` to `max` inclusive, filled by `voxel` from
+    /// world coordinates.
+    fn chunks<F>(min: (i32, i32, i32), max: (i32, i32, i32), voxel: F) -> FnvHashMap<(i32, i32, i32), ChunkData>
+    where F: Fn((i32, i32, i32)) -> Voxel
+    {
+        let mut chunks = FnvHashMap::default();
+        let size = CHUNK_SIZE as i32;
+        for cx in min.0..(max.0 + 1) {
+            for cy in min.1..(max.1 + 1) {
+                for cz in min.2..(max.2 + 1) {
+                    let mut data = ChunkData::default();
+                    for x in 0..CHUNK_SIZE {
+                        for y in 0..CHUNK_SIZE {
+                            for z in 0..CHUNK_SIZE {
+                                let position = (cx * size + x as i32, cy * size + y as i32, cz * size + z as i32);
+                                data.set_voxel((x, y, z), voxel(position));
+                            }
+                        }
+                    }
+                    chunks.insert((cx, cy, cz), data);
+                }
+            }
+        }
+        chunks
+    }
+
+    #[test]
+    fn uniform_chunks_have_no_quads() {
+        let registry = registry();
+        let stone = registry.voxel("stone").unwrap();
+        let mut quads = Vec::new();
+
+        let buried = chunks((-1, -1, -1), (1, 1, 1), |_| stone);
+        surface_nets_quads(&ChunkMapSlice::new(&buried, (0, 0, 0)), &registry, CHUNK_SIZE, &mut quads);
+        assert!(quads.is_empty());
+
+        let sky = chunks((-1, -1, -1), (1, 1, 1), |_| AIR);
+        surface_nets_quads(&ChunkMapSlice::new(&sky, (0, 0, 0)), &registry, CHUNK_SIZE, &mut quads);
+        assert!(quads.is_empty());
+
+        // but a uniform chunk under open air meshes the ground's surface
+        let ground = chunks((-1, -1, -1), (1, 1, 1), |p| if p.1 < CHUNK_SIZE as i32 { stone } else { AIR });
+        surface_nets_quads(&ChunkMapSlice::new(&ground, (0, 0, 0)), &registry, CHUNK_SIZE, &mut quads);
+        assert!(!quads.is_empty());
+        assert!(quads.iter().all(|quad| quad.side == Side::Top));
+    }
+
+    #[test]
+    fn border_vertices_match() {
+        let registry = registry();
+        let stone = registry.voxel("stone").unwrap();
+        // rolling ground across the border between the two chunks
+        let chunks = chunks((0, 0, 0), (1, 0, 0), |p| {
+            let height = 6 + (p.0 * 7 + p.2 * 3) % 5 + (p.0 / 4) % 2;
+            if p.1 < height { stone } else { AIR }
+        });
+        let left = ChunkMapSlice::new(&chunks, (0, 0, 0));
+        let right = ChunkMapSlice::new(&chunks, (1, 0, 0));
+        let size = CHUNK_SIZE as i32;
+        let left_samples = Samples::new(&left, &registry, size);
+        let right_samples = Samples::new(&right, &registry, size);
+
+        // the last cell of the left chunk is the right chunk's cell -1
+        let offset = Vector3::new(size as f32, 0., 0.);
+        let mut shared = Vec::new();
+        for y in -1..size {
+            for z in -1..size {
+                let a = cell_vertex(&left, &left_samples, (size - 1, y, z));
+                let b = cell_vertex(&right, &right_samples, (-1, y, z));
+                match (a, b) {
+                    (Some(a), Some(b)) => {
+                        assert_eq!(a.position, b.position + offset, "cell at {}, {}", y, z);
+                        assert_eq!(a.normal, b.normal);
+                        assert_eq!(a.light, b.light);
+                        shared.push(a.position);
+                    },
+                    (None, None) => {},
+                    _ => panic!("only one chunk has a vertex in the cell at {}, {}", y, z),
+                }
+            }
+        }
+        assert!(!shared.is_empty());
+
+        // which are the ones the right chunk's quads reach back to
+        let mut quads = Vec::new();
+        surface_nets_quads(&right, &registry, CHUNK_SIZE, &mut quads);
+        let border: Vec<_> = quads.iter()
+            .flat_map(|quad| quad.corners.iter())
+            .filter(|corner| corner.x < 0.5)
+            .collect();
+        assert!(!border.is_empty());
+        for corner in border {
+            assert!(shared.contains(&(corner + offset)), "{:?}", corner);
+        }
+    }
+}
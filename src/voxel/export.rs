@@ -0,0 +1,340 @@
+//! Writing chunk meshes to Wavefront OBJ, PLY and binary glTF, so meshing can
+//! be inspected without a window or GPU.
+//!
+//! Every quad becomes four vertices and two triangles. UVs are in blocks, so
+//! a merged quad three blocks wide runs from 0 to 3 and shows where the
+//! greedy merge joined faces. Each face's block id goes wherever the format
+//! allows: an OBJ material per block, a PLY face property, or a glTF vertex
+//! attribute (`_BLOCK_ID`, a float, as glTF has no 16-bit attributes that
+//! keep their alignment) shared by the four vertices of each quad.
+
+use super::{ChunkData, ChunkQuad, ChunkQuads, Side, CHUNK_SIZE, quads_from_data};
+use super::block::BlockRegistry;
+use super::world_slice::ChunkMapSlice;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use fnv::FnvHashMap;
+
+/// A triangle mesh gathered from chunk quads.
+#[derive(Clone, Debug, Default)]
+pub struct MeshExport {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    /// The block id of each quad, so of every two triangles.
+    pub block_ids: Vec<u16>,
+    /// Three per triangle.
+    pub indices: Vec<u32>,
+}
+
+impl MeshExport {
+    /// Meshes chunks that aren't part of a running world, such as imported
+    /// models or test fixtures. Chunks missing from the map are treated as
+    /// unloaded, so faces against them are kept.
+    pub fn from_chunks(chunks: &FnvHashMap<(i32, i32, i32), ChunkData>, registry: &BlockRegistry) -> Self {
+        let mut export = MeshExport::default();
+        let mut quads = ChunkQuads::default();
+        for &index in chunks.keys() {
+            quads_from_data(&ChunkMapSlice::new(chunks, index), registry, &mut quads);
+            export.add_quads(index, quads.quads());
+            export.add_quads(index, quads.translucent_quads());
+        }
+        export
+    }
+
+    /// Adds the quads of one chunk, placing them in the world by its index.
+    pub fn add_quads(&mut self, index: (i32, i32, i32), quads: &[ChunkQuad]) {
+        let size = CHUNK_SIZE as f32;
+        let offset = [index.0 as f32 * size, index.1 as f32 * size, index.2 as f32 * size];
+        for quad in quads.iter() {
+            let flat = side_normal(quad.side);
+            // UVs along the quad's width and height
+            let width = (quad.corners[1] - quad.corners[0]).norm().round();
+            let height = (quad.corners[3] - quad.corners[0]).norm().round();
+            let uvs = [[0., 0.], [width, 0.], [width, height], [0., height]];
+
+            let first = self.positions.len() as u32;
+            for (i, (corner, uv)) in quad.corners.iter().zip(uvs.iter()).enumerate() {
+                self.positions.push([corner.x + offset[0], corner.y + offset[1], corner.z + offset[2]]);
+                self.normals.push(quad.normals.map(|normals| [normals[i].x, normals[i].y, normals[i].z]).unwrap_or(flat));
+                self.uvs.push(*uv);
+            }
+            self.indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+            self.block_ids.push(quad.face.voxel.id);
+        }
+    }
+
+    pub fn save_obj<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_obj(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Faces are grouped under a `usemtl block_<id>` per block id.
+    pub fn write_obj<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for p in self.positions.iter() {
+            writeln!(writer, "v {} {} {}", p[0], p[1], p[2])?;
+        }
+        for uv in self.uvs.iter() {
+            writeln!(writer, "vt {} {}", uv[0], uv[1])?;
+        }
+        for n in self.normals.iter() {
+            writeln!(writer, "vn {} {} {}", n[0], n[1], n[2])?;
+        }
+        let mut material = None;
+        for (triangle, indices) in self.indices.chunks(3).enumerate() {
+            let id = self.block_ids[triangle / 2];
+            if material != Some(id) {
+                writeln!(writer, "usemtl block_{}", id)?;
+                material = Some(id);
+            }
+            // OBJ indices start at 1
+            let (a, b, c) = (indices[0] + 1, indices[1] + 1, indices[2] + 1);
+            writeln!(writer, "f {}/{}/{} {}/{}/{} {}/{}/{}", a, a, a, b, b, b, c, c, c)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_ply<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_ply(&mut writer)?;
+        writer.flush()
+    }
+
+    /// ASCII PLY, with a `block_id` property on each face.
+    pub fn write_ply<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "ply")?;
+        writeln!(writer, "format ascii 1.0")?;
+        writeln!(writer, "element vertex {}", self.positions.len())?;
+        for property in ["x", "y", "z", "nx", "ny", "nz", "s", "t"].iter() {
+            writeln!(writer, "property float {}", property)?;
+        }
+        writeln!(writer, "element face {}", self.indices.len() / 3)?;
+        writeln!(writer, "property list uchar uint vertex_indices")?;
+        writeln!(writer, "property ushort block_id")?;
+        writeln!(writer, "end_header")?;
+        for i in 0..self.positions.len() {
+            let (p, n, uv) = (self.positions[i], self.normals[i], self.uvs[i]);
+            writeln!(writer, "{} {} {} {} {} {} {} {}", p[0], p[1], p[2], n[0], n[1], n[2], uv[0], uv[1])?;
+        }
+        for (triangle, indices) in self.indices.chunks(3).enumerate() {
+            writeln!(writer, "3 {} {} {} {}", indices[0], indices[1], indices[2], self.block_ids[triangle / 2])?;
+        }
+        Ok(())
+    }
+
+    pub fn save_glb<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_glb(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Binary glTF 2.0: one mesh with `POSITION`, `NORMAL`, `TEXCOORD_0` and
+    /// `_BLOCK_ID` attributes and 32-bit indices, all in one buffer.
+    ///
+    /// glTF has no empty buffers, so a mesh without faces is an error.
+    pub fn write_glb<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let vertex_count = self.positions.len();
+        if vertex_count == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no faces to export"));
+        }
+        let mut bin = Vec::new();
+        let mut views = Vec::new();
+        {
+            // each view starts 4-byte aligned, as glTF requires
+            let mut view = |bin: &mut Vec<u8>, bytes: Vec<u8>, target: u32| {
+                while bin.len() % 4 != 0 {
+                    bin.push(0);
+                }
+                views.push((bin.len(), bytes.len(), target));
+                bin.extend(bytes);
+            };
+            let positions: Vec<f32> = self.positions.iter().flat_map(|p| p.to_vec()).collect();
+            let normals: Vec<f32> = self.normals.iter().flat_map(|n| n.to_vec()).collect();
+            let uvs: Vec<f32> = self.uvs.iter().flat_map(|uv| uv.to_vec()).collect();
+            view(&mut bin, f32_bytes(&positions), ARRAY_BUFFER);
+            view(&mut bin, f32_bytes(&normals), ARRAY_BUFFER);
+            view(&mut bin, f32_bytes(&uvs), ARRAY_BUFFER);
+            // the quad's block id on each of its four vertices
+            let ids: Vec<f32> = self.block_ids.iter()
+                .flat_map(|&id| vec![id as f32; 4])
+                .collect();
+            view(&mut bin, f32_bytes(&ids), ARRAY_BUFFER);
+            let indices = self.indices.iter().flat_map(|i| i.to_le_bytes().to_vec()).collect();
+            view(&mut bin, indices, ELEMENT_ARRAY_BUFFER);
+        }
+        while bin.len() % 4 != 0 {
+            bin.push(0);
+        }
+
+        let (min, max) = bounds(&self.positions);
+        let buffer_views: Vec<String> = views.iter()
+            .map(|&(offset, length, target)| format!(
+                r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#,
+                offset, length, target,
+            ))
+            .collect();
+        let accessors = [
+            format!(
+                r#"{{"bufferView":0,"componentType":5126,"count":{},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+                vertex_count, min[0], min[1], min[2], max[0], max[1], max[2],
+            ),
+            format!(r#"{{"bufferView":1,"componentType":5126,"count":{},"type":"VEC3"}}"#, vertex_count),
+            format!(r#"{{"bufferView":2,"componentType":5126,"count":{},"type":"VEC2"}}"#, vertex_count),
+            format!(r#"{{"bufferView":3,"componentType":5126,"count":{},"type":"SCALAR"}}"#, vertex_count),
+            format!(r#"{{"bufferView":4,"componentType":5125,"count":{},"type":"SCALAR"}}"#, self.indices.len()),
+        ];
+        let mut json = format!(
+            concat!(
+                r#"{{"asset":{{"version":"2.0","generator":"voxld40"}},"#,
+                r#""scene":0,"scenes":[{{"nodes":[0]}}],"nodes":[{{"mesh":0}}],"#,
+                r#""meshes":[{{"primitives":[{{"attributes":{{"POSITION":0,"NORMAL":1,"TEXCOORD_0":2,"_BLOCK_ID":3}},"indices":4,"mode":4}}]}}],"#,
+                r#""accessors":[{}],"bufferViews":[{}],"buffers":[{{"byteLength":{}}}]}}"#,
+            ),
+            accessors.join(","),
+            buffer_views.join(","),
+            bin.len(),
+        ).into_bytes();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+
+        let total = 12 + 8 + json.len() + 8 + bin.len();
+        writer.write_all(b"glTF")?;
+        writer.write_all(&2u32.to_le_bytes())?;
+        writer.write_all(&(total as u32).to_le_bytes())?;
+        writer.write_all(&(json.len() as u32).to_le_bytes())?;
+        writer.write_all(b"JSON")?;
+        writer.write_all(&json)?;
+        writer.write_all(&(bin.len() as u32).to_le_bytes())?;
+        writer.write_all(b"BIN\0")?;
+        writer.write_all(&bin)?;
+        Ok(())
+    }
+}
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+fn side_normal(side: Side) -> [f32; 3] {
+    match side {
+        Side::Bottom => [0., -1., 0.],
+        Side::Top => [0., 1., 0.],
+        Side::East => [1., 0., 0.],
+        Side::West => [-1., 0., 0.],
+        Side::North => [0., 0., 1.],
+        Side::South => [0., 0., -1.],
+    }
+}
+
+fn f32_bytes(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_bits().to_le_bytes().to_vec()).collect()
+}
+
+fn bounds(positions: &[[f32; 3]]) -> ([f32; 3], [f32; 3]) {
+    if positions.is_empty() {
+        return ([0.; 3], [0.; 3]);
+    }
+    let mut min = positions[0];
+    let mut max = positions[0];
+    for p in positions.iter() {
+        for k in 0..3 {
+            min[k] = min[k].min(p[k]);
+            max[k] = max[k].max(p[k]);
+        }
+    }
+    (min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Voxel;
+
+    /// Two blocks of id 1 side by side, with a block of id 2 on the first.
+    /// That leaves 14 faces showing, which merge into 11 quads: six of id 1
+    /// and five of id 2.
+    fn sample() -> MeshExport {
+        let mut chunk = ChunkData::default();
+        chunk.set_voxel((0, 0, 0), Voxel::new(1));
+        chunk.set_voxel((1, 0, 0), Voxel::new(1));
+        chunk.set_voxel((0, 1, 0), Voxel::new(2));
+        let mut chunks = FnvHashMap::default();
+        chunks.insert((0, 0, 0), chunk);
+        MeshExport::from_chunks(&chunks, &BlockRegistry::default())
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    }
+
+    #[test]
+    fn meshes_chunks() {
+        let export = sample();
+        assert_eq!(export.block_ids.len(), 11);
+        assert_eq!(export.block_ids.iter().filter(|&&id| id == 1).count(), 6);
+        assert_eq!(export.block_ids.iter().filter(|&&id| id == 2).count(), 5);
+        assert_eq!(export.positions.len(), 44);
+        assert_eq!(export.normals.len(), 44);
+        assert_eq!(export.uvs.len(), 44);
+        assert_eq!(export.indices.len(), 66);
+    }
+
+    #[test]
+    fn writes_obj() {
+        let mut bytes = Vec::new();
+        sample().write_obj(&mut bytes).unwrap();
+        let obj = String::from_utf8(bytes).unwrap();
+        assert_eq!(obj.lines().filter(|line| line.starts_with("v ")).count(), 44);
+        assert_eq!(obj.lines().filter(|line| line.starts_with("vt ")).count(), 44);
+        assert_eq!(obj.lines().filter(|line| line.starts_with("vn ")).count(), 44);
+        assert_eq!(obj.lines().filter(|line| line.starts_with("f ")).count(), 22);
+        assert!(obj.lines().any(|line| line == "usemtl block_1"));
+        assert!(obj.lines().any(|line| line == "usemtl block_2"));
+    }
+
+    #[test]
+    fn writes_ply() {
+        let mut bytes = Vec::new();
+        sample().write_ply(&mut bytes).unwrap();
+        let ply = String::from_utf8(bytes).unwrap();
+        assert!(ply.lines().any(|line| line == "element vertex 44"));
+        assert!(ply.lines().any(|line| line == "element face 22"));
+        let body: Vec<&str> = ply.lines().skip_while(|&line| line != "end_header").skip(1).collect();
+        assert_eq!(body.len(), 44 + 22);
+        let block_ids: Vec<&str> = body[44..].iter().map(|face| face.split(' ').last().unwrap()).collect();
+        assert_eq!(block_ids.iter().filter(|&&id| id == "1").count(), 12);
+        assert_eq!(block_ids.iter().filter(|&&id| id == "2").count(), 10);
+    }
+
+    #[test]
+    fn writes_glb() {
+        let mut bytes = Vec::new();
+        sample().write_glb(&mut bytes).unwrap();
+        assert_eq!(&bytes[0..4], b"glTF");
+        assert_eq!(u32_at(&bytes, 4), 2);
+        assert_eq!(u32_at(&bytes, 8) as usize, bytes.len());
+
+        let json_length = u32_at(&bytes, 12) as usize;
+        assert_eq!(json_length % 4, 0);
+        assert_eq!(&bytes[16..20], b"JSON");
+        let json = String::from_utf8(bytes[20..20 + json_length].to_vec()).unwrap();
+        let bin_header = 20 + json_length;
+        let bin_length = u32_at(&bytes, bin_header) as usize;
+        assert_eq!(bin_length % 4, 0);
+        assert_eq!(&bytes[bin_header + 4..bin_header + 8], b"BIN\0");
+        assert_eq!(bin_header + 8 + bin_length, bytes.len());
+
+        assert!(json.contains(&format!(r#""buffers":[{{"byteLength":{}}}]"#, bin_length)));
+        assert!(json.contains(r#"{"bufferView":3,"componentType":5126,"count":44,"type":"SCALAR"}"#));
+        assert!(json.contains(r#"{"bufferView":4,"componentType":5125,"count":66,"type":"SCALAR"}"#));
+    }
+
+    #[test]
+    fn refuses_empty_glb() {
+        assert!(MeshExport::default().write_glb(&mut Vec::new()).is_err());
+    }
+}
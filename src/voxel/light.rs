@@ -0,0 +1,692 @@
+//! Voxel lighting: sunlight falling from the sky and light spreading out of
+//! emissive blocks, each from 0 to 15, flood-filled across chunk borders.
+//!
+//! Sunlight keeps its full strength straight down through anything that
+//! isn't opaque and loses a level for every other step, as does block light.
+//! The top layer of a chunk with nothing loaded above it is treated as open
+//! sky, so the world's sunlight comes in from the highest loaded chunks. A
+//! chunk above that has been loaded before instead lets down the sunlight
+//! that left its bottom when it unloaded, so caves stay dark as the chunks
+//! over them come and go.
+//!
+//! Edits are relit incrementally: the light that came through or from the
+//! edited voxels is flooded out, then whatever still reaches the hole is
+//! flooded back in.
+
+use super::{ChunkData, ChunkIndex, Voxel, AIR, CHUNK_SIZE, world_to_chunk};
+use super::chunk::data::CHUNK_VOLUME;
+use super::block::BlockRegistry;
+
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::Arc;
+
+use fnv::{FnvHashMap, FnvHashSet};
+use specs::{
+    Entities,
+    System,
+    ReadStorage,
+    WriteStorage,
+    ReaderId,
+    storage::ComponentEvent,
+    world::Index,
+    Join,
+    BitSet,
+    SystemData,
+};
+use shred::{ReadExpect, Resources, WriteExpect};
+
+pub const MAX_LIGHT: u8 = 15;
+
+/// Set in a voxel's light info when it stops light.
+const OPAQUE: u8 = 0x80;
+
+/// Offsets to the six voxels sharing a face with a voxel.
+const NEIGHBOURS: [(i32, i32, i32); 6] = [
+    (1, 0, 0), (-1, 0, 0),
+    (0, 1, 0), (0, -1, 0),
+    (0, 0, 1), (0, 0, -1),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Channel {
+    Sky,
+    Block,
+}
+
+const CHANNELS: [Channel; 2] = [Channel::Sky, Channel::Block];
+
+/// The light of one chunk.
+#[derive(Clone, Debug)]
+pub struct ChunkLight {
+    /// Sky light in the high nibble, block light in the low.
+    levels: Vec<u8>,
+    /// What each voxel does to light: `OPAQUE`, plus the light it emits in
+    /// the low nibble.
+    info: Vec<u8>,
+}
+
+impl ChunkLight {
+    fn new(data: &ChunkData, registry: &BlockRegistry) -> Self {
+        ChunkLight {
+            levels: vec![0; CHUNK_VOLUME],
+            info: light_info(data, registry),
+        }
+    }
+
+    /// Sky light in the high nibble, block light in the low.
+    #[inline]
+    pub fn get(&self, local: (usize, usize, usize)) -> u8 {
+        self.levels[linear_index(local)]
+    }
+
+    #[inline]
+    pub fn sky(&self, local: (usize, usize, usize)) -> u8 {
+        self.get(local) >> 4
+    }
+
+    #[inline]
+    pub fn block(&self, local: (usize, usize, usize)) -> u8 {
+        self.get(local) & 0xf
+    }
+}
+
+#[inline(always)]
+fn linear_index(index: (usize, usize, usize)) -> usize {
+    (index.2 * CHUNK_SIZE + index.1) * CHUNK_SIZE + index.0
+}
+
+fn voxel_info(voxel: Voxel, registry: &BlockRegistry) -> u8 {
+    if voxel == AIR {
+        return 0;
+    }
+    let opaque = if registry.is_opaque(voxel) { OPAQUE } else { 0 };
+    opaque | registry.emissive(voxel).min(MAX_LIGHT)
+}
+
+fn light_info(data: &ChunkData, registry: &BlockRegistry) -> Vec<u8> {
+    if let Some(voxel) = data.uniform_voxel() {
+        return vec![voxel_info(voxel, registry); CHUNK_VOLUME];
+    }
+    let mut info = vec![0; CHUNK_VOLUME];
+    for z in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                info[linear_index((x, y, z))] = voxel_info(data.get_voxel((x, y, z)), registry);
+            }
+        }
+    }
+    info
+}
+
+/// A resource holding the light of every loaded chunk.
+#[derive(Clone, Debug, Default)]
+pub struct WorldLight {
+    /// Shared so meshing can take snapshots cheaply; copied on write.
+    chunks: FnvHashMap<(i32, i32, i32), Arc<ChunkLight>>,
+    /// Chunks whose meshes are out of date because light they show changed.
+    changed: FnvHashSet<(i32, i32, i32)>,
+    /// For unloaded chunks that blocked some sunlight, which columns let it
+    /// out of their bottom, indexed `z * CHUNK_SIZE + x`.
+    sky_exits: FnvHashMap<(i32, i32, i32), Vec<bool>>,
+}
+
+impl WorldLight {
+    pub fn new() -> Self {
+        WorldLight::default()
+    }
+
+    /// A chunk's light as it is now, unaffected by later changes.
+    pub fn snapshot(&self, index: (i32, i32, i32)) -> Option<Arc<ChunkLight>> {
+        self.chunks.get(&index).cloned()
+    }
+
+    /// The light at a world voxel coordinate, sky light in the high nibble
+    /// and block light in the low. `None` if its chunk isn't lit.
+    #[inline]
+    pub fn get(&self, pos: (i32, i32, i32)) -> Option<u8> {
+        let (index, local) = world_to_chunk(pos);
+        self.chunks.get(&index).map(|chunk| chunk.get(local))
+    }
+
+    /// Takes the chunks whose meshes need rebuilding for new light.
+    pub fn take_changed(&mut self) -> FnvHashSet<(i32, i32, i32)> {
+        mem::replace(&mut self.changed, FnvHashSet::default())
+    }
+
+    /// Forgets which columns of unloaded chunks let sunlight out, except for
+    /// the chunks `keep` picks, so the record doesn't grow with every chunk
+    /// ever unloaded. A chunk under a forgotten one is lit as if open to the
+    /// sky until that chunk loads again.
+    pub fn retain_sky_exits<F>(&mut self, mut keep: F)
+    where F: FnMut((i32, i32, i32)) -> bool
+    {
+        self.sky_exits.retain(|&index, _| keep(index));
+    }
+
+    /// Lights a newly loaded chunk, and lets its light into the chunks
+    /// around it and theirs into it.
+    pub fn insert_chunk(&mut self, index: (i32, i32, i32), data: &ChunkData, registry: &BlockRegistry) {
+        self.chunks.insert(index, Arc::new(ChunkLight::new(data, registry)));
+        self.sky_exits.remove(&index);
+        self.changed.insert(index);
+
+        let size = CHUNK_SIZE as i32;
+        let origin = (index.0 * size, index.1 * size, index.2 * size);
+        let mut sky = VecDeque::new();
+        let mut block = VecDeque::new();
+
+        // sunlight straight down each column, from the chunk above or the sky
+        let above = (index.0, index.1 + 1, index.2);
+        for z in 0..size {
+            for x in 0..size {
+                let open = match self.chunks.get(&above) {
+                    Some(chunk) => chunk.sky((x as usize, 0, z as usize)) == MAX_LIGHT,
+                    None => self.sky_exit((origin.0 + x, origin.1 + size, origin.2 + z)),
+                };
+                if !open {
+                    continue;
+                }
+                for y in (0..size).rev() {
+                    let pos = (origin.0 + x, origin.1 + y, origin.2 + z);
+                    if self.is_opaque(pos) {
+                        break;
+                    }
+                    self.set_level(Channel::Sky, pos, MAX_LIGHT);
+                    sky.push_back(pos);
+                }
+            }
+        }
+
+        for z in 0..size {
+            for y in 0..size {
+                for x in 0..size {
+                    let pos = (origin.0 + x, origin.1 + y, origin.2 + z);
+                    let emission = self.emission(pos);
+                    if emission > 0 {
+                        self.set_level(Channel::Block, pos, emission);
+                        block.push_back(pos);
+                    }
+                }
+            }
+        }
+
+        // The chunk below may have taken this one for open sky; take back
+        // the sunlight this chunk now blocks.
+        let below = (index.0, index.1 - 1, index.2);
+        if self.chunks.contains_key(&below) {
+            let mut removed = VecDeque::new();
+            for z in 0..size {
+                for x in 0..size {
+                    let bottom = (origin.0 + x, origin.1, origin.2 + z);
+                    let under = (bottom.0, bottom.1 - 1, bottom.2);
+                    if self.level(Channel::Sky, bottom) != Some(MAX_LIGHT) && self.level(Channel::Sky, under) == Some(MAX_LIGHT) {
+                        self.set_level(Channel::Sky, under, 0);
+                        removed.push_back((under, MAX_LIGHT));
+                    }
+                }
+            }
+            sky.extend(self.unspread(Channel::Sky, removed));
+        }
+
+        // light already in the neighbours' border layers flows in
+        for &(dx, dy, dz) in NEIGHBOURS.iter() {
+            for a in 0..size {
+                for b in 0..size {
+                    let pos = match (dx, dy, dz) {
+                        (1, _, _) => (origin.0 + size, origin.1 + a, origin.2 + b),
+                        (-1, _, _) => (origin.0 - 1, origin.1 + a, origin.2 + b),
+                        (_, 1, _) => (origin.0 + a, origin.1 + size, origin.2 + b),
+                        (_, -1, _) => (origin.0 + a, origin.1 - 1, origin.2 + b),
+                        (_, _, 1) => (origin.0 + a, origin.1 + b, origin.2 + size),
+                        _ => (origin.0 + a, origin.1 + b, origin.2 - 1),
+                    };
+                    if self.level(Channel::Sky, pos).unwrap_or(0) > 1 {
+                        sky.push_back(pos);
+                    }
+                    if self.level(Channel::Block, pos).unwrap_or(0) > 1 {
+                        block.push_back(pos);
+                    }
+                }
+            }
+        }
+
+        self.spread(Channel::Sky, sky);
+        self.spread(Channel::Block, block);
+    }
+
+    /// Relights a chunk whose voxels changed, touching only the light that
+    /// passed through or came from the voxels that changed.
+    pub fn update_chunk(&mut self, index: (i32, i32, i32), data: &ChunkData, registry: &BlockRegistry) {
+        let info = light_info(data, registry);
+        let edited: Vec<(i32, i32, i32)> = match self.chunks.get_mut(&index) {
+            Some(chunk) => {
+                let chunk = Arc::make_mut(chunk);
+                let size = CHUNK_SIZE as i32;
+                let mut edited = Vec::new();
+                for z in 0..CHUNK_SIZE {
+                    for y in 0..CHUNK_SIZE {
+                        for x in 0..CHUNK_SIZE {
+                            let i = linear_index((x, y, z));
+                            if chunk.info[i] != info[i] {
+                                edited.push((index.0 * size + x as i32, index.1 * size + y as i32, index.2 * size + z as i32));
+                            }
+                        }
+                    }
+                }
+                chunk.info = info;
+                edited
+            },
+            None => return self.insert_chunk(index, data, registry),
+        };
+        if edited.is_empty() {
+            return;
+        }
+
+        for &channel in CHANNELS.iter() {
+            let mut removed = VecDeque::new();
+            for &pos in edited.iter() {
+                if let Some(old) = self.level(channel, pos) {
+                    if old > 0 {
+                        self.set_level(channel, pos, 0);
+                        removed.push_back((pos, old));
+                    }
+                }
+            }
+            let mut seeds = self.unspread(channel, removed);
+
+            for &pos in edited.iter() {
+                if let Some(source) = self.source(channel, pos) {
+                    self.set_level(channel, pos, source);
+                    seeds.push_back(pos);
+                }
+                for &(dx, dy, dz) in NEIGHBOURS.iter() {
+                    let neighbour = (pos.0 + dx, pos.1 + dy, pos.2 + dz);
+                    if self.level(channel, neighbour).unwrap_or(0) > 0 {
+                        seeds.push_back(neighbour);
+                    }
+                }
+            }
+            self.spread(channel, seeds);
+        }
+    }
+
+    /// Forgets an unloaded chunk, taking the light it sent into its
+    /// neighbours with it. Which of its columns let sunlight out of its
+    /// bottom is kept, for when the chunk below is next lit.
+    pub fn remove_chunk(&mut self, index: (i32, i32, i32)) {
+        let chunk = match self.chunks.remove(&index) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+        let exits: Vec<bool> = (0..CHUNK_SIZE * CHUNK_SIZE)
+            .map(|i| chunk.sky((i % CHUNK_SIZE, 0, i / CHUNK_SIZE)) == MAX_LIGHT)
+            .collect();
+        // columns that all let sunlight through are the same as open sky
+        if exits.iter().any(|&open| !open) {
+            self.sky_exits.insert(index, exits);
+        }
+
+        // the chunk's border voxels, already gone, darken what they lit
+        let size = CHUNK_SIZE as i32;
+        let origin = (index.0 * size, index.1 * size, index.2 * size);
+        let border = |l: usize| l == 0 || l == CHUNK_SIZE - 1;
+        for &channel in CHANNELS.iter() {
+            let mut removed = VecDeque::new();
+            for z in 0..CHUNK_SIZE {
+                for y in 0..CHUNK_SIZE {
+                    for x in 0..CHUNK_SIZE {
+                        if !(border(x) || border(y) || border(z)) {
+                            continue;
+                        }
+                        let old = match channel {
+                            Channel::Sky => chunk.sky((x, y, z)),
+                            Channel::Block => chunk.block((x, y, z)),
+                        };
+                        if old > 0 {
+                            removed.push_back(((origin.0 + x as i32, origin.1 + y as i32, origin.2 + z as i32), old));
+                        }
+                    }
+                }
+            }
+            let seeds = self.unspread(channel, removed);
+            self.spread(channel, seeds);
+        }
+        self.changed.remove(&index);
+    }
+
+    #[inline]
+    fn level(&self, channel: Channel, pos: (i32, i32, i32)) -> Option<u8> {
+        self.get(pos).map(|light| match channel {
+            Channel::Sky => light >> 4,
+            Channel::Block => light & 0xf,
+        })
+    }
+
+    fn set_level(&mut self, channel: Channel, pos: (i32, i32, i32), value: u8) {
+        let (index, local) = world_to_chunk(pos);
+        let chunk = match self.chunks.get_mut(&index) {
+            Some(chunk) => Arc::make_mut(chunk),
+            None => return,
+        };
+        let i = linear_index(local);
+        chunk.levels[i] = match channel {
+            Channel::Sky => (chunk.levels[i] & 0x0f) | (value << 4),
+            Channel::Block => (chunk.levels[i] & 0xf0) | value,
+        };
+
+        // meshes sample light one voxel past their own chunk, so changes on a
+        // border plane reach the neighbours sharing it
+        let span = |l: usize| match l {
+            0 => -1..1,
+            l if l == CHUNK_SIZE - 1 => 0..2,
+            _ => 0..1,
+        };
+        for dx in span(local.0) {
+            for dy in span(local.1) {
+                for dz in span(local.2) {
+                    self.changed.insert((index.0 + dx, index.1 + dy, index.2 + dz));
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn info(&self, pos: (i32, i32, i32)) -> Option<u8> {
+        let (index, local) = world_to_chunk(pos);
+        self.chunks.get(&index).map(|chunk| chunk.info[linear_index(local)])
+    }
+
+    #[inline]
+    fn is_opaque(&self, pos: (i32, i32, i32)) -> bool {
+        self.info(pos).map(|info| info & OPAQUE != 0).unwrap_or(false)
+    }
+
+    #[inline]
+    fn emission(&self, pos: (i32, i32, i32)) -> u8 {
+        self.info(pos).map(|info| info & 0xf).unwrap_or(0)
+    }
+
+    /// Whether full sunlight comes down out of the bottom of the unloaded
+    /// chunk holding `pos`: as it did when the chunk unloaded, or always for
+    /// a chunk that's open sky.
+    fn sky_exit(&self, pos: (i32, i32, i32)) -> bool {
+        let (index, local) = world_to_chunk(pos);
+        self.sky_exits.get(&index)
+            .map(|exits| exits[local.2 * CHUNK_SIZE + local.0])
+            .unwrap_or(true)
+    }
+
+    /// The light a voxel makes itself: its emission, or full sunlight at the
+    /// top of a column with nothing loaded above that lets it through.
+    fn source(&self, channel: Channel, pos: (i32, i32, i32)) -> Option<u8> {
+        let level = match channel {
+            Channel::Block => self.emission(pos),
+            Channel::Sky => {
+                let above = (pos.0, pos.1 + 1, pos.2);
+                if self.info(pos).is_some() && !self.is_opaque(pos) && self.info(above).is_none() && self.sky_exit(above) {
+                    MAX_LIGHT
+                } else {
+                    0
+                }
+            },
+        };
+        if level > 0 { Some(level) } else { None }
+    }
+
+    /// Flood-fills light outwards from the queued voxels.
+    fn spread(&mut self, channel: Channel, mut queue: VecDeque<(i32, i32, i32)>) {
+        while let Some(pos) = queue.pop_front() {
+            let level = match self.level(channel, pos) {
+                Some(level) if level > 1 => level,
+                _ => continue,
+            };
+            for &(dx, dy, dz) in NEIGHBOURS.iter() {
+                let neighbour = (pos.0 + dx, pos.1 + dy, pos.2 + dz);
+                if self.info(neighbour).is_none() || self.is_opaque(neighbour) {
+                    continue;
+                }
+                let spread = if channel == Channel::Sky && dy == -1 && level == MAX_LIGHT {
+                    MAX_LIGHT
+                } else {
+                    level - 1
+                };
+                if self.level(channel, neighbour).unwrap_or(MAX_LIGHT) < spread {
+                    self.set_level(channel, neighbour, spread);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+
+    /// Clears out the light that came from the queued voxels, which have
+    /// already been darkened, paired with the level they had. Returns the
+    /// voxels still lit from elsewhere, to spread back from.
+    fn unspread(&mut self, channel: Channel, mut queue: VecDeque<((i32, i32, i32), u8)>) -> VecDeque<(i32, i32, i32)> {
+        let mut relight = VecDeque::new();
+        while let Some((pos, old)) = queue.pop_front() {
+            for &(dx, dy, dz) in NEIGHBOURS.iter() {
+                let neighbour = (pos.0 + dx, pos.1 + dy, pos.2 + dz);
+                let level = match self.level(channel, neighbour) {
+                    Some(level) if level > 0 => level,
+                    _ => continue,
+                };
+                let came_from_here = level < old
+                    || (channel == Channel::Sky && dy == -1 && old == MAX_LIGHT && level == MAX_LIGHT);
+                if !came_from_here {
+                    relight.push_back(neighbour);
+                    continue;
+                }
+                match self.source(channel, neighbour) {
+                    Some(source) => {
+                        self.set_level(channel, neighbour, source);
+                        relight.push_back(neighbour);
+                        if source < level {
+                            queue.push_back((neighbour, level));
+                        }
+                    },
+                    None => {
+                        self.set_level(channel, neighbour, 0);
+                        queue.push_back((neighbour, level));
+                    },
+                }
+            }
+        }
+        relight
+    }
+}
+
+/// Keeps `WorldLight` in step with the loaded chunks and their edits.
+pub struct LightSystem {
+    reader_id: Option<ReaderId<ComponentEvent>>,
+
+    /// Chunk index of every lit entity, so a removed chunk's light can be
+    /// dropped after its components are gone.
+    chunk_positions: FnvHashMap<Index, (i32, i32, i32)>,
+}
+
+impl Default for LightSystem {
+    fn default() -> Self {
+        LightSystem {
+            reader_id: None,
+            chunk_positions: FnvHashMap::default(),
+        }
+    }
+}
+
+impl<'a> System<'a> for LightSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, ChunkIndex>,
+        ReadStorage<'a, ChunkData>,
+        WriteExpect<'a, WorldLight>,
+        ReadExpect<'a, BlockRegistry>,
+    );
+
+    fn run(&mut self, (entities, chunk_indices, chunk_datas, mut world_light, registry): Self::SystemData) {
+        let mut dirty = BitSet::new();
+        for event in chunk_datas.channel().read(self.reader_id.as_mut().unwrap()) {
+            match event {
+                ComponentEvent::Modified(id) | ComponentEvent::Inserted(id) => {
+                    dirty.add(*id);
+                },
+                ComponentEvent::Removed(id) => {
+                    if let Some(index) = self.chunk_positions.remove(id) {
+                        world_light.remove_chunk(index);
+                    }
+                },
+            }
+        }
+
+        for (entity, _, chunk_data, chunk_index) in (&*entities, &dirty, &chunk_datas, &chunk_indices).join() {
+            let index: (i32, i32, i32) = (*chunk_index).into();
+            self.chunk_positions.insert(entity.id(), index);
+            world_light.update_chunk(index, chunk_data, &registry);
+        }
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        <Self::SystemData as SystemData>::setup(res);
+        self.reader_id = Some(WriteStorage::<ChunkData>::fetch(res).register_reader())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::block::{BlockDef, BlockTextures, Transparency};
+
+    const STONE: Voxel = Voxel { id: 1, state: 0 };
+    const LAMP: Voxel = Voxel { id: 2, state: 0 };
+
+    fn registry() -> BlockRegistry {
+        let def = |id: u16, name: &str, emissive: u8| BlockDef {
+            id,
+            name: name.to_string(),
+            transparency: Transparency::Opaque,
+            solid: true,
+            emissive,
+            textures: BlockTextures::default(),
+            color: [1., 1., 1., 1.],
+        };
+        BlockRegistry::from_defs(vec![def(1, "stone", 0), def(2, "lamp", 15)]).unwrap()
+    }
+
+    fn with(voxels: &[((usize, usize, usize), Voxel)]) -> ChunkData {
+        let mut data = ChunkData::default();
+        for &(local, voxel) in voxels.iter() {
+            data.set_voxel(local, voxel);
+        }
+        data
+    }
+
+    fn block(light: &WorldLight, pos: (i32, i32, i32)) -> Option<u8> {
+        light.level(Channel::Block, pos)
+    }
+
+    fn sky(light: &WorldLight, pos: (i32, i32, i32)) -> Option<u8> {
+        light.level(Channel::Sky, pos)
+    }
+
+    #[test]
+    fn single_emitter() {
+        let registry = registry();
+        let mut light = WorldLight::new();
+        light.insert_chunk((1, 0, 0), &ChunkData::default(), &registry);
+        light.insert_chunk((0, 0, 0), &with(&[((12, 8, 8), LAMP)]), &registry);
+
+        assert_eq!(block(&light, (12, 8, 8)), Some(15));
+        assert_eq!(block(&light, (12, 8, 12)), Some(11));
+        assert_eq!(block(&light, (13, 9, 7)), Some(12));
+        assert_eq!(block(&light, (0, 8, 8)), Some(3));
+        // across the border into the chunk loaded first
+        assert_eq!(block(&light, (16, 8, 8)), Some(11));
+        assert_eq!(block(&light, (25, 8, 8)), Some(2));
+        assert_eq!(block(&light, (27, 8, 8)), Some(0));
+    }
+
+    #[test]
+    fn removed_emitter() {
+        let registry = registry();
+        let mut light = WorldLight::new();
+        light.insert_chunk((0, 0, 0), &with(&[((15, 8, 8), LAMP)]), &registry);
+        light.insert_chunk((1, 0, 0), &ChunkData::default(), &registry);
+        assert_eq!(block(&light, (16, 8, 8)), Some(14));
+
+        light.update_chunk((0, 0, 0), &ChunkData::default(), &registry);
+        assert_eq!(block(&light, (15, 8, 8)), Some(0));
+        assert_eq!(block(&light, (16, 8, 8)), Some(0));
+
+        // unloading the emitter's chunk takes its light out of the neighbour
+        light.update_chunk((0, 0, 0), &with(&[((15, 8, 8), LAMP)]), &registry);
+        assert_eq!(block(&light, (16, 8, 8)), Some(14));
+        light.remove_chunk((0, 0, 0));
+        assert_eq!(block(&light, (15, 8, 8)), None);
+        for z in 0..16 {
+            for y in 0..16 {
+                for x in 16..32 {
+                    assert_eq!(block(&light, (x, y, z)), Some(0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sunlight_blocked_at_border() {
+        let registry = registry();
+        let mut light = WorldLight::new();
+        light.insert_chunk((0, 0, 0), &ChunkData::default(), &registry);
+        assert_eq!(sky(&light, (4, 15, 4)), Some(15));
+        assert_eq!(sky(&light, (4, 0, 4)), Some(15));
+
+        // a roof loaded over the chunk takes its sunlight back
+        light.insert_chunk((0, 1, 0), &ChunkData::filled(STONE), &registry);
+        assert_eq!(sky(&light, (4, 15, 4)), Some(0));
+        assert_eq!(sky(&light, (4, 0, 4)), Some(0));
+
+        // and it stays dark with the roof unloaded, or reloaded under it
+        light.remove_chunk((0, 1, 0));
+        assert_eq!(sky(&light, (4, 15, 4)), Some(0));
+        light.remove_chunk((0, 0, 0));
+        light.insert_chunk((0, 0, 0), &ChunkData::default(), &registry);
+        assert_eq!(sky(&light, (4, 15, 4)), Some(0));
+
+        // until the roof is opened up
+        light.insert_chunk((0, 1, 0), &ChunkData::default(), &registry);
+        assert_eq!(sky(&light, (4, 15, 4)), Some(15));
+        assert_eq!(sky(&light, (4, 0, 4)), Some(15));
+    }
+
+    #[test]
+    fn sunlight_into_neighbour() {
+        let registry = registry();
+        let mut light = WorldLight::new();
+        // a covered chunk only lit from the side by its open neighbour
+        light.insert_chunk((0, 1, 0), &ChunkData::filled(STONE), &registry);
+        light.insert_chunk((0, 0, 0), &ChunkData::default(), &registry);
+        light.insert_chunk((1, 0, 0), &ChunkData::default(), &registry);
+        assert_eq!(sky(&light, (16, 8, 8)), Some(15));
+        assert_eq!(sky(&light, (15, 8, 8)), Some(14));
+        assert_eq!(sky(&light, (2, 8, 8)), Some(1));
+
+        light.remove_chunk((1, 0, 0));
+        assert_eq!(sky(&light, (15, 8, 8)), Some(0));
+        assert_eq!(sky(&light, (2, 8, 8)), Some(0));
+    }
+
+    #[test]
+    fn forgotten_sky_exits() {
+        let registry = registry();
+        let mut light = WorldLight::new();
+        light.insert_chunk((0, 1, 0), &ChunkData::filled(STONE), &registry);
+        light.insert_chunk((5, 1, 0), &ChunkData::filled(STONE), &registry);
+        light.remove_chunk((0, 1, 0));
+        light.remove_chunk((5, 1, 0));
+
+        light.retain_sky_exits(|index| index.0 < 5);
+        light.insert_chunk((0, 0, 0), &ChunkData::default(), &registry);
+        light.insert_chunk((5, 0, 0), &ChunkData::default(), &registry);
+        assert_eq!(sky(&light, (4, 15, 4)), Some(0));
+        assert_eq!(sky(&light, (84, 15, 4)), Some(15));
+    }
+}
@@ -0,0 +1,321 @@
+//! Level of detail for distant chunks.
+//!
+//! A chunk at level `n` is meshed from a copy downsampled `2^n` times along
+//! each axis, each cube of voxels becoming whichever block fills most of it,
+//! or air if it's mostly empty. Chunks next to a chunk at a different level
+//! keep the faces on their shared border, closing off both meshes so the
+//! mismatched surfaces don't leave gaps to see through.
+
+use super::{ChunkData, ChunkIndex, ChunkLoader, Side, Voxel, AIR, CHUNK_SIZE, SIDES, world_to_chunk};
+use super::block::BlockRegistry;
+use super::world_slice::VoxelSource;
+
+use amethyst::core::Transform;
+use specs::{
+    Component,
+    Entities,
+    FlaggedStorage,
+    HashMapStorage,
+    System,
+    ReadStorage,
+    WriteStorage,
+    Join,
+};
+
+/// The coarsest level: cubes of 8 voxels a side, two to a chunk.
+pub const MAX_LOD: u8 = 3;
+
+/// The level of detail a chunk is meshed at, from 0 (every voxel) to
+/// `MAX_LOD`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChunkLod(pub u8);
+
+impl ChunkLod {
+    /// Voxels along each side of one downsampled cell.
+    pub fn scale(self) -> usize {
+        1 << self.0
+    }
+}
+
+impl Component for ChunkLod {
+    // the flag tells the mesher to rebuild at the new level
+    type Storage = FlaggedStorage<Self, HashMapStorage<Self>>;
+}
+
+/// Picks each chunk's level of detail from its distance to the nearest
+/// `ChunkLoader`.
+pub struct ChunkLodSystem {
+    /// Horizontal distances in chunks at which levels 1, 2 and 3 start.
+    pub distances: [i32; MAX_LOD as usize],
+}
+
+impl Default for ChunkLodSystem {
+    fn default() -> Self {
+        ChunkLodSystem {
+            distances: [4, 7, 10],
+        }
+    }
+}
+
+impl ChunkLodSystem {
+    fn lod(&self, distance_squared: i32) -> u8 {
+        self.distances.iter()
+            .take_while(|&&d| distance_squared >= d * d)
+            .count() as u8
+    }
+}
+
+impl<'a> System<'a> for ChunkLodSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, ChunkLoader>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, ChunkIndex>,
+        WriteStorage<'a, ChunkLod>,
+    );
+
+    fn run(&mut self, (entities, loaders, transforms, chunk_indices, mut chunk_lods): Self::SystemData) {
+        let centres: Vec<(i32, i32, i32)> = (&loaders, &transforms).join()
+            .map(|(_, transform)| {
+                let m = transform.global_matrix();
+                let position = (m[(0, 3)].floor() as i32, m[(1, 3)].floor() as i32, m[(2, 3)].floor() as i32);
+                world_to_chunk(position).0
+            })
+            .collect();
+        if centres.is_empty() {
+            return;
+        }
+
+        for (entity, chunk_index) in (&*entities, &chunk_indices).join() {
+            let distance_squared = centres.iter()
+                .map(|centre| {
+                    let (dx, dz) = (chunk_index.x - centre.0, chunk_index.z - centre.2);
+                    dx * dx + dz * dz
+                })
+                .min()
+                .unwrap_or(0);
+            let lod = ChunkLod(self.lod(distance_squared));
+            // only touch chunks whose level changed, so the rest aren't remeshed
+            if chunk_lods.get(entity) != Some(&lod) {
+                let _ = chunk_lods.insert(entity, lod);
+            }
+        }
+    }
+}
+
+/// Which borders of a chunk at `lod` get skirts, in `SIDES` order: those
+/// against a loaded neighbour at another level. `neighbour` gives the level
+/// of the chunk on a side, or `None` if it isn't loaded.
+pub fn border_skirts<F>(lod: ChunkLod, mut neighbour: F) -> [bool; 6]
+where F: FnMut(Side) -> Option<ChunkLod>
+{
+    let mut skirts = [false; 6];
+    for (skirt, side) in skirts.iter_mut().zip(SIDES.iter()) {
+        *skirt = neighbour(*side).map(|other| other != lod).unwrap_or(false);
+    }
+    skirts
+}
+
+/// Cells of the neighbouring chunks a `LodSlice` keeps on each side.
+const MARGIN: i32 = 2;
+
+/// A downsampled view of the chunk at the centre of another `VoxelSource`,
+/// plus two cells of its neighbours all round, enough for smooth meshing.
+/// Coordinates are in cells.
+pub struct LodSlice<'s, S: 's> {
+    source: &'s S,
+    /// Cells along each side of the chunk.
+    size: i32,
+    /// Cells `-MARGIN..size + MARGIN` along each axis.
+    voxels: Vec<Option<Voxel>>,
+    lights: Vec<Option<u8>>,
+}
+
+impl<'s, S: VoxelSource> LodSlice<'s, S> {
+    pub fn new(source: &'s S, lod: ChunkLod, registry: &BlockRegistry) -> Self {
+        let scale = lod.scale() as i32;
+        let size = CHUNK_SIZE as i32 / scale;
+        let span = (size + MARGIN * 2) as usize;
+        let mut voxels = Vec::with_capacity(span * span * span);
+        let mut lights = Vec::with_capacity(span * span * span);
+        for z in -MARGIN..(size + MARGIN) {
+            for y in -MARGIN..(size + MARGIN) {
+                for x in -MARGIN..(size + MARGIN) {
+                    let (voxel, light) = downsample(source, registry, (x * scale, y * scale, z * scale), scale);
+                    voxels.push(voxel);
+                    lights.push(light);
+                }
+            }
+        }
+        LodSlice { source, size, voxels, lights }
+    }
+
+    /// Cells along each side of the chunk.
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    #[inline]
+    fn cell(&self, index: (i32, i32, i32)) -> Option<usize> {
+        let range = -MARGIN..(self.size + MARGIN);
+        if !range.contains(&index.0) || !range.contains(&index.1) || !range.contains(&index.2) {
+            return None;
+        }
+        let span = self.size + MARGIN * 2;
+        Some((((index.2 + MARGIN) * span + index.1 + MARGIN) * span + index.0 + MARGIN) as usize)
+    }
+}
+
+impl<'s, S: VoxelSource> VoxelSource for LodSlice<'s, S> {
+    /// Whole chunks are passed through; a uniform chunk downsamples to
+    /// itself.
+    #[inline]
+    fn chunk(&self, offset: (i32, i32, i32)) -> Option<&ChunkData> {
+        self.source.chunk(offset)
+    }
+
+    #[inline(always)]
+    fn get_voxel(&self, index: (i32, i32, i32)) -> Option<Voxel> {
+        self.cell(index).and_then(|i| self.voxels[i])
+    }
+
+    #[inline(always)]
+    fn get_light(&self, index: (i32, i32, i32)) -> Option<u8> {
+        self.cell(index).and_then(|i| self.lights[i])
+    }
+}
+
+/// The block standing in for a cube of voxels, and the brightest light in
+/// it. Ties go to the higher block, so grass wins over the dirt under it.
+fn downsample<S: VoxelSource>(
+    source: &S,
+    registry: &BlockRegistry,
+    corner: (i32, i32, i32),
+    scale: i32,
+) -> (Option<Voxel>, Option<u8>) {
+    let mut counts: Vec<(Voxel, u32)> = Vec::new();
+    let (mut known, mut air) = (0, 0);
+    let mut light: Option<u8> = None;
+    for y in (0..scale).rev() {
+        for z in 0..scale {
+            for x in 0..scale {
+                let index = (corner.0 + x, corner.1 + y, corner.2 + z);
+                let voxel = match source.get_voxel(index) {
+                    Some(voxel) => voxel,
+                    None => continue,
+                };
+                known += 1;
+                if !registry.is_opaque(voxel) {
+                    if let Some(l) = source.get_light(index) {
+                        let brighter = light.map(|best| (l >> 4).max(l & 0xf) > (best >> 4).max(best & 0xf)).unwrap_or(true);
+                        if brighter {
+                            light = Some(l);
+                        }
+                    }
+                }
+                if voxel == AIR {
+                    air += 1;
+                    continue;
+                }
+                match counts.iter_mut().find(|(v, _)| *v == voxel) {
+                    Some(entry) => entry.1 += 1,
+                    None => counts.push((voxel, 1)),
+                }
+            }
+        }
+    }
+    if known == 0 {
+        return (None, None);
+    }
+    if air * 2 > known {
+        return (Some(AIR), light);
+    }
+    let mut best: Option<(Voxel, u32)> = None;
+    for &(voxel, count) in counts.iter() {
+        if best.map(|(_, most)| count > most).unwrap_or(true) {
+            best = Some((voxel, count));
+        }
+    }
+    (best.map(|(voxel, _)| voxel), light)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{ChunkQuads, ChunkMesher, lod_quads_from_data};
+    use super::super::world_slice::ChunkMapSlice;
+
+    use fnv::FnvHashMap;
+    use std::path::Path;
+
+    fn registry() -> BlockRegistry {
+        BlockRegistry::load(Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/blocks.ron")).unwrap()
+    }
+
+    /// Downsamples the 2x2x2 cube at the origin holding `voxels`, in y, z, x
+    /// order from the top.
+    fn downsample_cube(registry: &BlockRegistry, voxels: [&str; 8]) -> Option<Voxel> {
+        let mut data = ChunkData::default();
+        for (i, name) in voxels.iter().enumerate() {
+            let voxel = if *name == "air" { AIR } else { registry.voxel(name).unwrap() };
+            data.set_voxel((i & 1, 1 - (i >> 2), (i >> 1) & 1), voxel);
+        }
+        let mut chunks = FnvHashMap::default();
+        chunks.insert((0, 0, 0), data);
+        downsample(&ChunkMapSlice::new(&chunks, (0, 0, 0)), registry, (0, 0, 0), 2).0
+    }
+
+    #[test]
+    fn downsample_majority() {
+        let registry = registry();
+        let voxel = |name| Some(registry.voxel(name).unwrap());
+
+        assert_eq!(downsample_cube(&registry, ["stone", "dirt", "stone", "dirt", "stone", "stone", "dirt", "stone"]), voxel("stone"));
+        // ties go to the block on top
+        assert_eq!(downsample_cube(&registry, ["grass", "grass", "grass", "grass", "dirt", "dirt", "dirt", "dirt"]), voxel("grass"));
+        // half air still leaves a block, but mostly air is air
+        assert_eq!(downsample_cube(&registry, ["air", "air", "air", "air", "stone", "stone", "stone", "stone"]), voxel("stone"));
+        assert_eq!(downsample_cube(&registry, ["air", "air", "air", "air", "air", "stone", "dirt", "stone"]), Some(AIR));
+    }
+
+    #[test]
+    fn skirts_where_levels_differ() {
+        let mut levels = FnvHashMap::default();
+        levels.insert(Side::East, ChunkLod(1));
+        levels.insert(Side::West, ChunkLod(2));
+        levels.insert(Side::Top, ChunkLod(0));
+        let skirts = border_skirts(ChunkLod(1), |side| levels.get(&side).cloned());
+        for (side, skirt) in SIDES.iter().zip(skirts.iter()) {
+            // unloaded neighbours get none, as there's nothing to see through to
+            let expected = *side == Side::West || *side == Side::Top;
+            assert_eq!(*skirt, expected, "{:?}", side);
+        }
+
+        // a skirt keeps the border faces the neighbour would hide
+        let registry = registry();
+        let stone = registry.voxel("stone").unwrap();
+        let mut chunks = FnvHashMap::default();
+        let mut ground = ChunkData::default();
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE / 2 {
+                for z in 0..CHUNK_SIZE {
+                    ground.set_voxel((x, y, z), stone);
+                }
+            }
+        }
+        for x in -1..2 {
+            for z in -1..2 {
+                chunks.insert((x, 0, z), ground.clone());
+            }
+        }
+        let mut mesh = ChunkQuads::default();
+        let slice = ChunkMapSlice::new(&chunks, (0, 0, 0));
+        lod_quads_from_data(&slice, &registry, &mut mesh, ChunkLod(1), skirts, ChunkMesher::Blocky);
+        let has = |side| mesh.quads().iter().any(|quad| quad.side == side);
+        assert!(has(Side::West));
+        assert!(has(Side::Top));
+        assert!(!has(Side::East));
+        assert!(!has(Side::North));
+        assert!(!has(Side::South));
+    }
+}
@@ -1,6 +1,19 @@
 pub mod chunk;
 pub mod bundle;
 pub mod world_slice;
+pub mod raycast;
+pub mod block;
+pub mod atlas;
+pub mod region;
+pub mod store;
+pub mod streaming;
+pub mod terrain;
+pub mod vox;
+pub mod nbt;
+pub mod schematic;
+pub mod export;
+pub mod light;
+pub mod lod;
 
 pub use self::chunk::{
     ChunkIndex,
@@ -9,19 +22,45 @@ pub use self::chunk::{
     Face,
     Side,
     Axis,
+    SIDES,
 };
 pub use self::chunk::data::{
     ChunkData,
     Voxel,
+    VoxelFace,
+    AIR,
     CHUNK_SIZE,
 };
 pub use self::chunk::mesh::{
+    ChunkQuad,
     ChunkQuads,
+    AtlasTile,
+    ChunkMeshBuffers,
+    ChunkVertex,
+    ChunkMesher,
+    TranslucentChunkMesh,
     MeshFaceSystem,
+    quads_from_data,
+    lod_quads_from_data,
 };
 pub use self::chunk::material::ChunkMaterialSystem;
+pub use self::chunk::pass::{ChunkPassDef, RenderChunks};
 pub use self::bundle::VoxelBundle;
 pub use self::world_slice::*;
+pub use self::raycast::{raycast, RaycastHit};
+pub use self::block::{BlockRegistry, BlockDef, BlockTextures, BlockRegistryError, Transparency};
+pub use self::atlas::TextureAtlas;
+pub use self::region::RegionError;
+pub use self::store::{ChunkStore, ChunkDirtySystem, ChunkSaveSystem};
+pub use self::streaming::{ChunkGenerator, ChunkLoader, ChunkStreamingSystem};
+pub use self::terrain::{TerrainGenerator, TerrainConfig, TerrainError, Biome};
+pub use self::vox::{VoxFile, VoxPaletteMap, VoxError};
+pub use self::schematic::{Schematic, SchematicMapping, SchematicError};
+pub use self::export::MeshExport;
+pub use self::light::{ChunkLight, LightSystem, WorldLight, MAX_LIGHT};
+pub use self::lod::{ChunkLod, ChunkLodSystem, LodSlice, MAX_LOD};
+
+use std::ops::{Deref, DerefMut};
 
 use fnv::FnvHashMap;
 
@@ -31,6 +70,8 @@ use specs::{
     world::EntitiesRes,
     System,
     ReadStorage,
+    Storage,
+    storage::MaskedStorage,
     Join,
 };
 use shred::{FetchMut, WriteExpect};
@@ -51,11 +92,45 @@ impl VoxelWorld {
         self.index_entity.insert(index, entity);
     }
 
+    fn remove(&mut self, index: (i32, i32, i32)) {
+        self.index_entity.remove(&index);
+    }
+
     #[allow(unused)]
     pub fn get_entity(&self, index: (i32, i32, i32)) -> Option<Entity> {
         self.index_entity.get(&index).map(|e| *e)
     }
 
+    /// Reads the voxel at the given world voxel coordinate.
+    ///
+    /// Returns `None` if the chunk containing the voxel is not loaded.
+    pub fn get_voxel<'e, T>(&self, chunk_datas: &Storage<'e, ChunkData, T>, pos: (i32, i32, i32)) -> Option<Voxel>
+    where T: Deref<Target=MaskedStorage<ChunkData>>
+    {
+        let (chunk, local) = world_to_chunk(pos);
+        self.get_entity(chunk)
+            .and_then(|entity| chunk_datas.get(entity))
+            .map(|chunk_data| chunk_data.get_voxel(local))
+    }
+
+    /// Writes the voxel at the given world voxel coordinate, returning the
+    /// voxel that was there before.
+    ///
+    /// The chunk's `ChunkData` is only flagged as modified if the voxel
+    /// actually changes. Returns `None` if the chunk is not loaded.
+    pub fn set_voxel<'e, T>(&self, chunk_datas: &mut Storage<'e, ChunkData, T>, pos: (i32, i32, i32), voxel: Voxel) -> Option<Voxel>
+    where T: DerefMut<Target=MaskedStorage<ChunkData>>
+    {
+        let (chunk, local) = world_to_chunk(pos);
+        let entity = self.get_entity(chunk)?;
+        let previous = chunk_datas.get(entity)?.get_voxel(local);
+        if previous != voxel {
+            // get_mut is what emits the Modified event for MeshFaceSystem
+            chunk_datas.get_mut(entity)?.set_voxel(local, voxel);
+        }
+        Some(previous)
+    }
+
     fn clear_dead(&mut self, entities: &EntitiesRes) {
         self.index_entity.retain(|_, e| {
             entities.is_alive(*e)
@@ -63,6 +138,35 @@ impl VoxelWorld {
     }
 }
 
+/// Splits a world voxel coordinate into the index of the chunk containing it
+/// and the local index of the voxel inside that chunk.
+///
+/// Rounds towards negative infinity, so `-1` lands in chunk `-1` at local `15`.
+#[inline]
+pub fn world_to_chunk(pos: (i32, i32, i32)) -> ((i32, i32, i32), (usize, usize, usize)) {
+    let (cx, lx) = split_axis(pos.0);
+    let (cy, ly) = split_axis(pos.1);
+    let (cz, lz) = split_axis(pos.2);
+    ((cx, cy, cz), (lx, ly, lz))
+}
+
+/// The world voxel coordinate of a local index inside the given chunk.
+#[inline]
+pub fn chunk_to_world(chunk: (i32, i32, i32), local: (usize, usize, usize)) -> (i32, i32, i32) {
+    (
+        chunk.0 * CHUNK_SIZE as i32 + local.0 as i32,
+        chunk.1 * CHUNK_SIZE as i32 + local.1 as i32,
+        chunk.2 * CHUNK_SIZE as i32 + local.2 as i32,
+    )
+}
+
+#[inline(always)]
+fn split_axis(v: i32) -> (i32, usize) {
+    let size = CHUNK_SIZE as i32;
+    let local = ((v % size) + size) % size;
+    ((v - local) / size, local as usize)
+}
+
 /// Updates VoxelWorld's data to represent the current scene
 pub struct Bookkeeper;
 
@@ -0,0 +1,368 @@
+//! A reader for Minecraft's NBT format: a tree of named, typed values,
+//! big-endian, usually gzipped.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+
+use fnv::FnvHashMap;
+use flate2::read::GzDecoder;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Nbt {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<Nbt>),
+    Compound(FnvHashMap<String, Nbt>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+#[derive(Debug)]
+pub enum NbtError {
+    Io(io::Error),
+    UnknownTag(u8),
+    /// The root isn't a compound.
+    BadRoot,
+    /// Compounds or lists nested deeper than anything real uses.
+    TooDeep,
+}
+
+impl fmt::Display for NbtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NbtError::Io(ref e) => write!(f, "nbt i/o error: {}", e),
+            NbtError::UnknownTag(tag) => write!(f, "unknown nbt tag {}", tag),
+            NbtError::BadRoot => write!(f, "nbt root is not a compound"),
+            NbtError::TooDeep => write!(f, "nbt nested too deeply"),
+        }
+    }
+}
+
+impl Error for NbtError {}
+
+impl From<io::Error> for NbtError {
+    fn from(e: io::Error) -> NbtError {
+        NbtError::Io(e)
+    }
+}
+
+const MAX_DEPTH: usize = 512;
+
+impl Nbt {
+    /// Reads a whole NBT file, gzipped or not, returning the root compound's
+    /// name and value.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<(String, Nbt), NbtError> {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        if contents.starts_with(&[0x1f, 0x8b]) {
+            let mut inflated = Vec::new();
+            GzDecoder::new(&contents[..]).read_to_end(&mut inflated)?;
+            contents = inflated;
+        }
+
+        let mut reader = &contents[..];
+        if read_u8(&mut reader)? != 10 {
+            return Err(NbtError::BadRoot);
+        }
+        let name = read_string(&mut reader)?;
+        let root = read_payload(&mut reader, 10, 0)?;
+        Ok((name, root))
+    }
+
+    /// A child of a compound.
+    pub fn get(&self, name: &str) -> Option<&Nbt> {
+        match *self {
+            Nbt::Compound(ref children) => children.get(name),
+            _ => None,
+        }
+    }
+
+    pub fn as_compound(&self) -> Option<&FnvHashMap<String, Nbt>> {
+        match *self {
+            Nbt::Compound(ref children) => Some(children),
+            _ => None,
+        }
+    }
+
+    /// Any integer tag, widened.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Nbt::Byte(v) => Some(v as i64),
+            Nbt::Short(v) => Some(v as i64),
+            Nbt::Int(v) => Some(v as i64),
+            Nbt::Long(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[i8]> {
+        match *self {
+            Nbt::ByteArray(ref bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_ints(&self) -> Option<&[i32]> {
+        match *self {
+            Nbt::IntArray(ref ints) => Some(ints),
+            _ => None,
+        }
+    }
+}
+
+fn read_payload(reader: &mut &[u8], tag: u8, depth: usize) -> Result<Nbt, NbtError> {
+    if depth > MAX_DEPTH {
+        return Err(NbtError::TooDeep);
+    }
+    Ok(match tag {
+        1 => Nbt::Byte(read_u8(reader)? as i8),
+        2 => Nbt::Short(read_array::<[u8; 2]>(reader).map(i16::from_be_bytes)?),
+        3 => Nbt::Int(read_i32(reader)?),
+        4 => Nbt::Long(read_array::<[u8; 8]>(reader).map(i64::from_be_bytes)?),
+        5 => Nbt::Float(f32::from_bits(read_i32(reader)? as u32)),
+        6 => Nbt::Double(f64::from_bits(read_array::<[u8; 8]>(reader).map(u64::from_be_bytes)?)),
+        7 => {
+            let len = read_len(reader)?;
+            let bytes = take(reader, len)?;
+            Nbt::ByteArray(bytes.iter().map(|&b| b as i8).collect())
+        },
+        8 => Nbt::String(read_string(reader)?),
+        9 => {
+            let element = read_u8(reader)?;
+            let len = read_len(reader)?;
+            let mut list = Vec::with_capacity(len.min(reader.len()));
+            for _ in 0..len {
+                list.push(read_payload(reader, element, depth + 1)?);
+            }
+            Nbt::List(list)
+        },
+        10 => {
+            let mut children = FnvHashMap::default();
+            loop {
+                let child = read_u8(reader)?;
+                if child == 0 {
+                    break;
+                }
+                let name = read_string(reader)?;
+                children.insert(name, read_payload(reader, child, depth + 1)?);
+            }
+            Nbt::Compound(children)
+        },
+        11 => {
+            let len = read_len(reader)?;
+            let bytes = take(reader, len * 4)?;
+            Nbt::IntArray(bytes.chunks(4).map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]])).collect())
+        },
+        12 => {
+            let len = read_len(reader)?;
+            let bytes = take(reader, len * 8)?;
+            Nbt::LongArray(bytes.chunks(8).map(|b| {
+                i64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])
+            }).collect())
+        },
+        tag => return Err(NbtError::UnknownTag(tag)),
+    })
+}
+
+fn take<'a>(reader: &mut &'a [u8], n: usize) -> Result<&'a [u8], NbtError> {
+    if reader.len() < n {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "nbt ends early").into());
+    }
+    let (taken, rest) = reader.split_at(n);
+    *reader = rest;
+    Ok(taken)
+}
+
+fn read_array<A: Default + AsMut<[u8]>>(reader: &mut &[u8]) -> Result<A, NbtError> {
+    let mut array = A::default();
+    reader.read_exact(array.as_mut())?;
+    Ok(array)
+}
+
+fn read_u8(reader: &mut &[u8]) -> Result<u8, NbtError> {
+    Ok(read_array::<[u8; 1]>(reader)?[0])
+}
+
+fn read_i32(reader: &mut &[u8]) -> Result<i32, NbtError> {
+    read_array::<[u8; 4]>(reader).map(i32::from_be_bytes)
+}
+
+fn read_len(reader: &mut &[u8]) -> Result<usize, NbtError> {
+    Ok(read_i32(reader)?.max(0) as usize)
+}
+
+/// Strings are a u16 length and (Java's modified) UTF-8; the differences
+/// only matter for characters block names don't use.
+fn read_string(reader: &mut &[u8]) -> Result<String, NbtError> {
+    let len = read_array::<[u8; 2]>(reader).map(u16::from_be_bytes)? as usize;
+    Ok(String::from_utf8_lossy(take(reader, len)?).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    fn tag(nbt: &Nbt) -> u8 {
+        match *nbt {
+            Nbt::Byte(_) => 1,
+            Nbt::Short(_) => 2,
+            Nbt::Int(_) => 3,
+            Nbt::Long(_) => 4,
+            Nbt::Float(_) => 5,
+            Nbt::Double(_) => 6,
+            Nbt::ByteArray(_) => 7,
+            Nbt::String(_) => 8,
+            Nbt::List(_) => 9,
+            Nbt::Compound(_) => 10,
+            Nbt::IntArray(_) => 11,
+            Nbt::LongArray(_) => 12,
+        }
+    }
+
+    fn write_string(out: &mut Vec<u8>, s: &str) {
+        out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+        out.extend_from_slice(s.as_bytes());
+    }
+
+    fn write_payload(out: &mut Vec<u8>, nbt: &Nbt) {
+        match *nbt {
+            Nbt::Byte(v) => out.push(v as u8),
+            Nbt::Short(v) => out.extend_from_slice(&v.to_be_bytes()),
+            Nbt::Int(v) => out.extend_from_slice(&v.to_be_bytes()),
+            Nbt::Long(v) => out.extend_from_slice(&v.to_be_bytes()),
+            Nbt::Float(v) => out.extend_from_slice(&v.to_bits().to_be_bytes()),
+            Nbt::Double(v) => out.extend_from_slice(&v.to_bits().to_be_bytes()),
+            Nbt::ByteArray(ref bytes) => {
+                out.extend_from_slice(&(bytes.len() as i32).to_be_bytes());
+                out.extend(bytes.iter().map(|&b| b as u8));
+            },
+            Nbt::String(ref s) => write_string(out, s),
+            Nbt::List(ref list) => {
+                out.push(list.first().map(tag).unwrap_or(0));
+                out.extend_from_slice(&(list.len() as i32).to_be_bytes());
+                for element in list.iter() {
+                    write_payload(out, element);
+                }
+            },
+            Nbt::Compound(ref children) => {
+                for (name, child) in children.iter() {
+                    out.push(tag(child));
+                    write_string(out, name);
+                    write_payload(out, child);
+                }
+                out.push(0);
+            },
+            Nbt::IntArray(ref ints) => {
+                out.extend_from_slice(&(ints.len() as i32).to_be_bytes());
+                for v in ints.iter() {
+                    out.extend_from_slice(&v.to_be_bytes());
+                }
+            },
+            Nbt::LongArray(ref longs) => {
+                out.extend_from_slice(&(longs.len() as i32).to_be_bytes());
+                for v in longs.iter() {
+                    out.extend_from_slice(&v.to_be_bytes());
+                }
+            },
+        }
+    }
+
+    /// A whole uncompressed file with the given root.
+    fn write(name: &str, root: &Nbt) -> Vec<u8> {
+        let mut out = vec![10];
+        write_string(&mut out, name);
+        write_payload(&mut out, root);
+        out
+    }
+
+    fn compound(children: Vec<(&str, Nbt)>) -> Nbt {
+        Nbt::Compound(children.into_iter().map(|(name, child)| (name.to_string(), child)).collect())
+    }
+
+    fn sample() -> Nbt {
+        compound(vec![
+            ("byte", Nbt::Byte(-3)),
+            ("short", Nbt::Short(-300)),
+            ("int", Nbt::Int(70000)),
+            ("long", Nbt::Long(-1 << 40)),
+            ("float", Nbt::Float(1.5)),
+            ("double", Nbt::Double(-0.25)),
+            ("bytes", Nbt::ByteArray(vec![-1, 0, 127])),
+            ("string", Nbt::String("minecraft:oak_log[axis=y]".to_string())),
+            ("ints", Nbt::IntArray(vec![1, -2, 3])),
+            ("longs", Nbt::LongArray(vec![i64::max_value(), i64::min_value()])),
+            ("empty", Nbt::List(Vec::new())),
+            ("lists", Nbt::List(vec![
+                Nbt::List(vec![Nbt::Short(1), Nbt::Short(2)]),
+                Nbt::List(vec![Nbt::String("a".to_string())]),
+            ])),
+            ("compounds", Nbt::List(vec![
+                compound(vec![("x", Nbt::Int(1)), ("inner", compound(vec![("y", Nbt::Byte(2))]))]),
+                compound(Vec::new()),
+            ])),
+        ])
+    }
+
+    #[test]
+    fn round_trip_raw() {
+        let root = sample();
+        let (name, read) = Nbt::read_from(&mut &write("Schematic", &root)[..]).unwrap();
+        assert_eq!(name, "Schematic");
+        assert_eq!(read, root);
+        assert_eq!(read.get("compounds").and_then(|c| match *c {
+            Nbt::List(ref list) => list[0].get("inner").and_then(|i| i.get("y")).and_then(|y| y.as_i64()),
+            _ => None,
+        }), Some(2));
+    }
+
+    #[test]
+    fn round_trip_gzip() {
+        let root = sample();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&write("", &root)).unwrap();
+        let bytes = encoder.finish().unwrap();
+        assert_eq!(Nbt::read_from(&mut &bytes[..]).unwrap(), (String::new(), root));
+    }
+
+    /// `depth` lists, one inside the other, in a root compound.
+    fn nested(depth: usize) -> Nbt {
+        let mut nbt = Nbt::List(Vec::new());
+        for _ in 1..depth {
+            nbt = Nbt::List(vec![nbt]);
+        }
+        compound(vec![("nested", nbt)])
+    }
+
+    #[test]
+    fn depth_limit() {
+        let root = nested(MAX_DEPTH);
+        assert_eq!(Nbt::read_from(&mut &write("", &root)[..]).unwrap().1, root);
+        match Nbt::read_from(&mut &write("", &nested(MAX_DEPTH + 1))[..]) {
+            Err(NbtError::TooDeep) => {},
+            other => panic!("expected TooDeep, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_roots() {
+        match Nbt::read_from(&mut &[8, 0, 0, 0, 0][..]) {
+            Err(NbtError::BadRoot) => {},
+            other => panic!("expected BadRoot, got {:?}", other.map(|_| ())),
+        }
+        match Nbt::read_from(&mut &[10, 0, 0, 13, 0, 0][..]) {
+            Err(NbtError::UnknownTag(13)) => {},
+            other => panic!("expected an unknown tag, got {:?}", other.map(|_| ())),
+        }
+    }
+}
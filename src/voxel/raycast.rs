@@ -0,0 +1,217 @@
+//! Ray traversal over the voxel grid, after Amanatides & Woo,
+//! "A Fast Voxel Traversal Algorithm for Ray Tracing" (1987).
+
+use super::{
+    ChunkData,
+    VoxelWorld,
+    Voxel,
+    Side,
+    AIR,
+};
+use super::block::BlockRegistry;
+
+use std::f32;
+use std::ops::Deref;
+
+use specs::{
+    Storage,
+    storage::MaskedStorage,
+};
+use cgmath::Vector3;
+
+/// The first solid voxel along a ray.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RaycastHit {
+    pub voxel: Voxel,
+    /// World voxel coordinate of the hit voxel.
+    pub position: (i32, i32, i32),
+    /// The side of the voxel the ray entered through, or `None` if the ray
+    /// started inside it.
+    pub side: Option<Side>,
+    /// Distance along the ray to the entry point.
+    pub distance: f32,
+}
+
+/// Walks the voxels along a ray until a solid voxel is found or
+/// `max_distance` is exceeded.
+///
+/// `lookup` resolves a voxel coordinate to its voxel, returning `None` for
+/// unloaded space, which the ray passes through like air. This keeps the
+/// traversal independent of where the voxels live: a `VoxelWorld`, a
+/// `VoxelSource` or a lone `ChunkData` via `try_get_voxel` all work.
+///
+/// Rays with a zero direction or any non-finite input, such as an infinite
+/// `max_distance`, would never finish and hit nothing.
+#[allow(unused)]
+pub fn raycast<F>(origin: Vector3<f32>, direction: Vector3<f32>, max_distance: f32, mut lookup: F) -> Option<RaycastHit>
+where F: FnMut((i32, i32, i32)) -> Option<Voxel>
+{
+    let length = (direction.x * direction.x + direction.y * direction.y + direction.z * direction.z).sqrt();
+    let finite = max_distance.is_finite() && length.is_finite()
+        && origin.x.is_finite() && origin.y.is_finite() && origin.z.is_finite();
+    if length == 0. || !finite {
+        return None;
+    }
+    let dir = [direction.x / length, direction.y / length, direction.z / length];
+    let start = [origin.x, origin.y, origin.z];
+
+    let mut position = [start[0].floor() as i32, start[1].floor() as i32, start[2].floor() as i32];
+    let mut step = [0i32; 3];
+    let mut t_max = [f32::INFINITY; 3];
+    let mut t_delta = [f32::INFINITY; 3];
+
+    for axis in 0..3 {
+        if dir[axis] > 0. {
+            step[axis] = 1;
+            t_delta[axis] = 1. / dir[axis];
+            t_max[axis] = (position[axis] as f32 + 1. - start[axis]) / dir[axis];
+        } else if dir[axis] < 0. {
+            step[axis] = -1;
+            t_delta[axis] = -1. / dir[axis];
+            t_max[axis] = (position[axis] as f32 - start[axis]) / dir[axis];
+        }
+    }
+
+    let mut side = None;
+    let mut distance = 0.;
+    loop {
+        let index = (position[0], position[1], position[2]);
+        match lookup(index) {
+            Some(voxel) if voxel != AIR => {
+                return Some(RaycastHit {
+                    voxel,
+                    position: index,
+                    side,
+                    distance,
+                });
+            },
+            _ => (),
+        }
+
+        // step across whichever voxel boundary is closest
+        let axis = if t_max[0] < t_max[1] && t_max[0] < t_max[2] {
+            0
+        } else if t_max[1] < t_max[2] {
+            1
+        } else {
+            2
+        };
+        distance = t_max[axis];
+        if distance > max_distance {
+            return None;
+        }
+        position[axis] += step[axis];
+        t_max[axis] += t_delta[axis];
+
+        // moving in the positive direction enters through the negative side
+        side = Some(match (axis, step[axis] > 0) {
+            (0, true) => Side::West,
+            (0, false) => Side::East,
+            (1, true) => Side::Bottom,
+            (1, false) => Side::Top,
+            (_, true) => Side::South,
+            (_, false) => Side::North,
+        });
+    }
+}
+
+impl VoxelWorld {
+    /// Casts a ray in world voxel coordinates against the solid blocks of
+    /// the loaded chunks.
+    #[allow(unused)]
+    pub fn raycast<'e, T>(&self, chunk_datas: &Storage<'e, ChunkData, T>, registry: &BlockRegistry, origin: Vector3<f32>, direction: Vector3<f32>, max_distance: f32) -> Option<RaycastHit>
+    where T: Deref<Target=MaskedStorage<ChunkData>>
+    {
+        raycast(origin, direction, max_distance, |index| {
+            self.get_voxel(chunk_datas, index).filter(|voxel| registry.is_solid(*voxel))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::world_to_chunk;
+
+    use fnv::FnvHashMap;
+
+    const STONE: Voxel = Voxel { id: 1, state: 0 };
+
+    fn chunk_with(voxels: &[(usize, usize, usize)]) -> ChunkData {
+        let mut chunk = ChunkData::default();
+        for &voxel in voxels.iter() {
+            chunk.set_voxel(voxel, STONE);
+        }
+        chunk
+    }
+
+    fn hit(position: (i32, i32, i32), side: Option<Side>, distance: f32) -> Option<RaycastHit> {
+        Some(RaycastHit { voxel: STONE, position, side, distance })
+    }
+
+    #[test]
+    fn axis_aligned() {
+        let chunk = chunk_with(&[(8, 3, 3), (3, 0, 3)]);
+        let lookup = |index| chunk.try_get_voxel(index);
+        assert_eq!(raycast(Vector3::new(0.5, 3.5, 3.5), Vector3::new(1., 0., 0.), 64., lookup), hit((8, 3, 3), Some(Side::West), 7.5));
+        assert_eq!(raycast(Vector3::new(15.5, 3.5, 3.5), Vector3::new(-2., 0., 0.), 64., lookup), hit((8, 3, 3), Some(Side::East), 6.5));
+        assert_eq!(raycast(Vector3::new(3.5, 10.5, 3.5), Vector3::new(0., -1., 0.), 64., lookup), hit((3, 0, 3), Some(Side::Top), 9.5));
+        assert_eq!(raycast(Vector3::new(0.5, 3.5, 3.5), Vector3::new(0., 0., 1.), 64., lookup), None);
+    }
+
+    #[test]
+    fn diagonal() {
+        let chunk = chunk_with(&[(5, 5, 0), (1, 1, 4)]);
+        let lookup = |index| chunk.try_get_voxel(index);
+        let found = raycast(Vector3::new(0.5, 0.5, 0.5), Vector3::new(1., 1., 0.), 64., lookup).unwrap();
+        assert_eq!((found.position, found.side), ((5, 5, 0), Some(Side::West)));
+        assert!((found.distance - 4.5 * 2f32.sqrt()).abs() < 1e-4);
+
+        // through a corner, ties step along y first and then across x
+        let found = raycast(Vector3::new(3.5, 3.5, 4.5), Vector3::new(-1., -1., 0.), 64., lookup).unwrap();
+        assert_eq!((found.position, found.side), ((1, 1, 4), Some(Side::East)));
+        assert!((found.distance - 1.5 * 2f32.sqrt()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn starts_inside() {
+        let chunk = chunk_with(&[(2, 2, 2)]);
+        let lookup = |index| chunk.try_get_voxel(index);
+        assert_eq!(raycast(Vector3::new(2.25, 2.75, 2.5), Vector3::new(0., 1., 1.), 64., lookup), hit((2, 2, 2), None, 0.));
+    }
+
+    #[test]
+    fn crosses_chunks() {
+        let mut chunks = FnvHashMap::default();
+        chunks.insert((0, 0, 0), ChunkData::default());
+        chunks.insert((1, 0, 0), chunk_with(&[(4, 3, 3)]));
+        let lookup = |index| {
+            let (chunk, local) = world_to_chunk(index);
+            chunks.get(&chunk).map(|chunk| chunk.get_voxel(local))
+        };
+        assert_eq!(raycast(Vector3::new(0.5, 3.5, 3.5), Vector3::new(1., 0., 0.), 64., lookup), hit((20, 3, 3), Some(Side::West), 19.5));
+        // out of the loaded chunks, where nothing is hit
+        assert_eq!(raycast(Vector3::new(0.5, 3.5, 3.5), Vector3::new(-1., 0., 0.), 64., lookup), None);
+    }
+
+    #[test]
+    fn max_distance() {
+        let chunk = chunk_with(&[(8, 3, 3)]);
+        let lookup = |index| chunk.try_get_voxel(index);
+        assert_eq!(raycast(Vector3::new(0.5, 3.5, 3.5), Vector3::new(1., 0., 0.), 7.4, lookup), None);
+        assert_eq!(raycast(Vector3::new(0.5, 3.5, 3.5), Vector3::new(1., 0., 0.), 7.5, lookup), hit((8, 3, 3), Some(Side::West), 7.5));
+        assert_eq!(raycast(Vector3::new(0.5, 3.5, 3.5), Vector3::new(0., 0., 0.), 64., lookup), None);
+    }
+
+    #[test]
+    fn non_finite() {
+        let chunk = chunk_with(&[(8, 3, 3)]);
+        let lookup = |index| chunk.try_get_voxel(index);
+        let (origin, direction) = (Vector3::new(0.5, 3.5, 3.5), Vector3::new(1., 0., 0.));
+        assert_eq!(raycast(origin, direction, f32::INFINITY, lookup), None);
+        assert_eq!(raycast(origin, direction, f32::NAN, lookup), None);
+        assert_eq!(raycast(origin, Vector3::new(f32::NAN, 0., 0.), 64., lookup), None);
+        assert_eq!(raycast(origin, Vector3::new(f32::INFINITY, 0., 0.), 64., lookup), None);
+        assert_eq!(raycast(Vector3::new(f32::NEG_INFINITY, 3.5, 3.5), direction, 64., lookup), None);
+    }
+}
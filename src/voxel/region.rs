@@ -0,0 +1,398 @@
+//! Region files group a cube of `REGION_SIZE`³ chunks into one file.
+//!
+//! Layout, all integers little-endian:
+//!
+//! ```text
+//! magic     b"VXRG"
+//! version   u32
+//! table     REGION_VOLUME x (offset: u32, length: u32, crc32: u32)
+//! payloads  ...
+//! ```
+//!
+//! A slot with zero length holds no chunk. A payload is the chunk's voxels
+//! in x, then y, then z order, run-length encoded as
+//! `(run: u16, id: u16, state: u16)`. Version 1 files stored `(run: u16, id: u8)`
+//! and are upgraded when read. A version 1 region with a chunk that can't be
+//! decoded is left as it is rather than upgraded without that chunk.
+
+use super::{ChunkData, Voxel, CHUNK_SIZE};
+use super::chunk::data::CHUNK_VOLUME;
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write, Seek, SeekFrom};
+
+pub const REGION_MAGIC: &'static [u8; 4] = b"VXRG";
+pub const REGION_VERSION: u32 = 2;
+
+/// Chunks per region along each axis.
+pub const REGION_SIZE: i32 = 8;
+pub const REGION_VOLUME: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+
+const SLOT_BYTES: usize = 12;
+const HEADER_BYTES: usize = 8 + REGION_VOLUME * SLOT_BYTES;
+
+#[derive(Debug)]
+pub enum RegionError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u32),
+    /// The stored checksum does not match the chunk's payload.
+    ChecksumMismatch((i32, i32, i32)),
+    /// The payload passed its checksum but does not decode to a chunk.
+    Corrupt((i32, i32, i32)),
+}
+
+impl fmt::Display for RegionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RegionError::Io(ref e) => write!(f, "region i/o error: {}", e),
+            RegionError::BadMagic => write!(f, "not a region file"),
+            RegionError::UnsupportedVersion(v) => write!(f, "unsupported region version {}", v),
+            RegionError::ChecksumMismatch(i) => write!(f, "checksum mismatch for chunk {:?}", i),
+            RegionError::Corrupt(i) => write!(f, "corrupt payload for chunk {:?}", i),
+        }
+    }
+}
+
+impl Error for RegionError {}
+
+impl From<io::Error> for RegionError {
+    fn from(e: io::Error) -> RegionError {
+        RegionError::Io(e)
+    }
+}
+
+/// The region containing the given chunk.
+pub fn region_of(chunk: (i32, i32, i32)) -> (i32, i32, i32) {
+    (
+        div_floor(chunk.0, REGION_SIZE),
+        div_floor(chunk.1, REGION_SIZE),
+        div_floor(chunk.2, REGION_SIZE),
+    )
+}
+
+#[inline]
+fn div_floor(v: i32, d: i32) -> i32 {
+    let r = ((v % d) + d) % d;
+    (v - r) / d
+}
+
+/// The chunk in the given slot of a region.
+#[inline]
+fn chunk_of(region: (i32, i32, i32), slot: usize) -> (i32, i32, i32) {
+    let size = REGION_SIZE as usize;
+    (
+        region.0 * REGION_SIZE + (slot % size) as i32,
+        region.1 * REGION_SIZE + ((slot / size) % size) as i32,
+        region.2 * REGION_SIZE + (slot / (size * size)) as i32,
+    )
+}
+
+#[inline]
+fn slot_of(chunk: (i32, i32, i32)) -> usize {
+    let (rx, ry, rz) = region_of(chunk);
+    let x = (chunk.0 - rx * REGION_SIZE) as usize;
+    let y = (chunk.1 - ry * REGION_SIZE) as usize;
+    let z = (chunk.2 - rz * REGION_SIZE) as usize;
+    (z * REGION_SIZE as usize + y) * REGION_SIZE as usize + x
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Slot {
+    offset: u32,
+    length: u32,
+    checksum: u32,
+}
+
+/// An in-memory region: the raw payload of each slot.
+#[derive(Clone, Debug)]
+pub struct RegionFile {
+    payloads: Vec<Option<(Vec<u8>, u32)>>,
+}
+
+impl RegionFile {
+    pub fn new() -> Self {
+        RegionFile {
+            payloads: vec![None; REGION_VOLUME],
+        }
+    }
+
+    /// Reads the whole of the given region. Payloads are kept as-is;
+    /// checksums are only verified when a chunk is decoded, so one bad chunk
+    /// does not make the rest of the region unreadable.
+    ///
+    /// Older regions are decoded and re-encoded in the current format, so
+    /// there a bad chunk is an error, as writing the region back would lose
+    /// it.
+    pub fn read_from<R: Read + Seek>(reader: &mut R, region_index: (i32, i32, i32)) -> Result<RegionFile, RegionError> {
+        let (version, slots) = read_header(reader)?;
+        let mut region = RegionFile::new();
+        for (i, slot) in slots.iter().enumerate() {
+            if slot.length == 0 {
+                continue;
+            }
+            reader.seek(SeekFrom::Start(slot.offset as u64))?;
+            let mut payload = vec![0u8; slot.length as usize];
+            reader.read_exact(&mut payload)?;
+
+            if version == REGION_VERSION {
+                region.payloads[i] = Some((payload, slot.checksum));
+            } else {
+                // re-encode older payloads so the region is written back in the current format
+                let data = decode_checked(&payload, slot.checksum, version, chunk_of(region_index, i))?;
+                let payload = encode_chunk(&data);
+                let checksum = crc32(&payload);
+                region.payloads[i] = Some((payload, checksum));
+            }
+        }
+        Ok(region)
+    }
+
+    /// Reads a single chunk without loading the rest of the region.
+    pub fn read_chunk<R: Read + Seek>(reader: &mut R, chunk: (i32, i32, i32)) -> Result<Option<ChunkData>, RegionError> {
+        let (version, slots) = read_header(reader)?;
+        let slot = slots[slot_of(chunk)];
+        if slot.length == 0 {
+            return Ok(None);
+        }
+        reader.seek(SeekFrom::Start(slot.offset as u64))?;
+        let mut payload = vec![0u8; slot.length as usize];
+        reader.read_exact(&mut payload)?;
+        decode_checked(&payload, slot.checksum, version, chunk).map(Some)
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut header = Vec::with_capacity(HEADER_BYTES);
+        header.extend_from_slice(REGION_MAGIC);
+        header.extend_from_slice(&REGION_VERSION.to_le_bytes());
+
+        let mut offset = HEADER_BYTES as u32;
+        for payload in self.payloads.iter() {
+            let slot = match *payload {
+                Some((ref bytes, checksum)) => {
+                    let slot = Slot { offset, length: bytes.len() as u32, checksum };
+                    offset += bytes.len() as u32;
+                    slot
+                },
+                None => Slot::default(),
+            };
+            header.extend_from_slice(&slot.offset.to_le_bytes());
+            header.extend_from_slice(&slot.length.to_le_bytes());
+            header.extend_from_slice(&slot.checksum.to_le_bytes());
+        }
+        writer.write_all(&header)?;
+
+        for payload in self.payloads.iter() {
+            if let Some((ref bytes, _)) = *payload {
+                writer.write_all(bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, chunk: (i32, i32, i32)) -> Result<Option<ChunkData>, RegionError> {
+        match self.payloads[slot_of(chunk)] {
+            Some((ref bytes, checksum)) => decode_checked(bytes, checksum, REGION_VERSION, chunk).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set(&mut self, chunk: (i32, i32, i32), data: &ChunkData) {
+        let payload = encode_chunk(data);
+        let checksum = crc32(&payload);
+        self.payloads[slot_of(chunk)] = Some((payload, checksum));
+    }
+}
+
+fn read_header<R: Read>(reader: &mut R) -> Result<(u32, Vec<Slot>), RegionError> {
+    let mut header = vec![0u8; HEADER_BYTES];
+    reader.read_exact(&mut header)?;
+    if &header[0..4] != REGION_MAGIC {
+        return Err(RegionError::BadMagic);
+    }
+    let version = read_u32(&header[4..8]);
+    if version == 0 || version > REGION_VERSION {
+        return Err(RegionError::UnsupportedVersion(version));
+    }
+
+    let slots = header[8..].chunks(SLOT_BYTES)
+        .map(|slot| Slot {
+            offset: read_u32(&slot[0..4]),
+            length: read_u32(&slot[4..8]),
+            checksum: read_u32(&slot[8..12]),
+        })
+        .collect();
+    Ok((version, slots))
+}
+
+#[inline]
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn decode_checked(payload: &[u8], checksum: u32, version: u32, chunk: (i32, i32, i32)) -> Result<ChunkData, RegionError> {
+    if crc32(payload) != checksum {
+        return Err(RegionError::ChecksumMismatch(chunk));
+    }
+    decode_chunk(payload, version).ok_or(RegionError::Corrupt(chunk))
+}
+
+fn encode_chunk(data: &ChunkData) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut run: Option<(u16, Voxel)> = None;
+    for z in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let voxel = data.get_voxel((x, y, z));
+                run = match run {
+                    Some((n, v)) if v == voxel => Some((n + 1, v)),
+                    Some((n, v)) => {
+                        write_run(&mut out, n, v);
+                        Some((1, voxel))
+                    },
+                    None => Some((1, voxel)),
+                };
+            }
+        }
+    }
+    if let Some((n, v)) = run {
+        write_run(&mut out, n, v);
+    }
+    out
+}
+
+fn write_run(out: &mut Vec<u8>, run: u16, voxel: Voxel) {
+    out.extend_from_slice(&run.to_le_bytes());
+    out.extend_from_slice(&voxel.id.to_le_bytes());
+    out.extend_from_slice(&voxel.state.to_le_bytes());
+}
+
+fn decode_chunk(payload: &[u8], version: u32) -> Option<ChunkData> {
+    let run_bytes = if version == 1 { 3 } else { 6 };
+    if payload.len() % run_bytes != 0 {
+        return None;
+    }
+    let mut data = ChunkData::default();
+    let mut i = 0;
+    for run in payload.chunks(run_bytes) {
+        let n = u16::from_le_bytes([run[0], run[1]]) as usize;
+        let voxel = if version == 1 {
+            Voxel::new(run[2] as u16)
+        } else {
+            Voxel {
+                id: u16::from_le_bytes([run[2], run[3]]),
+                state: u16::from_le_bytes([run[4], run[5]]),
+            }
+        };
+        if i + n > CHUNK_VOLUME {
+            return None;
+        }
+        for j in i..(i + n) {
+            data.set_voxel((j % CHUNK_SIZE, (j / CHUNK_SIZE) % CHUNK_SIZE, j / (CHUNK_SIZE * CHUNK_SIZE)), voxel);
+        }
+        i += n;
+    }
+    if i == CHUNK_VOLUME { Some(data) } else { None }
+}
+
+/// CRC-32 (IEEE), bitwise. Payloads are small enough that a table is not worth it.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    /// A version 1 region holding the given `(chunk, payload, checksum)`s.
+    fn version_1(chunks: &[((i32, i32, i32), Vec<u8>, u32)]) -> Vec<u8> {
+        let mut slots = vec![Slot::default(); REGION_VOLUME];
+        let mut payloads = Vec::new();
+        for &(chunk, ref payload, checksum) in chunks.iter() {
+            let offset = (HEADER_BYTES + payloads.len()) as u32;
+            slots[slot_of(chunk)] = Slot { offset, length: payload.len() as u32, checksum };
+            payloads.extend_from_slice(payload);
+        }
+        let mut bytes = REGION_MAGIC.to_vec();
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        for slot in slots.iter() {
+            bytes.extend_from_slice(&slot.offset.to_le_bytes());
+            bytes.extend_from_slice(&slot.length.to_le_bytes());
+            bytes.extend_from_slice(&slot.checksum.to_le_bytes());
+        }
+        bytes.extend(payloads);
+        bytes
+    }
+
+    /// A whole chunk of one block, as a version 1 payload.
+    fn filled_payload(id: u8) -> Vec<u8> {
+        let mut payload = (CHUNK_VOLUME as u16).to_le_bytes().to_vec();
+        payload.push(id);
+        payload
+    }
+
+    #[test]
+    fn slots_map_to_chunks() {
+        for &chunk in [(0, 0, 0), (9, -7, 2), (-1, -8, 15), (-9, 23, -16)].iter() {
+            assert_eq!(chunk_of(region_of(chunk), slot_of(chunk)), chunk);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut data = ChunkData::default();
+        data.set_voxel((1, 2, 3), Voxel::new(300).with_state(2));
+        let mut region = RegionFile::new();
+        region.set((9, -7, 2), &data);
+
+        let mut bytes = Vec::new();
+        region.write_to(&mut bytes).unwrap();
+        let region = RegionFile::read_from(&mut Cursor::new(bytes), (1, -1, 0)).unwrap();
+        let read = region.get((9, -7, 2)).unwrap().unwrap();
+        assert_eq!(read.get_voxel((1, 2, 3)), Voxel::new(300).with_state(2));
+        assert_eq!(read.get_voxel((0, 0, 0)), Voxel::default());
+        assert!(region.get((10, -7, 2)).unwrap().is_none());
+    }
+
+    #[test]
+    fn upgrades_version_1() {
+        let payload = filled_payload(3);
+        let checksum = crc32(&payload);
+        let bytes = version_1(&[((9, -7, 2), payload, checksum)]);
+        let region = RegionFile::read_from(&mut Cursor::new(bytes), (1, -1, 0)).unwrap();
+        assert_eq!(region.get((9, -7, 2)).unwrap().unwrap().uniform_voxel(), Some(Voxel::new(3)));
+    }
+
+    #[test]
+    fn keeps_bad_version_1_chunks() {
+        let payload = filled_payload(3);
+        let checksum = crc32(&payload);
+        let bytes = version_1(&[
+            ((9, -7, 2), payload.clone(), checksum),
+            ((10, -7, 2), payload.clone(), checksum ^ 1),
+        ]);
+        match RegionFile::read_from(&mut Cursor::new(bytes), (1, -1, 0)) {
+            Err(RegionError::ChecksumMismatch(chunk)) => assert_eq!(chunk, (10, -7, 2)),
+            other => panic!("expected a checksum mismatch, got {:?}", other.map(|_| ())),
+        }
+
+        let short = payload[..2].to_vec();
+        let checksum = crc32(&short);
+        let bytes = version_1(&[((12, -3, 7), short, checksum)]);
+        match RegionFile::read_from(&mut Cursor::new(bytes), (1, -1, 0)) {
+            Err(RegionError::Corrupt(chunk)) => assert_eq!(chunk, (12, -3, 7)),
+            other => panic!("expected a corrupt chunk, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,415 @@
+//! Importing builds from Sponge `.schem` (versions 1 to 3) and legacy MCEdit
+//! `.schematic` files.
+//!
+//! Both store a box of `Width` x `Height` x `Length` blocks indexed
+//! `(y * Length + z) * Width + x`. Sponge files name their blocks through a
+//! palette (`minecraft:oak_log[axis=y]`); MCEdit files store numeric ids and
+//! data values, which are looked up as `"<id>:<data>"` and then `"<id>"`.
+//!
+//! Both can carry an offset from the paste point to the box's corner: Sponge's
+//! `Offset`, or WorldEdit's `WEOffsetX`/`Y`/`Z` in MCEdit files.
+//!
+//! Coordinates are copied straight across, so builds keep their shape; note
+//! that Minecraft's +z is south where ours is north.
+
+use super::{ChunkData, Voxel, Axis, AIR, world_to_chunk};
+use super::block::BlockRegistry;
+use super::nbt::{Nbt, NbtError};
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use fnv::{FnvHashMap, FnvHashSet};
+use ron;
+
+#[derive(Debug)]
+pub enum SchematicError {
+    Io(io::Error),
+    Nbt(NbtError),
+    /// A tag the format needs is missing or has the wrong type.
+    Missing(&'static str),
+    /// The block data doesn't match the schematic's size.
+    Corrupt,
+    Mapping(ron::de::Error),
+    UnknownBlock(String),
+}
+
+impl fmt::Display for SchematicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SchematicError::Io(ref e) => write!(f, "schematic i/o error: {}", e),
+            SchematicError::Nbt(ref e) => write!(f, "failed to read schematic: {}", e),
+            SchematicError::Missing(tag) => write!(f, "schematic has no valid {} tag", tag),
+            SchematicError::Corrupt => write!(f, "schematic block data doesn't match its size"),
+            SchematicError::Mapping(ref e) => write!(f, "failed to parse schematic block mapping: {}", e),
+            SchematicError::UnknownBlock(ref name) => write!(f, "schematic block mapping names unknown block {:?}", name),
+        }
+    }
+}
+
+impl Error for SchematicError {}
+
+impl From<io::Error> for SchematicError {
+    fn from(e: io::Error) -> SchematicError {
+        SchematicError::Io(e)
+    }
+}
+
+impl From<NbtError> for SchematicError {
+    fn from(e: NbtError) -> SchematicError {
+        SchematicError::Nbt(e)
+    }
+}
+
+/// Which of our blocks each Minecraft block becomes, as written in a RON
+/// mapping file.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SchematicMappingFile {
+    /// Minecraft block names, with or without states, or legacy
+    /// `"<id>:<data>"`/`"<id>"` keys, to our block names.
+    pub blocks: FnvHashMap<String, String>,
+    /// The block anything unmapped becomes. Unmapped blocks are left out
+    /// when this is `None`.
+    #[serde(default)]
+    pub fallback: Option<String>,
+}
+
+/// A block mapping with our block names looked up.
+#[derive(Clone, Debug, Default)]
+pub struct SchematicMapping {
+    blocks: FnvHashMap<String, Voxel>,
+    fallback: Option<Voxel>,
+}
+
+impl SchematicMapping {
+    pub fn load<P: AsRef<Path>>(path: P, registry: &BlockRegistry) -> Result<Self, SchematicError> {
+        let contents = fs::read_to_string(path)?;
+        let file: SchematicMappingFile = ron::de::from_str(&contents).map_err(SchematicError::Mapping)?;
+        SchematicMapping::new(&file, registry)
+    }
+
+    pub fn new(file: &SchematicMappingFile, registry: &BlockRegistry) -> Result<Self, SchematicError> {
+        let voxel = |name: &str| registry.voxel(name).ok_or_else(|| SchematicError::UnknownBlock(name.to_string()));
+        let mut blocks = FnvHashMap::default();
+        for (from, to) in file.blocks.iter() {
+            blocks.insert(from.clone(), voxel(to)?);
+        }
+        let fallback = match file.fallback {
+            Some(ref name) => Some(voxel(name)?),
+            None => None,
+        };
+        Ok(SchematicMapping { blocks, fallback })
+    }
+
+    pub fn insert(&mut self, name: &str, voxel: Voxel) {
+        self.blocks.insert(name.to_string(), voxel);
+    }
+
+    /// Looks up a Sponge block name, trying the full name with its states
+    /// first and then the bare name. An `axis` state orients the block.
+    fn sponge(&self, name: &str) -> Option<Voxel> {
+        let (bare, states) = match name.find('[') {
+            Some(i) => {
+                // tolerate a missing closing bracket
+                let states = &name[i + 1..];
+                (&name[..i], states.rfind(']').map(|end| &states[..end]).unwrap_or(states))
+            },
+            None => (name, ""),
+        };
+        if is_air(bare) {
+            return Some(self.blocks.get(name).cloned().unwrap_or(AIR));
+        }
+        let voxel = self.blocks.get(name).or_else(|| self.blocks.get(bare)).cloned().or(self.fallback)?;
+        let axis = states.split(',')
+            .filter_map(|state| {
+                let mut parts = state.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some("axis"), Some("x")) => Some(Axis::X),
+                    (Some("axis"), Some("y")) => Some(Axis::Y),
+                    (Some("axis"), Some("z")) => Some(Axis::Z),
+                    _ => None,
+                }
+            })
+            .next();
+        Some(match axis {
+            Some(axis) => voxel.with_orientation(axis),
+            None => voxel,
+        })
+    }
+
+    fn legacy(&self, id: u16, data: u8) -> Option<Voxel> {
+        if id == 0 {
+            return Some(AIR);
+        }
+        self.blocks.get(&format!("{}:{}", id, data))
+            .or_else(|| self.blocks.get(&id.to_string()))
+            .cloned()
+            .or(self.fallback)
+    }
+}
+
+fn is_air(name: &str) -> bool {
+    match name {
+        "minecraft:air" | "minecraft:cave_air" | "minecraft:void_air" => true,
+        _ => false,
+    }
+}
+
+/// A box of blocks read from a schematic, ready to paste into the world.
+#[derive(Clone, Debug)]
+pub struct Schematic {
+    /// `(width, height, length)`, along x, y and z.
+    pub size: (usize, usize, usize),
+    /// Indexed `(y * length + z) * width + x`, `None` where the block wasn't
+    /// mapped, so pasting leaves the world there alone.
+    pub blocks: Vec<Option<Voxel>>,
+    /// Where the schematic wants its corner relative to the paste point.
+    pub offset: (i32, i32, i32),
+    /// Block names with no mapping, for reporting.
+    pub unmapped: FnvHashSet<String>,
+}
+
+impl Schematic {
+    pub fn load<P: AsRef<Path>>(path: P, mapping: &SchematicMapping) -> Result<Schematic, SchematicError> {
+        Schematic::read_from(&mut BufReader::new(File::open(path)?), mapping)
+    }
+
+    /// Reads either format, telling them apart by their tags.
+    pub fn read_from<R: Read>(reader: &mut R, mapping: &SchematicMapping) -> Result<Schematic, SchematicError> {
+        let (_, root) = Nbt::read_from(reader)?;
+        // version 3 Sponge files nest everything in a Schematic compound
+        let root = root.get("Schematic").unwrap_or(&root);
+
+        let dimension = |name: &'static str| {
+            root.get(name)
+                .and_then(|v| v.as_i64())
+                .map(|v| v as u16 as usize)
+                .ok_or(SchematicError::Missing(name))
+        };
+        let size = (dimension("Width")?, dimension("Height")?, dimension("Length")?);
+        let volume = size.0 * size.1 * size.2;
+
+        let mut unmapped = FnvHashSet::default();
+        let (blocks, offset) = if root.get("Materials").is_some() || root.get("Blocks").and_then(|b| b.as_bytes()).is_some() {
+            // WorldEdit keeps the copy's offset alongside the MCEdit tags
+            let we_offset = |name: &'static str| root.get(name).and_then(|o| o.as_i64()).unwrap_or(0) as i32;
+            let offset = (we_offset("WEOffsetX"), we_offset("WEOffsetY"), we_offset("WEOffsetZ"));
+            (read_mcedit(root, mapping, volume, &mut unmapped)?, offset)
+        } else {
+            let offset = match root.get("Offset").and_then(|o| o.as_ints()) {
+                Some(o) if o.len() == 3 => (o[0], o[1], o[2]),
+                _ => (0, 0, 0),
+            };
+            (read_sponge(root, mapping, volume, &mut unmapped)?, offset)
+        };
+
+        Ok(Schematic { size, blocks, offset, unmapped })
+    }
+
+    /// Calls `place` for each mapped block with the schematic's corner at
+    /// `origin` plus its offset. `place` can write into loaded chunks, queue
+    /// blocks for unloaded ones, or fill new `ChunkData`.
+    pub fn place<F>(&self, origin: (i32, i32, i32), mut place: F)
+    where F: FnMut((i32, i32, i32), Voxel)
+    {
+        let (width, height, length) = self.size;
+        let corner = (origin.0 + self.offset.0, origin.1 + self.offset.1, origin.2 + self.offset.2);
+        for y in 0..height {
+            for z in 0..length {
+                for x in 0..width {
+                    if let Some(voxel) = self.blocks[(y * length + z) * width + x] {
+                        place((corner.0 + x as i32, corner.1 + y as i32, corner.2 + z as i32), voxel);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The schematic as chunks, with air wherever it has nothing to say.
+    pub fn to_chunks(&self, origin: (i32, i32, i32)) -> FnvHashMap<(i32, i32, i32), ChunkData> {
+        let mut chunks: FnvHashMap<(i32, i32, i32), ChunkData> = FnvHashMap::default();
+        self.place(origin, |position, voxel| {
+            let (index, local) = world_to_chunk(position);
+            chunks.entry(index).or_insert_with(ChunkData::default).set_voxel(local, voxel);
+        });
+        chunks
+    }
+}
+
+fn read_mcedit(
+    root: &Nbt,
+    mapping: &SchematicMapping,
+    volume: usize,
+    unmapped: &mut FnvHashSet<String>,
+) -> Result<Vec<Option<Voxel>>, SchematicError> {
+    let ids = root.get("Blocks").and_then(|b| b.as_bytes()).ok_or(SchematicError::Missing("Blocks"))?;
+    let data = root.get("Data").and_then(|d| d.as_bytes()).ok_or(SchematicError::Missing("Data"))?;
+    // the high four bits of ids above 255, two blocks to a byte
+    let add = root.get("AddBlocks").and_then(|a| a.as_bytes());
+    if ids.len() < volume || data.len() < volume {
+        return Err(SchematicError::Corrupt);
+    }
+
+    let mut blocks = Vec::with_capacity(volume);
+    for i in 0..volume {
+        let high = add.and_then(|add| add.get(i / 2)).map(|&b| {
+            let b = b as u8;
+            if i % 2 == 0 { b >> 4 } else { b & 0xf }
+        }).unwrap_or(0);
+        let id = ((high as u16) << 8) | ids[i] as u8 as u16;
+        let meta = data[i] as u8 & 0xf;
+        let voxel = mapping.legacy(id, meta);
+        if voxel.is_none() {
+            unmapped.insert(format!("{}:{}", id, meta));
+        }
+        blocks.push(voxel);
+    }
+    Ok(blocks)
+}
+
+fn read_sponge(
+    root: &Nbt,
+    mapping: &SchematicMapping,
+    volume: usize,
+    unmapped: &mut FnvHashSet<String>,
+) -> Result<Vec<Option<Voxel>>, SchematicError> {
+    // version 3 moved the palette and data into a Blocks compound
+    let container = root.get("Blocks").filter(|b| b.as_compound().is_some()).unwrap_or(root);
+    let palette = container.get("Palette").and_then(|p| p.as_compound()).ok_or(SchematicError::Missing("Palette"))?;
+    let data = container.get("BlockData")
+        .or_else(|| container.get("Data"))
+        .and_then(|d| d.as_bytes())
+        .ok_or(SchematicError::Missing("BlockData"))?;
+
+    let mut by_id: FnvHashMap<u32, Option<Voxel>> = FnvHashMap::default();
+    for (name, id) in palette.iter() {
+        let id = id.as_i64().ok_or(SchematicError::Missing("Palette"))? as u32;
+        let voxel = mapping.sponge(name);
+        if voxel.is_none() {
+            unmapped.insert(name.clone());
+        }
+        by_id.insert(id, voxel);
+    }
+
+    // palette ids are varints
+    let mut blocks = Vec::with_capacity(volume);
+    let mut bytes = data.iter().map(|&b| b as u8);
+    while blocks.len() < volume {
+        let mut id = 0u32;
+        let mut shift = 0;
+        loop {
+            let byte = bytes.next().ok_or(SchematicError::Corrupt)?;
+            id |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+            if shift > 28 {
+                return Err(SchematicError::Corrupt);
+            }
+        }
+        blocks.push(by_id.get(&id).cloned().unwrap_or(None));
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping() -> SchematicMapping {
+        let mut mapping = SchematicMapping::default();
+        mapping.insert("minecraft:oak_log", Voxel::new(5));
+        mapping.insert("minecraft:oak_log[axis=x]", Voxel::new(6));
+        mapping
+    }
+
+    #[test]
+    fn sponge_names() {
+        let mapping = mapping();
+        assert_eq!(mapping.sponge("minecraft:oak_log"), Some(Voxel::new(5)));
+        // the full name wins over the bare one
+        assert_eq!(mapping.sponge("minecraft:oak_log[axis=x]"), Some(Voxel::new(6).with_orientation(Axis::X)));
+        assert_eq!(mapping.sponge("minecraft:oak_log[axis=z,stripped=true]"), Some(Voxel::new(5).with_orientation(Axis::Z)));
+        assert_eq!(mapping.sponge("minecraft:air"), Some(AIR));
+        assert_eq!(mapping.sponge("minecraft:stone"), None);
+    }
+
+    #[test]
+    fn sponge_unclosed_states() {
+        let mapping = mapping();
+        assert_eq!(mapping.sponge("minecraft:oak_log["), Some(Voxel::new(5)));
+        assert_eq!(mapping.sponge("minecraft:oak_log[axis=z"), Some(Voxel::new(5).with_orientation(Axis::Z)));
+        assert_eq!(mapping.sponge("["), None);
+    }
+
+    fn registry() -> BlockRegistry {
+        BlockRegistry::load(Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/blocks.ron")).unwrap()
+    }
+
+    /// The default mapping, but leaving out unmapped blocks.
+    fn default_mapping(registry: &BlockRegistry) -> SchematicMapping {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/schematic_blocks.ron");
+        let mut file: SchematicMappingFile = ron::de::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        file.fallback = None;
+        SchematicMapping::new(&file, registry).unwrap()
+    }
+
+    fn fixture(name: &str, mapping: &SchematicMapping) -> Schematic {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/schematic").join(name);
+        Schematic::load(&path, mapping).unwrap_or_else(|e| panic!("failed to load {:?}: {}", path, e))
+    }
+
+    fn assert_fixture(schematic: &Schematic, registry: &BlockRegistry, log: Voxel, unmapped: &str) {
+        let block = |name| Some(registry.voxel(name).unwrap());
+        assert_eq!(schematic.size, (2, 2, 2));
+        assert_eq!(schematic.blocks, vec![
+            block("stone"), Some(log), Some(AIR), block("glass"),
+            None, Some(AIR), Some(AIR), block("stone"),
+        ]);
+        assert_eq!(schematic.unmapped.iter().collect::<Vec<_>>(), vec![unmapped]);
+    }
+
+    #[test]
+    fn reads_sponge_v2() {
+        let registry = registry();
+        let schematic = fixture("sponge_v2.schem", &default_mapping(&registry));
+        let log = registry.voxel("log").unwrap().with_orientation(Axis::X);
+        assert_fixture(&schematic, &registry, log, "minecraft:mystery_block");
+        assert_eq!(schematic.offset, (1, -2, 3));
+    }
+
+    #[test]
+    fn reads_sponge_v3() {
+        let registry = registry();
+        let schematic = fixture("sponge_v3.schem", &default_mapping(&registry));
+        let log = registry.voxel("log").unwrap().with_orientation(Axis::X);
+        assert_fixture(&schematic, &registry, log, "minecraft:mystery_block");
+        assert_eq!(schematic.offset, (1, -2, 3));
+    }
+
+    #[test]
+    fn reads_mcedit() {
+        let registry = registry();
+        let schematic = fixture("mcedit.schematic", &default_mapping(&registry));
+        // legacy data values aren't read for orientation
+        assert_fixture(&schematic, &registry, registry.voxel("log").unwrap(), "250:0");
+        assert_eq!(schematic.offset, (-1, 0, -2));
+    }
+
+    #[test]
+    fn places_at_offset() {
+        let registry = registry();
+        let schematic = fixture("mcedit.schematic", &default_mapping(&registry));
+        let chunks = schematic.to_chunks((0, 16, 0));
+        // the corner lands at the offset, so the box straddles a chunk border
+        let (index, local) = world_to_chunk((-1, 16, -2));
+        assert_eq!(chunks[&index].get_voxel(local), registry.voxel("stone").unwrap());
+        let (index, local) = world_to_chunk((0, 17, -1));
+        assert_eq!(chunks[&index].get_voxel(local), registry.voxel("stone").unwrap());
+        assert_eq!(chunks.len(), 2);
+    }
+}
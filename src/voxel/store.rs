@@ -0,0 +1,456 @@
+use super::{ChunkData, ChunkIndex, ChunkMesher, Voxel, VoxelWorld, CHUNK_SIZE, world_to_chunk};
+use super::region::{RegionFile, RegionError, region_of};
+use super::terrain::{TerrainConfig, TerrainError};
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use fnv::{FnvHashMap, FnvHashSet};
+use ron;
+
+use specs::{
+    Entities,
+    System,
+    ReadStorage,
+    WriteStorage,
+    Storage,
+    storage::MaskedStorage,
+    ReaderId,
+    storage::ComponentEvent,
+    SystemData,
+};
+use shred::{
+    ReadExpect,
+    WriteExpect,
+    Resources,
+};
+
+/// The parts of a save that failed; everything else was written.
+#[derive(Debug, Default)]
+pub struct SaveError {
+    /// Each region that couldn't be saved, and why.
+    pub regions: Vec<((i32, i32, i32), RegionError)>,
+    /// Why the pending feature blocks couldn't be saved, if they weren't.
+    pub pending: Option<io::Error>,
+}
+
+impl SaveError {
+    fn into_result(self) -> Result<(), SaveError> {
+        if self.regions.is_empty() && self.pending.is_none() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for &(region, ref e) in self.regions.iter() {
+            if !first {
+                write!(f, "; ")?;
+            }
+            first = false;
+            write!(f, "region {:?}: {}", region, e)?;
+        }
+        if let Some(ref e) = self.pending {
+            if !first {
+                write!(f, "; ")?;
+            }
+            write!(f, "pending blocks: {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for SaveError {}
+
+/// A resource for reading and writing chunks to region files on disk.
+///
+/// Also keeps the set of chunks modified since they were last saved, and the
+/// blocks of generated features waiting for chunks that aren't loaded yet.
+pub struct ChunkStore {
+    root: PathBuf,
+    dirty: FnvHashSet<(i32, i32, i32)>,
+    /// Edited chunks that failed to save as they unloaded, held until
+    /// `save_dirty` manages to write them.
+    unsaved: FnvHashMap<(i32, i32, i32), ChunkData>,
+    pending: FnvHashMap<(i32, i32, i32), Vec<((usize, usize, usize), Voxel)>>,
+    pending_changed: bool,
+}
+
+impl ChunkStore {
+    /// Opens (creating if needed) the world directory at `root`.
+    pub fn open<P: Into<PathBuf>>(root: P) -> io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        let pending = match File::open(root.join("pending")) {
+            Ok(file) => read_pending(&mut BufReader::new(file))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => FnvHashMap::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(ChunkStore {
+            root,
+            dirty: FnvHashSet::default(),
+            unsaved: FnvHashMap::default(),
+            pending,
+            pending_changed: false,
+        })
+    }
+
+    fn region_path(&self, region: (i32, i32, i32)) -> PathBuf {
+        self.root.join(format!("r.{}.{}.{}.vxr", region.0, region.1, region.2))
+    }
+
+    /// Reads the world seed, if one has been saved.
+    pub fn seed(&self) -> io::Result<Option<u32>> {
+        let mut contents = String::new();
+        match File::open(self.root.join("seed")) {
+            Ok(mut file) => { file.read_to_string(&mut contents)?; },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        contents.trim().parse()
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn set_seed(&self, seed: u32) -> io::Result<()> {
+        let mut file = File::create(self.root.join("seed"))?;
+        writeln!(file, "{}", seed)
+    }
+
+    /// Reads which terrain generator the world uses, if one has been saved.
+    pub fn terrain_config(&self) -> Result<Option<TerrainConfig>, TerrainError> {
+        match TerrainConfig::load(self.root.join("terrain.ron")) {
+            Ok(config) => Ok(Some(config)),
+            Err(TerrainError::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set_terrain_config(&self, config: &TerrainConfig) -> Result<(), TerrainError> {
+        config.save(self.root.join("terrain.ron"))
+    }
+
+    /// Reads which mesher the world's chunks use, if the world picks one.
+    pub fn mesher(&self) -> io::Result<Option<ChunkMesher>> {
+        let contents = match fs::read_to_string(self.root.join("mesher.ron")) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        ron::de::from_str(&contents)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Loads a chunk, or `None` if it has never been saved.
+    pub fn load_chunk(&self, index: (i32, i32, i32)) -> Result<Option<ChunkData>, RegionError> {
+        // newer than what's on disk
+        if let Some(data) = self.unsaved.get(&index) {
+            return Ok(Some(data.clone()));
+        }
+        match File::open(self.region_path(region_of(index))) {
+            Ok(file) => RegionFile::read_chunk(&mut BufReader::new(file), index),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Saves the given chunks, rewriting each affected region file once.
+    ///
+    /// Regions are saved independently, so one that can't be read or
+    /// written doesn't stop the others; its chunks stay dirty.
+    pub fn save_chunks<'c, I>(&mut self, chunks: I) -> Result<(), SaveError>
+    where I: IntoIterator<Item=((i32, i32, i32), &'c ChunkData)>
+    {
+        let mut by_region: FnvHashMap<(i32, i32, i32), Vec<((i32, i32, i32), &'c ChunkData)>> = FnvHashMap::default();
+        for (index, data) in chunks {
+            by_region.entry(region_of(index)).or_insert_with(Vec::new).push((index, data));
+        }
+
+        let mut error = SaveError::default();
+        for (region, chunks) in by_region {
+            if let Err(e) = self.save_region(region, &chunks) {
+                error.regions.push((region, e));
+                continue;
+            }
+            for (index, _) in chunks {
+                self.dirty.remove(&index);
+                self.unsaved.remove(&index);
+            }
+        }
+        error.into_result()
+    }
+
+    fn save_region(&self, region: (i32, i32, i32), chunks: &[((i32, i32, i32), &ChunkData)]) -> Result<(), RegionError> {
+        let path = self.region_path(region);
+        let mut region_file = match File::open(&path) {
+            Ok(file) => RegionFile::read_from(&mut BufReader::new(file), region)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => RegionFile::new(),
+            Err(e) => return Err(e.into()),
+        };
+        for &(index, data) in chunks.iter() {
+            region_file.set(index, data);
+        }
+        write_atomically(&path, &region_file)?;
+        Ok(())
+    }
+
+    /// Saves every chunk that has been modified since it was last saved,
+    /// loaded or held after failing to save as it unloaded, and the pending
+    /// feature blocks.
+    pub fn save_dirty<'e, T>(&mut self, voxel_world: &VoxelWorld, chunk_datas: &Storage<'e, ChunkData, T>) -> Result<(), SaveError>
+    where T: Deref<Target=MaskedStorage<ChunkData>>
+    {
+        let mut loaded = Vec::new();
+        let mut unsaved = Vec::new();
+        for &index in self.dirty.iter() {
+            match voxel_world.get_entity(index).and_then(|entity| chunk_datas.get(entity)) {
+                Some(data) => loaded.push((index, data)),
+                None => match self.unsaved.get(&index) {
+                    Some(data) => unsaved.push((index, data.clone())),
+                    None => eprintln!("Chunk {:?} was unloaded without being saved; its edits are lost", index),
+                },
+            }
+        }
+        let saved: FnvHashSet<_> = loaded.iter().map(|&(index, _)| index)
+            .chain(unsaved.iter().map(|&(index, _)| index))
+            .collect();
+        self.dirty.retain(|index| saved.contains(index));
+        let mut error = match self.save_chunks(loaded.into_iter().chain(unsaved.iter().map(|&(index, ref data)| (index, data)))) {
+            Ok(()) => SaveError::default(),
+            Err(e) => e,
+        };
+        error.pending = self.save_pending().err();
+        error.into_result()
+    }
+
+    /// Saves the chunks being unloaded that have unsaved edits, rewriting
+    /// each affected region file once. Chunks that fail to save are held
+    /// until `save_dirty` manages to write them, and `load_chunk` returns
+    /// them in the meantime.
+    pub fn unload_chunks<'c, I>(&mut self, chunks: I) -> Result<(), SaveError>
+    where I: IntoIterator<Item=((i32, i32, i32), &'c ChunkData)>
+    {
+        let dirty: Vec<_> = chunks.into_iter()
+            .filter(|&(index, _)| self.is_dirty(index))
+            .collect();
+        let result = self.save_chunks(dirty.iter().cloned());
+        // whatever is still dirty didn't make it to disk
+        for (index, data) in dirty {
+            if self.is_dirty(index) {
+                self.unsaved.insert(index, data.clone());
+            }
+        }
+        result
+    }
+
+    pub fn mark_dirty(&mut self, index: (i32, i32, i32)) {
+        self.dirty.insert(index);
+    }
+
+    pub fn is_dirty(&self, index: (i32, i32, i32)) -> bool {
+        self.dirty.contains(&index)
+    }
+
+    /// Holds on to a feature block for a chunk that isn't loaded, to be
+    /// placed when it is.
+    pub fn queue_block(&mut self, position: (i32, i32, i32), voxel: Voxel) {
+        let (index, local) = world_to_chunk(position);
+        self.pending.entry(index).or_insert_with(Vec::new).push((local, voxel));
+        self.pending_changed = true;
+    }
+
+    /// Takes the feature blocks waiting for a chunk.
+    pub fn take_pending(&mut self, index: (i32, i32, i32)) -> Vec<((usize, usize, usize), Voxel)> {
+        match self.pending.remove(&index) {
+            Some(blocks) => {
+                self.pending_changed = true;
+                blocks
+            },
+            None => Vec::new(),
+        }
+    }
+
+    /// Writes the waiting feature blocks to disk, if they changed.
+    pub fn save_pending(&mut self) -> io::Result<()> {
+        if !self.pending_changed {
+            return Ok(());
+        }
+        let path = self.root.join("pending");
+        let temp_path = path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            write_pending(&mut writer, &self.pending)?;
+            writer.flush()?;
+        }
+        fs::rename(&temp_path, path)?;
+        self.pending_changed = false;
+        Ok(())
+    }
+}
+
+/// Pending blocks are stored as, for each chunk, its index (3 x i32 LE) and
+/// block count (u32 LE), followed by that many `(x u8, y u8, z u8, id u16,
+/// state u16)`.
+fn write_pending<W: Write>(writer: &mut W, pending: &FnvHashMap<(i32, i32, i32), Vec<((usize, usize, usize), Voxel)>>) -> io::Result<()> {
+    for (index, blocks) in pending.iter() {
+        writer.write_all(&index.0.to_le_bytes())?;
+        writer.write_all(&index.1.to_le_bytes())?;
+        writer.write_all(&index.2.to_le_bytes())?;
+        writer.write_all(&(blocks.len() as u32).to_le_bytes())?;
+        for &((x, y, z), voxel) in blocks.iter() {
+            writer.write_all(&[x as u8, y as u8, z as u8])?;
+            writer.write_all(&voxel.id.to_le_bytes())?;
+            writer.write_all(&voxel.state.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn read_pending<R: Read>(reader: &mut R) -> io::Result<FnvHashMap<(i32, i32, i32), Vec<((usize, usize, usize), Voxel)>>> {
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents)?;
+    let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "pending feature blocks are truncated");
+
+    let mut pending = FnvHashMap::default();
+    let mut rest = &contents[..];
+    while !rest.is_empty() {
+        if rest.len() < 16 {
+            return Err(corrupt());
+        }
+        let int = |bytes: &[u8]| [bytes[0], bytes[1], bytes[2], bytes[3]];
+        let index = (
+            i32::from_le_bytes(int(&rest[0..4])),
+            i32::from_le_bytes(int(&rest[4..8])),
+            i32::from_le_bytes(int(&rest[8..12])),
+        );
+        let count = u32::from_le_bytes(int(&rest[12..16])) as usize;
+        rest = &rest[16..];
+        if rest.len() < count * 7 {
+            return Err(corrupt());
+        }
+        let blocks = rest[..count * 7].chunks(7)
+            .filter(|block| (block[0] as usize) < CHUNK_SIZE && (block[1] as usize) < CHUNK_SIZE && (block[2] as usize) < CHUNK_SIZE)
+            .map(|block| {
+                let local = (block[0] as usize, block[1] as usize, block[2] as usize);
+                let voxel = Voxel::new(u16::from_le_bytes([block[3], block[4]])).with_state(u16::from_le_bytes([block[5], block[6]]));
+                (local, voxel)
+            })
+            .collect();
+        rest = &rest[count * 7..];
+        pending.insert(index, blocks);
+    }
+    Ok(pending)
+}
+
+fn write_atomically(path: &Path, region: &RegionFile) -> io::Result<()> {
+    let temp_path = path.with_extension("vxr.tmp");
+    {
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        region.write_to(&mut writer)?;
+        writer.flush()?;
+    }
+    fs::rename(&temp_path, path)
+}
+
+/// Marks chunks in the `ChunkStore` as dirty when their data is modified.
+#[derive(Default)]
+pub struct ChunkDirtySystem {
+    reader_id: Option<ReaderId<ComponentEvent>>,
+}
+
+impl<'a> System<'a> for ChunkDirtySystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, ChunkIndex>,
+        ReadStorage<'a, ChunkData>,
+        WriteExpect<'a, ChunkStore>,
+    );
+
+    fn run(&mut self, (entities, chunk_indices, chunk_datas, mut store): Self::SystemData) {
+        // Inserted chunks are either freshly loaded or regenerated from the seed,
+        // so only edits need to reach the disk.
+        for event in chunk_datas.channel().read(self.reader_id.as_mut().unwrap()) {
+            if let ComponentEvent::Modified(id) = event {
+                if let Some(index) = chunk_indices.get(entities.entity(*id)) {
+                    store.mark_dirty((*index).into());
+                }
+            }
+        }
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        <Self::SystemData as SystemData>::setup(res);
+        self.reader_id = Some(WriteStorage::<ChunkData>::fetch(res).register_reader())
+    }
+}
+
+/// Writes dirty chunks to disk. Meant to be wrapped in an `IntervalSystem`.
+pub struct ChunkSaveSystem;
+
+impl<'a> System<'a> for ChunkSaveSystem {
+    type SystemData = (
+        ReadExpect<'a, VoxelWorld>,
+        ReadStorage<'a, ChunkData>,
+        WriteExpect<'a, ChunkStore>,
+    );
+
+    fn run(&mut self, (voxel_world, chunk_datas, mut store): Self::SystemData) {
+        if let Err(e) = store.save_dirty(&voxel_world, &chunk_datas) {
+            eprintln!("Failed to save chunks: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process;
+
+    /// A fresh, empty world directory for one test.
+    fn temp_world(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("voxel-store-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        root
+    }
+
+    fn filled(id: u16) -> ChunkData {
+        let mut data = ChunkData::default();
+        data.set_voxel((1, 2, 3), Voxel::new(id));
+        data
+    }
+
+    #[test]
+    fn saves_regions_independently() {
+        let root = temp_world("independent");
+        let mut store = ChunkStore::open(&root).unwrap();
+        let (good, bad) = ((0, 0, 0), (8, 0, 0));
+        fs::write(store.region_path(region_of(bad)), b"not a region").unwrap();
+
+        let (good_data, bad_data) = (filled(1), filled(2));
+        store.mark_dirty(good);
+        store.mark_dirty(bad);
+        let error = store.save_chunks(vec![(good, &good_data), (bad, &bad_data)]).unwrap_err();
+        assert_eq!(error.regions.len(), 1);
+        assert_eq!(error.regions[0].0, region_of(bad));
+
+        assert!(!store.is_dirty(good));
+        assert_eq!(store.load_chunk(good).unwrap().unwrap().get_voxel((1, 2, 3)), Voxel::new(1));
+        assert!(store.is_dirty(bad));
+
+        // a chunk that fails to save as it unloads is held rather than lost
+        assert!(store.unload_chunks(vec![(bad, &bad_data)]).is_err());
+        assert_eq!(store.load_chunk(bad).unwrap().unwrap().get_voxel((1, 2, 3)), Voxel::new(2));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
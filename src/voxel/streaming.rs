@@ -0,0 +1,333 @@
+use super::{
+    ChunkData,
+    ChunkIndex,
+    ChunkQuads,
+    ChunkStore,
+    Voxel,
+    VoxelWorld,
+    WorldLight,
+    AIR,
+    world_to_chunk,
+};
+use super::terrain::{TerrainGenerator, Biome};
+
+use fnv::{FnvHashMap, FnvHashSet};
+
+use amethyst::core::Transform;
+use specs::{
+    Component,
+    Entities,
+    HashMapStorage,
+    System,
+    ReadStorage,
+    WriteStorage,
+    Join,
+};
+use shred::{ReadExpect, WriteExpect};
+use rayon::prelude::*;
+
+/// A resource holding the world's terrain generator, used for chunks that
+/// have never been saved.
+pub struct ChunkGenerator(Box<TerrainGenerator>);
+
+impl ChunkGenerator {
+    pub fn new(generator: Box<TerrainGenerator>) -> Self {
+        ChunkGenerator(generator)
+    }
+
+    pub fn generate(&self, index: (i32, i32, i32)) -> ChunkData {
+        self.0.generate(index.into())
+    }
+
+    pub fn features(&self, index: (i32, i32, i32)) -> Vec<((i32, i32, i32), Voxel)> {
+        self.0.features(index.into())
+    }
+
+    pub fn surface_layers(&self, column: (i32, i32)) -> Option<(i32, i32)> {
+        self.0.surface_layers(column)
+    }
+
+    /// The biome of a world column, if the generator has biomes.
+    pub fn biome_at(&self, x: i32, z: i32) -> Option<&Biome> {
+        self.0.biome_at(x, z)
+    }
+}
+
+/// Keeps the chunks around an entity loaded.
+///
+/// Chunks within `radius` horizontally and `vertical_radius` vertically of
+/// the entity's chunk are loaded, as are the layers holding the terrain
+/// surface of every column within `radius`. They are only unloaded once they
+/// are further than `unload_radius`, with the same margin applied vertically,
+/// so moving back and forth across a chunk border doesn't churn chunks.
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkLoader {
+    pub radius: i32,
+    pub unload_radius: i32,
+    pub vertical_radius: i32,
+}
+
+impl Default for ChunkLoader {
+    fn default() -> Self {
+        ChunkLoader {
+            radius: 8,
+            unload_radius: 10,
+            vertical_radius: 1,
+        }
+    }
+}
+
+impl ChunkLoader {
+    fn wants(&self, centre: (i32, i32, i32), index: (i32, i32, i32), surface: Option<(i32, i32)>) -> bool {
+        within(centre, index, self.radius, self.vertical_radius, surface)
+    }
+
+    fn keeps(&self, centre: (i32, i32, i32), index: (i32, i32, i32), surface: Option<(i32, i32)>) -> bool {
+        let margin = self.unload_radius - self.radius;
+        within(centre, index, self.unload_radius, self.vertical_radius + margin, surface)
+    }
+
+    fn keeps_column(&self, centre: (i32, i32, i32), column: (i32, i32)) -> bool {
+        let (dx, dz) = (column.0 - centre.0, column.1 - centre.2);
+        dx * dx + dz * dz <= self.unload_radius * self.unload_radius
+    }
+}
+
+fn within(
+    centre: (i32, i32, i32),
+    index: (i32, i32, i32),
+    radius: i32,
+    vertical_radius: i32,
+    surface: Option<(i32, i32)>,
+) -> bool {
+    let (dx, dz) = (index.0 - centre.0, index.2 - centre.2);
+    let on_surface = surface.map(|(low, high)| low <= index.1 && index.1 <= high).unwrap_or(false);
+    dx * dx + dz * dz <= radius * radius && ((index.1 - centre.1).abs() <= vertical_radius || on_surface)
+}
+
+impl Component for ChunkLoader {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// Loads or generates chunks around `ChunkLoader`s and unloads the ones
+/// that are left behind, saving them first if they were edited.
+pub struct ChunkStreamingSystem {
+    /// The most chunks loaded or generated in one frame.
+    pub budget: usize,
+    /// The lowest chunk layer that is ever loaded.
+    pub min_y: i32,
+    /// The highest chunk layer that is ever loaded.
+    pub max_y: i32,
+    /// the generator's surface layers of columns near loaders
+    surfaces: FnvHashMap<(i32, i32), Option<(i32, i32)>>,
+}
+
+impl Default for ChunkStreamingSystem {
+    fn default() -> Self {
+        ChunkStreamingSystem {
+            budget: 16,
+            min_y: -16,
+            max_y: 32,
+            surfaces: FnvHashMap::default(),
+        }
+    }
+}
+
+impl<'a> System<'a> for ChunkStreamingSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, ChunkLoader>,
+        WriteExpect<'a, VoxelWorld>,
+        WriteExpect<'a, ChunkStore>,
+        ReadExpect<'a, ChunkGenerator>,
+        WriteExpect<'a, WorldLight>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, ChunkIndex>,
+        WriteStorage<'a, ChunkData>,
+        WriteStorage<'a, ChunkQuads>,
+    );
+
+    fn run(&mut self, (
+        entities,
+        loaders,
+        mut voxel_world,
+        mut store,
+        generator,
+        mut world_light,
+        mut transforms,
+        mut chunk_indices,
+        mut chunk_datas,
+        mut chunk_quads,
+    ): Self::SystemData) {
+        let centres: Vec<((i32, i32, i32), ChunkLoader)> = (&loaders, &transforms).join()
+            .map(|(loader, transform)| {
+                let m = transform.global_matrix();
+                let position = (m[(0, 3)].floor() as i32, m[(1, 3)].floor() as i32, m[(2, 3)].floor() as i32);
+                (world_to_chunk(position).0, *loader)
+            })
+            .collect();
+        if centres.is_empty() {
+            return;
+        }
+
+        // look up the surface of columns that came into range
+        let surfaces = &mut self.surfaces;
+        surfaces.retain(|&column, _| centres.iter().any(|&(centre, loader)| loader.keeps_column(centre, column)));
+        let mut new_columns = FnvHashSet::default();
+        for &(centre, loader) in centres.iter() {
+            for x in (centre.0 - loader.radius)..(centre.0 + loader.radius + 1) {
+                for z in (centre.2 - loader.radius)..(centre.2 + loader.radius + 1) {
+                    if !surfaces.contains_key(&(x, z)) {
+                        new_columns.insert((x, z));
+                    }
+                }
+            }
+        }
+        {
+            let generator = &*generator;
+            let found: Vec<((i32, i32), Option<(i32, i32)>)> = new_columns.into_par_iter()
+                .map(|column| (column, generator.surface_layers(column)))
+                .collect();
+            surfaces.extend(found);
+        }
+        let surface = |index: (i32, i32, i32)| surfaces.get(&(index.0, index.2)).cloned().unwrap_or(None);
+
+        // unload chunks no loader wants to keep
+        {
+            let mut unloaded = Vec::new();
+            for (entity, chunk_index, chunk_data) in (&*entities, &chunk_indices, &chunk_datas).join() {
+                let index: (i32, i32, i32) = (*chunk_index).into();
+                if centres.iter().any(|&(centre, loader)| loader.keeps(centre, index, surface(index))) {
+                    continue;
+                }
+                unloaded.push((entity, index, chunk_data));
+            }
+            if let Err(e) = store.unload_chunks(unloaded.iter().map(|&(_, index, data)| (index, data))) {
+                eprintln!("Failed to save chunks while unloading them, keeping them to retry: {}", e);
+            }
+            for (entity, index, _) in unloaded {
+                voxel_world.remove(index);
+                let _ = entities.delete(entity);
+            }
+            // whole columns out of range are only lit again from scratch
+            world_light.retain_sky_exits(|index| {
+                centres.iter().any(|&(centre, loader)| loader.keeps_column(centre, (index.0, index.2)))
+            });
+        }
+
+        // find the closest missing chunks, up to the budget
+        let mut wanted = FnvHashSet::default();
+        for &(centre, loader) in centres.iter() {
+            for x in (centre.0 - loader.radius)..(centre.0 + loader.radius + 1) {
+                for z in (centre.2 - loader.radius)..(centre.2 + loader.radius + 1) {
+                    let column_surface = surface((x, 0, z));
+                    let (mut low, mut high) = (centre.1 - loader.vertical_radius, centre.1 + loader.vertical_radius);
+                    if let Some((surface_low, surface_high)) = column_surface {
+                        low = low.min(surface_low);
+                        high = high.max(surface_high);
+                    }
+                    for y in low.max(self.min_y)..(high.min(self.max_y) + 1) {
+                        let index = (x, y, z);
+                        if loader.wants(centre, index, column_surface) && voxel_world.get_entity(index).is_none() {
+                            wanted.insert(index);
+                        }
+                    }
+                }
+            }
+        }
+        let mut wanted: Vec<(i32, i32, i32)> = wanted.into_iter().collect();
+        wanted.sort_by_key(|index| {
+            centres.iter()
+                .map(|&(centre, _)| {
+                    let (dx, dy, dz) = (index.0 - centre.0, index.1 - centre.1, index.2 - centre.2);
+                    dx * dx + dy * dy + dz * dz
+                })
+                .min()
+        });
+        wanted.truncate(self.budget);
+
+        // chunks, and for freshly generated ones the blocks of features starting in them
+        let loaded: Vec<((i32, i32, i32), ChunkData, Vec<((i32, i32, i32), Voxel)>)> = {
+            let store = &*store;
+            let generator = &*generator;
+            let generate = |index| (generator.generate(index), generator.features(index));
+            wanted.into_par_iter()
+                .map(|index| {
+                    let (data, features) = match store.load_chunk(index) {
+                        Ok(Some(data)) => (data, Vec::new()),
+                        Ok(None) => generate(index),
+                        Err(e) => {
+                            eprintln!("Failed to load chunk {:?}, regenerating it: {}", index, e);
+                            generate(index)
+                        },
+                    };
+                    (index, data, features)
+                })
+                .collect()
+        };
+
+        let mut batch: FnvHashMap<(i32, i32, i32), ChunkData> = FnvHashMap::default();
+        let mut features = Vec::new();
+        // chunks holding feature blocks, which can't be regenerated from the
+        // seed alone any more. This includes chunks whose features spilled over,
+        // as regenerating those would place their features a second time.
+        let mut edited = FnvHashSet::default();
+        for (index, data, chunk_features) in loaded {
+            if !chunk_features.is_empty() {
+                edited.insert(index);
+            }
+            batch.insert(index, data);
+            features.extend(chunk_features);
+        }
+
+        // blocks neighbours placed here while these chunks weren't loaded
+        for (&index, data) in batch.iter_mut() {
+            for (local, voxel) in store.take_pending(index) {
+                if place_feature_block(data, local, voxel) {
+                    edited.insert(index);
+                }
+            }
+        }
+
+        for (position, voxel) in features {
+            let (index, local) = world_to_chunk(position);
+            if let Some(data) = batch.get_mut(&index) {
+                if place_feature_block(data, local, voxel) {
+                    edited.insert(index);
+                }
+                continue;
+            }
+            match voxel_world.get_voxel(&chunk_datas, position) {
+                Some(current) => {
+                    if current == AIR {
+                        voxel_world.set_voxel(&mut chunk_datas, position, voxel);
+                    }
+                },
+                None => store.queue_block(position, voxel),
+            }
+        }
+
+        for (index, data) in batch {
+            let entity = entities.create();
+            chunk_indices.insert(entity, index.into());
+            chunk_datas.insert(entity, data);
+            transforms.insert(entity, Transform::default());
+            chunk_quads.insert(entity, ChunkQuads::default());
+            // registered straight away so this frame's meshing sees neighbours loaded alongside
+            voxel_world.insert(index, entity);
+        }
+        for index in edited {
+            store.mark_dirty(index);
+        }
+    }
+}
+
+/// Places a feature block unless something is already there, so features
+/// never cut into terrain, each other, or the player's building.
+fn place_feature_block(data: &mut ChunkData, local: (usize, usize, usize), voxel: Voxel) -> bool {
+    if data.get_voxel(local) != AIR {
+        return false;
+    }
+    data.set_voxel(local, voxel);
+    true
+}
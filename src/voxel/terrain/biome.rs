@@ -0,0 +1,396 @@
+use super::{TerrainGenerator, TerrainError, block_voxel, column_hash, layer_of};
+use super::structure::{FeatureRng, ShapeDef, Structure, StructureDef};
+use super::super::{ChunkData, ChunkIndex, Voxel, CHUNK_SIZE};
+use super::super::block::BlockRegistry;
+
+use noise::{NoiseModule, Perlin, Seedable};
+
+/// One biome, as written in the terrain config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BiomeDef {
+    pub name: String,
+    /// Where the biome sits in climate space. Temperature and humidity noise
+    /// both run from -1 to 1, and each column takes the closest biome.
+    pub temperature: f32,
+    pub humidity: f32,
+    /// The lowest the surface goes.
+    pub base: f32,
+    /// How far above `base` the surface reaches at most.
+    pub amplitude: f32,
+    /// The width, in blocks, of one unit of height noise. Larger is smoother.
+    pub scale: f32,
+    /// The top block of each column.
+    pub surface: String,
+    /// The blocks under the surface, before stone.
+    pub subsurface: String,
+    pub subsurface_depth: i32,
+    #[serde(default)]
+    pub decorations: Vec<DecorationDef>,
+    #[serde(default)]
+    pub structures: Vec<StructureDef>,
+}
+
+/// A column of blocks sometimes placed on top of a biome's surface.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DecorationDef {
+    pub block: String,
+    /// The chance of a column getting this decoration, from 0 to 1.
+    pub chance: f32,
+    pub height: i32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BiomesConfig {
+    /// The block everything below the subsurface is made of.
+    pub stone: String,
+    /// The width, in blocks, of one unit of climate noise.
+    pub climate_scale: f32,
+    /// How gradually heights blend between neighbouring biomes. Zero gives
+    /// sheer cliffs at biome borders.
+    pub blend: f32,
+    pub biomes: Vec<BiomeDef>,
+}
+
+impl Default for BiomesConfig {
+    fn default() -> Self {
+        let biome = |name: &str, (temperature, humidity), (base, amplitude, scale), surface: &str, subsurface: &str| BiomeDef {
+            name: name.to_string(),
+            temperature,
+            humidity,
+            base,
+            amplitude,
+            scale,
+            surface: surface.to_string(),
+            subsurface: subsurface.to_string(),
+            subsurface_depth: 3,
+            decorations: Vec::new(),
+            structures: Vec::new(),
+        };
+        let tree = |per_chunk| StructureDef {
+            shape: ShapeDef::Tree {
+                trunk: "log".to_string(),
+                leaves: "leaves".to_string(),
+                min_height: 4,
+                max_height: 7,
+                radius: 2,
+            },
+            per_chunk,
+        };
+        let boulder = |per_chunk| StructureDef {
+            shape: ShapeDef::Boulder {
+                block: "stone".to_string(),
+                radius: 2,
+            },
+            per_chunk,
+        };
+        let mut plains = biome("plains", (0.2, 0.2), (32., 16., 128.), "grass", "dirt");
+        plains.structures.push(tree(0.3));
+        let mut forest = biome("forest", (0.0, 0.6), (36., 24., 96.), "grass", "dirt");
+        forest.structures.push(tree(4.));
+        let mut desert = biome("desert", (0.6, -0.6), (28., 10., 96.), "sand", "sand");
+        desert.subsurface_depth = 5;
+        let mut tundra = biome("tundra", (-0.6, 0.0), (40., 30., 128.), "snow", "dirt");
+        tundra.structures.push(boulder(0.3));
+        let mut mountains = biome("mountains", (-0.2, -0.5), (48., 160., 192.), "stone", "stone");
+        mountains.structures.push(boulder(0.5));
+        BiomesConfig {
+            stone: "stone".to_string(),
+            climate_scale: 512.,
+            blend: 0.02,
+            biomes: vec![plains, forest, desert, tundra, mountains],
+        }
+    }
+}
+
+/// A biome with its blocks looked up.
+#[derive(Clone, Debug)]
+pub struct Biome {
+    pub name: String,
+    pub temperature: f32,
+    pub humidity: f32,
+    pub base: f32,
+    pub amplitude: f32,
+    pub scale: f32,
+    pub surface: Voxel,
+    pub subsurface: Voxel,
+    pub subsurface_depth: i32,
+    pub decorations: Vec<Decoration>,
+    pub structures: Vec<Structure>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Decoration {
+    pub voxel: Voxel,
+    pub chance: f32,
+    pub height: i32,
+}
+
+impl Biome {
+    fn new(def: &BiomeDef, registry: &BlockRegistry) -> Result<Self, TerrainError> {
+        let mut decorations = Vec::with_capacity(def.decorations.len());
+        for decoration in def.decorations.iter() {
+            decorations.push(Decoration {
+                voxel: block_voxel(registry, &decoration.block)?,
+                chance: decoration.chance,
+                height: decoration.height,
+            });
+        }
+        let mut structures = Vec::with_capacity(def.structures.len());
+        for structure in def.structures.iter() {
+            structures.push(Structure::new(structure, registry)?);
+        }
+        Ok(Biome {
+            name: def.name.clone(),
+            temperature: def.temperature,
+            humidity: def.humidity,
+            base: def.base,
+            amplitude: def.amplitude,
+            scale: def.scale,
+            surface: block_voxel(registry, &def.surface)?,
+            subsurface: block_voxel(registry, &def.subsurface)?,
+            subsurface_depth: def.subsurface_depth,
+            decorations,
+            structures,
+        })
+    }
+
+    /// The decoration standing on a column, if any. `roll` is uniform in `0..1`.
+    fn decoration(&self, roll: f32) -> Option<Decoration> {
+        let mut total = 0.;
+        for decoration in self.decorations.iter() {
+            total += decoration.chance;
+            if roll < total {
+                return Some(*decoration);
+            }
+        }
+        None
+    }
+}
+
+/// Terrain made of biomes picked from temperature and humidity noise, each
+/// with its own height curve and blocks. Heights are a weighted mix of every
+/// biome's, favouring the biomes closest in climate, so borders slope
+/// smoothly instead of stepping.
+pub struct BiomeGenerator {
+    seed: u32,
+    stone: Voxel,
+    climate_scale: f32,
+    blend: f32,
+    biomes: Vec<Biome>,
+    temperature: Perlin,
+    humidity: Perlin,
+    height: Perlin,
+    /// the tallest decoration or structure and deepest subsurface of any biome
+    max_decoration: i32,
+    max_subsurface: i32,
+}
+
+/// What a column of a chunk is made of.
+#[derive(Clone, Copy)]
+struct Column {
+    height: i32,
+    biome: usize,
+    decoration: Option<Decoration>,
+}
+
+impl BiomeGenerator {
+    pub fn new(config: &BiomesConfig, seed: u32, registry: &BlockRegistry) -> Result<Self, TerrainError> {
+        let mut biomes = Vec::with_capacity(config.biomes.len());
+        for def in config.biomes.iter() {
+            biomes.push(Biome::new(def, registry)?);
+        }
+        if biomes.is_empty() {
+            return Err(TerrainError::NoBiomes);
+        }
+        let max_decoration = biomes.iter()
+            .flat_map(|biome| {
+                biome.decorations.iter().map(|decoration| decoration.height)
+                    .chain(biome.structures.iter().map(|structure| structure.reach()))
+            })
+            .max()
+            .unwrap_or(0);
+        let max_subsurface = biomes.iter().map(|biome| biome.subsurface_depth).max().unwrap_or(0);
+        Ok(BiomeGenerator {
+            seed,
+            stone: block_voxel(registry, &config.stone)?,
+            climate_scale: config.climate_scale,
+            blend: config.blend,
+            biomes,
+            temperature: Perlin::new().set_seed(seed.wrapping_add(10)),
+            humidity: Perlin::new().set_seed(seed.wrapping_add(11)),
+            height: Perlin::new().set_seed(seed),
+            max_decoration,
+            max_subsurface,
+        })
+    }
+
+    /// The temperature and humidity of a column, each from -1 to 1.
+    pub fn climate(&self, x: i32, z: i32) -> (f32, f32) {
+        let point = [x as f32 / self.climate_scale, z as f32 / self.climate_scale];
+        (self.temperature.get(point), self.humidity.get(point))
+    }
+
+    fn climate_distances(&self, x: i32, z: i32) -> Vec<f32> {
+        let (temperature, humidity) = self.climate(x, z);
+        self.biomes.iter()
+            .map(|biome| {
+                let (dt, dh) = (temperature - biome.temperature, humidity - biome.humidity);
+                dt * dt + dh * dh
+            })
+            .collect()
+    }
+
+    fn nearest(distances: &[f32]) -> usize {
+        let mut nearest = 0;
+        for (i, &distance) in distances.iter().enumerate() {
+            if distance < distances[nearest] {
+                nearest = i;
+            }
+        }
+        nearest
+    }
+
+    /// The biome a column belongs to.
+    pub fn biome_at(&self, x: i32, z: i32) -> &Biome {
+        &self.biomes[BiomeGenerator::nearest(&self.climate_distances(x, z))]
+    }
+
+    fn column(&self, x: i32, z: i32) -> Column {
+        let distances = self.climate_distances(x, z);
+        let biome = BiomeGenerator::nearest(&distances);
+
+        let height = if self.blend <= 0. {
+            self.biome_height(&self.biomes[biome], x, z)
+        } else {
+            let closest = distances[biome];
+            let mut total = 0.;
+            let mut weighted = 0.;
+            for (other, &distance) in self.biomes.iter().zip(distances.iter()) {
+                let weight = (-(distance - closest) / self.blend).exp();
+                // far off biomes don't change the height enough to be worth the noise lookup
+                if weight < 0.001 {
+                    continue;
+                }
+                total += weight;
+                weighted += weight * self.biome_height(other, x, z);
+            }
+            weighted / total
+        };
+
+        let roll = column_hash(self.seed, x, z) as f32 / (u32::max_value() as f32 + 1.);
+        Column {
+            height: height.round() as i32,
+            biome,
+            decoration: self.biomes[biome].decoration(roll),
+        }
+    }
+
+    fn biome_height(&self, biome: &Biome, x: i32, z: i32) -> f32 {
+        let n = self.height.get([x as f32 / biome.scale, z as f32 / biome.scale]);
+        biome.base + biome.amplitude * (0.5 + 0.5 * n)
+    }
+
+    /// The surface height of a column, in world blocks.
+    pub fn height(&self, x: i32, z: i32) -> i32 {
+        self.column(x, z).height
+    }
+
+    fn columns(&self, column: (i32, i32)) -> ([[Column; CHUNK_SIZE]; CHUNK_SIZE], i32, i32) {
+        let size = CHUNK_SIZE as i32;
+        let empty = Column {
+            height: 0,
+            biome: 0,
+            decoration: None,
+        };
+        let mut columns = [[empty; CHUNK_SIZE]; CHUNK_SIZE];
+        let (mut lowest, mut highest) = (i32::max_value(), i32::min_value());
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let c = self.column(column.0 * size + x as i32, column.1 * size + z as i32);
+                lowest = lowest.min(c.height);
+                highest = highest.max(c.height);
+                columns[x][z] = c;
+            }
+        }
+        (columns, lowest, highest)
+    }
+}
+
+impl TerrainGenerator for BiomeGenerator {
+    fn generate(&self, index: ChunkIndex) -> ChunkData {
+        let size = CHUNK_SIZE as i32;
+        let bottom = index.y * size;
+        let (columns, lowest, highest) = self.columns((index.x, index.z));
+
+        if bottom >= highest + self.max_decoration {
+            return ChunkData::default();
+        }
+        if bottom + size <= lowest - self.max_subsurface - 1 {
+            return ChunkData::filled(self.stone);
+        }
+
+        let mut chunk_data = ChunkData::default();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let column = columns[x][z];
+                let biome = &self.biomes[column.biome];
+                for y in 0..CHUNK_SIZE {
+                    let world_y = bottom + y as i32;
+                    let depth = column.height - 1 - world_y;
+                    let voxel = if depth > biome.subsurface_depth {
+                        self.stone
+                    } else if depth > 0 {
+                        biome.subsurface
+                    } else if depth == 0 {
+                        biome.surface
+                    } else {
+                        match column.decoration {
+                            Some(decoration) if -depth <= decoration.height => decoration.voxel,
+                            _ => break,
+                        }
+                    };
+                    chunk_data.set_voxel((x, y, z), voxel);
+                }
+            }
+        }
+        chunk_data
+    }
+
+    fn surface_layers(&self, column: (i32, i32)) -> Option<(i32, i32)> {
+        let (_, lowest, highest) = self.columns(column);
+        Some((layer_of(lowest - 1), layer_of(highest + self.max_decoration - 1)))
+    }
+
+    fn biome_at(&self, x: i32, z: i32) -> Option<&Biome> {
+        Some(BiomeGenerator::biome_at(self, x, z))
+    }
+
+    fn features(&self, index: ChunkIndex) -> Vec<((i32, i32, i32), Voxel)> {
+        let size = CHUNK_SIZE as i32;
+        let mut rng = FeatureRng::new(self.seed, index.into());
+        let mut blocks = Vec::new();
+        for _ in 0..STRUCTURE_ATTEMPTS {
+            let x = index.x * size + rng.range(0, size);
+            let z = index.z * size + rng.range(0, size);
+            let roll = rng.next_f32();
+            let column = self.column(x, z);
+            // a structure belongs to the chunk holding the block it stands on,
+            // so exactly one chunk places it however far it spreads
+            if layer_of(column.height - 1) != index.y {
+                continue;
+            }
+            let mut total = 0.;
+            for structure in self.biomes[column.biome].structures.iter() {
+                total += structure.per_chunk / STRUCTURE_ATTEMPTS as f32;
+                if roll < total {
+                    blocks.extend(structure.place((x, column.height - 1, z), &mut rng));
+                    break;
+                }
+            }
+        }
+        blocks
+    }
+}
+
+/// Spots tried for structures in each chunk, which caps `per_chunk`.
+const STRUCTURE_ATTEMPTS: usize = 16;
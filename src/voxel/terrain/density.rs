@@ -0,0 +1,119 @@
+use super::{TerrainGenerator, TerrainError, layer_of};
+use super::heightmap::{HeightmapConfig, HeightmapGenerator, height_range};
+use super::super::{ChunkData, ChunkIndex, CHUNK_SIZE};
+use super::super::block::BlockRegistry;
+
+use noise::{NoiseModule, Perlin, Seedable};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DensityConfig {
+    /// The heightmap the terrain is shaped around.
+    pub surface: HeightmapConfig,
+    /// How far, in blocks, 3D noise can push the ground above or below the
+    /// heightmap. Zero gives plain heightmap terrain.
+    pub overhang: f32,
+    /// The size, in blocks, of overhang features.
+    pub overhang_scale: f32,
+    /// The size, in blocks, of the cave network. Zero turns caves off.
+    pub cave_scale: f32,
+    /// How wide tunnels are, from 0 (none) to 1 (everything).
+    pub cave_width: f32,
+}
+
+impl Default for DensityConfig {
+    fn default() -> Self {
+        DensityConfig {
+            surface: HeightmapConfig::default(),
+            overhang: 12.,
+            overhang_scale: 32.,
+            cave_scale: 48.,
+            cave_width: 0.08,
+        }
+    }
+}
+
+/// Decides solidity per voxel from a density: the height above the heightmap
+/// surface, offset by 3D noise so the ground can lean out into overhangs and
+/// arches. Caves are carved where two further noise fields are both near
+/// zero, which traces out long winding tunnels.
+pub struct DensityGenerator {
+    surface: HeightmapGenerator,
+    overhang: f32,
+    overhang_scale: f32,
+    overhang_noise: Perlin,
+    cave_scale: f32,
+    cave_width: f32,
+    cave_noise: (Perlin, Perlin),
+}
+
+impl DensityGenerator {
+    pub fn new(config: &DensityConfig, seed: u32, registry: &BlockRegistry) -> Result<Self, TerrainError> {
+        Ok(DensityGenerator {
+            surface: HeightmapGenerator::new(&config.surface, seed, registry)?,
+            overhang: config.overhang,
+            overhang_scale: config.overhang_scale,
+            overhang_noise: Perlin::new().set_seed(seed.wrapping_add(1)),
+            cave_scale: config.cave_scale,
+            cave_width: config.cave_width,
+            cave_noise: (
+                Perlin::new().set_seed(seed.wrapping_add(2)),
+                Perlin::new().set_seed(seed.wrapping_add(3)),
+            ),
+        })
+    }
+
+    /// Whether a voxel is ground, given its column's surface height.
+    fn solid(&self, (x, y, z): (i32, i32, i32), height: i32) -> bool {
+        let mut density = (height - y) as f32;
+        if self.overhang > 0. {
+            let s = self.overhang_scale;
+            density += self.overhang * self.overhang_noise.get([x as f32 / s, y as f32 / s, z as f32 / s]);
+        }
+        density > 0. && !self.cave((x, y, z))
+    }
+
+    fn cave(&self, (x, y, z): (i32, i32, i32)) -> bool {
+        if self.cave_scale <= 0. {
+            return false;
+        }
+        let s = self.cave_scale;
+        // squashed vertically so tunnels run more across than down
+        let point = [x as f32 / s, y as f32 / (s * 0.5), z as f32 / s];
+        self.cave_noise.0.get(point).abs() < self.cave_width
+            && self.cave_noise.1.get(point).abs() < self.cave_width
+    }
+}
+
+impl TerrainGenerator for DensityGenerator {
+    fn generate(&self, index: ChunkIndex) -> ChunkData {
+        let size = CHUNK_SIZE as i32;
+        let bottom = index.y * size;
+        let heights = self.surface.column_heights((index.x, index.z));
+        let (_, highest) = height_range(&heights);
+
+        // nothing up here however far the ground leans
+        if bottom as f32 >= highest as f32 + self.overhang {
+            return ChunkData::default();
+        }
+
+        let voxel = self.surface.voxel();
+        let mut chunk_data = ChunkData::default();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for y in 0..CHUNK_SIZE {
+                    let position = (index.x * size + x as i32, bottom + y as i32, index.z * size + z as i32);
+                    if self.solid(position, heights[x][z]) {
+                        chunk_data.set_voxel((x, y, z), voxel);
+                    }
+                }
+            }
+        }
+        chunk_data
+    }
+
+    fn surface_layers(&self, column: (i32, i32)) -> Option<(i32, i32)> {
+        let (lowest, highest) = height_range(&self.surface.column_heights(column));
+        let reach = self.overhang.ceil() as i32;
+        Some((layer_of(lowest - reach - 1), layer_of(highest + reach - 1)))
+    }
+}
@@ -0,0 +1,114 @@
+use super::{TerrainGenerator, TerrainError, block_voxel, layer_of};
+use super::super::{ChunkData, ChunkIndex, Voxel, CHUNK_SIZE};
+use super::super::block::BlockRegistry;
+
+use noise::{NoiseModule, Perlin, Seedable};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HeightmapConfig {
+    pub block: String,
+    /// The lowest the surface goes.
+    pub base: f32,
+    /// How far above `base` the surface reaches at most.
+    pub amplitude: f32,
+    /// The width, in blocks, of one unit of noise. Larger is smoother.
+    pub scale: f32,
+}
+
+impl Default for HeightmapConfig {
+    fn default() -> Self {
+        HeightmapConfig {
+            block: "stone".to_string(),
+            base: 16.,
+            amplitude: 160.,
+            scale: 256.,
+        }
+    }
+}
+
+/// Rolling hills: each column is solid up to a height taken from 2D Perlin noise.
+pub struct HeightmapGenerator {
+    voxel: Voxel,
+    base: f32,
+    amplitude: f32,
+    scale: f32,
+    noise: Perlin,
+}
+
+impl HeightmapGenerator {
+    pub fn new(config: &HeightmapConfig, seed: u32, registry: &BlockRegistry) -> Result<Self, TerrainError> {
+        Ok(HeightmapGenerator {
+            voxel: block_voxel(registry, &config.block)?,
+            base: config.base,
+            amplitude: config.amplitude,
+            scale: config.scale,
+            noise: Perlin::new().set_seed(seed),
+        })
+    }
+
+    /// The surface height of a column, in world blocks.
+    pub fn height(&self, x: i32, z: i32) -> i32 {
+        let n = self.noise.get([x as f32 / self.scale, z as f32 / self.scale]);
+        (self.base + self.amplitude * (0.5 + 0.5 * n)).round() as i32
+    }
+
+    /// The block the terrain is made of.
+    pub fn voxel(&self) -> Voxel {
+        self.voxel
+    }
+
+    /// The heights of a chunk column, indexed `[x][z]`.
+    pub fn column_heights(&self, column: (i32, i32)) -> [[i32; CHUNK_SIZE]; CHUNK_SIZE] {
+        let size = CHUNK_SIZE as i32;
+        let mut heights = [[0; CHUNK_SIZE]; CHUNK_SIZE];
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                heights[x][z] = self.height(column.0 * size + x as i32, column.1 * size + z as i32);
+            }
+        }
+        heights
+    }
+}
+
+/// The lowest and highest of a chunk column's heights.
+pub fn height_range(heights: &[[i32; CHUNK_SIZE]; CHUNK_SIZE]) -> (i32, i32) {
+    let mut range = (i32::max_value(), i32::min_value());
+    for row in heights.iter() {
+        for &height in row.iter() {
+            range = (range.0.min(height), range.1.max(height));
+        }
+    }
+    range
+}
+
+impl TerrainGenerator for HeightmapGenerator {
+    fn generate(&self, index: ChunkIndex) -> ChunkData {
+        let size = CHUNK_SIZE as i32;
+        let bottom = index.y * size;
+        let heights = self.column_heights((index.x, index.z));
+        let (lowest, highest) = height_range(&heights);
+
+        if bottom >= highest {
+            return ChunkData::default();
+        }
+        if bottom + size <= lowest {
+            return ChunkData::filled(self.voxel);
+        }
+
+        let mut chunk_data = ChunkData::default();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let top = (heights[x][z] - bottom).max(0).min(size) as usize;
+                for y in 0..top {
+                    chunk_data.set_voxel((x, y, z), self.voxel);
+                }
+            }
+        }
+        chunk_data
+    }
+
+    fn surface_layers(&self, column: (i32, i32)) -> Option<(i32, i32)> {
+        let (lowest, highest) = height_range(&self.column_heights(column));
+        Some((layer_of(lowest - 1), layer_of(highest - 1)))
+    }
+}
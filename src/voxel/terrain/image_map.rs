@@ -0,0 +1,225 @@
+use super::{TerrainGenerator, TerrainError, block_voxel, layer_of};
+use super::super::{ChunkData, ChunkIndex, Voxel, CHUNK_SIZE};
+use super::super::block::BlockRegistry;
+
+use image::{self, GrayImage, RgbImage};
+
+/// Terrain read from a greyscale heightmap image, as written in the terrain
+/// config. Any format the `image` crate reads works, PNG and PGM included.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImageMapConfig {
+    pub heightmap: String,
+    /// A colour image the same shape as the heightmap, picking each column's
+    /// blocks by the closest colour in `materials`.
+    #[serde(default)]
+    pub material_map: Option<String>,
+    #[serde(default)]
+    pub materials: Vec<ImageMaterialDef>,
+    /// The block columns are made of, and the whole column where there's no
+    /// material map.
+    pub block: String,
+    /// The height of black pixels.
+    pub base: f32,
+    /// Blocks of height per grey level.
+    pub vertical_scale: f32,
+    /// Blocks per pixel. Heights are interpolated between pixels, so scaled
+    /// up images give smooth slopes rather than steps.
+    pub horizontal_scale: f32,
+    /// The world x and z of the image's top left corner.
+    #[serde(default)]
+    pub origin: (i32, i32),
+    /// Repeat the image forever instead of leaving empty space around it.
+    #[serde(default)]
+    pub tile: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImageMaterialDef {
+    pub color: (u8, u8, u8),
+    pub surface: String,
+    pub subsurface: String,
+    pub subsurface_depth: i32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Material {
+    color: (u8, u8, u8),
+    surface: Voxel,
+    subsurface: Voxel,
+    subsurface_depth: i32,
+}
+
+/// Builds terrain from images authored in an image editor, with each chunk
+/// column reading its own patch of pixels.
+pub struct ImageMapGenerator {
+    heights: GrayImage,
+    materials_image: Option<RgbImage>,
+    materials: Vec<Material>,
+    voxel: Voxel,
+    base: f32,
+    vertical_scale: f32,
+    horizontal_scale: f32,
+    origin: (i32, i32),
+    tile: bool,
+}
+
+fn open_image(path: &str) -> Result<image::DynamicImage, TerrainError> {
+    image::open(path).map_err(|e| TerrainError::Image(path.to_string(), e))
+}
+
+impl ImageMapGenerator {
+    pub fn new(config: &ImageMapConfig, registry: &BlockRegistry) -> Result<Self, TerrainError> {
+        let heights = open_image(&config.heightmap)?.to_luma();
+        let materials_image = match config.material_map {
+            Some(ref path) => Some(open_image(path)?.to_rgb()),
+            None => None,
+        };
+        let mut materials = Vec::with_capacity(config.materials.len());
+        for def in config.materials.iter() {
+            materials.push(Material {
+                color: def.color,
+                surface: block_voxel(registry, &def.surface)?,
+                subsurface: block_voxel(registry, &def.subsurface)?,
+                subsurface_depth: def.subsurface_depth,
+            });
+        }
+        Ok(ImageMapGenerator {
+            heights,
+            materials_image,
+            materials,
+            voxel: block_voxel(registry, &config.block)?,
+            base: config.base,
+            vertical_scale: config.vertical_scale,
+            horizontal_scale: config.horizontal_scale.max(1. / 256.),
+            origin: config.origin,
+            tile: config.tile,
+        })
+    }
+
+    /// A world position in pixels, or `None` off the edge of an untiled image.
+    fn pixel_position(&self, x: i32, z: i32) -> Option<(f32, f32)> {
+        let (width, height) = self.heights.dimensions();
+        let mut px = (x - self.origin.0) as f32 / self.horizontal_scale;
+        let mut pz = (z - self.origin.1) as f32 / self.horizontal_scale;
+        if self.tile {
+            px = px.rem_euclid(width as f32);
+            pz = pz.rem_euclid(height as f32);
+        } else if px < 0. || pz < 0. || px >= width as f32 || pz >= height as f32 {
+            return None;
+        }
+        Some((px, pz))
+    }
+
+    fn pixel_index(&self, p: i32, size: u32) -> u32 {
+        if self.tile {
+            p.rem_euclid(size as i32) as u32
+        } else {
+            p.max(0).min(size as i32 - 1) as u32
+        }
+    }
+
+    /// The surface height of a column, in world blocks, or `None` off the
+    /// edge of an untiled image.
+    pub fn height(&self, x: i32, z: i32) -> Option<i32> {
+        let (px, pz) = self.pixel_position(x, z)?;
+        let (width, height) = self.heights.dimensions();
+        let (x0, z0) = (px.floor() as i32, pz.floor() as i32);
+        let (fx, fz) = (px - x0 as f32, pz - z0 as f32);
+        let sample = |dx: i32, dz: i32| {
+            let x = self.pixel_index(x0 + dx, width);
+            let z = self.pixel_index(z0 + dz, height);
+            self.heights.get_pixel(x, z).data[0] as f32
+        };
+        let top = sample(0, 0) * (1. - fx) + sample(1, 0) * fx;
+        let bottom = sample(0, 1) * (1. - fx) + sample(1, 1) * fx;
+        let level = top * (1. - fz) + bottom * fz;
+        Some((self.base + level * self.vertical_scale).round() as i32)
+    }
+
+    fn material(&self, x: i32, z: i32) -> Option<Material> {
+        let image = self.materials_image.as_ref()?;
+        let (px, pz) = self.pixel_position(x, z)?;
+        // the material map may be a different size, so it's sampled by proportion
+        let (width, height) = self.heights.dimensions();
+        let (mw, mh) = image.dimensions();
+        let mx = ((px / width as f32) * mw as f32) as u32;
+        let mz = ((pz / height as f32) * mh as f32) as u32;
+        let pixel = image.get_pixel(mx.min(mw - 1), mz.min(mh - 1)).data;
+
+        let distance = |color: (u8, u8, u8)| {
+            let d = |a: u8, b: u8| (a as i32 - b as i32) * (a as i32 - b as i32);
+            d(color.0, pixel[0]) + d(color.1, pixel[1]) + d(color.2, pixel[2])
+        };
+        self.materials.iter()
+            .min_by_key(|material| distance(material.color))
+            .cloned()
+    }
+
+    fn column_heights(&self, column: (i32, i32)) -> [[Option<i32>; CHUNK_SIZE]; CHUNK_SIZE] {
+        let size = CHUNK_SIZE as i32;
+        let mut heights = [[None; CHUNK_SIZE]; CHUNK_SIZE];
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                heights[x][z] = self.height(column.0 * size + x as i32, column.1 * size + z as i32);
+            }
+        }
+        heights
+    }
+}
+
+fn height_range(heights: &[[Option<i32>; CHUNK_SIZE]; CHUNK_SIZE]) -> Option<(i32, i32)> {
+    heights.iter()
+        .flat_map(|row| row.iter().filter_map(|&height| height))
+        .fold(None, |range, height| match range {
+            Some((lowest, highest)) => Some((height.min(lowest), height.max(highest))),
+            None => Some((height, height)),
+        })
+}
+
+impl TerrainGenerator for ImageMapGenerator {
+    fn generate(&self, index: ChunkIndex) -> ChunkData {
+        let size = CHUNK_SIZE as i32;
+        let bottom = index.y * size;
+        let heights = self.column_heights((index.x, index.z));
+        let (lowest, highest) = match height_range(&heights) {
+            Some(range) => range,
+            None => return ChunkData::default(),
+        };
+
+        if bottom >= highest {
+            return ChunkData::default();
+        }
+        let max_subsurface = self.materials.iter().map(|material| material.subsurface_depth).max().unwrap_or(0);
+        let complete = heights.iter().all(|row| row.iter().all(|height| height.is_some()));
+        if complete && bottom + size <= lowest - max_subsurface - 1 {
+            return ChunkData::filled(self.voxel);
+        }
+
+        let mut chunk_data = ChunkData::default();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let height = match heights[x][z] {
+                    Some(height) => height,
+                    None => continue,
+                };
+                let material = self.material(index.x * size + x as i32, index.z * size + z as i32);
+                let top = (height - bottom).max(0).min(size) as usize;
+                for y in 0..top {
+                    let depth = height - 1 - (bottom + y as i32);
+                    let voxel = match material {
+                        Some(material) if depth == 0 => material.surface,
+                        Some(material) if depth <= material.subsurface_depth => material.subsurface,
+                        _ => self.voxel,
+                    };
+                    chunk_data.set_voxel((x, y, z), voxel);
+                }
+            }
+        }
+        chunk_data
+    }
+
+    fn surface_layers(&self, column: (i32, i32)) -> Option<(i32, i32)> {
+        height_range(&self.column_heights(column))
+            .map(|(lowest, highest)| (layer_of(lowest - 1), layer_of(highest - 1)))
+    }
+}
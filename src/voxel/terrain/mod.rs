@@ -0,0 +1,183 @@
+pub mod heightmap;
+pub mod density;
+pub mod biome;
+pub mod structure;
+pub mod image_map;
+
+pub use self::heightmap::{HeightmapConfig, HeightmapGenerator};
+pub use self::density::{DensityConfig, DensityGenerator};
+pub use self::biome::{Biome, BiomeDef, BiomesConfig, BiomeGenerator, Decoration, DecorationDef};
+pub use self::structure::{Structure, StructureDef, Shape, ShapeDef, FeatureRng};
+pub use self::image_map::{ImageMapConfig, ImageMaterialDef, ImageMapGenerator};
+
+use super::{ChunkData, ChunkIndex, Voxel, CHUNK_SIZE, world_to_chunk};
+use super::block::BlockRegistry;
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use ron;
+use image;
+
+/// Produces the initial data of chunks that have never been saved.
+///
+/// Generators must be deterministic: the same seed and chunk index always give
+/// the same chunk, whichever order chunks are asked for in.
+pub trait TerrainGenerator: Send + Sync {
+    fn generate(&self, index: ChunkIndex) -> ChunkData;
+
+    /// The lowest and highest chunk layers holding the surface of a chunk
+    /// column, so streaming can load the ground far from any loader without
+    /// loading the sky above it or the rock below. `None` if the generator
+    /// can't tell, in which case only layers near loaders are loaded.
+    fn surface_layers(&self, _column: (i32, i32)) -> Option<(i32, i32)> {
+        None
+    }
+
+    /// The biome of a world column, for generators that have biomes.
+    fn biome_at(&self, _x: i32, _z: i32) -> Option<&Biome> {
+        None
+    }
+
+    /// Blocks of the multi-block features (trees, boulders...) that start in
+    /// a chunk, in world coordinates. They can spill into neighbouring chunks;
+    /// streaming takes care of getting each block to its chunk. Only called
+    /// once, when the chunk is first generated.
+    fn features(&self, _index: ChunkIndex) -> Vec<((i32, i32, i32), Voxel)> {
+        Vec::new()
+    }
+}
+
+/// The chunk layer holding a world y coordinate.
+pub fn layer_of(y: i32) -> i32 {
+    let (chunk, _) = world_to_chunk((0, y, 0));
+    chunk.1
+}
+
+/// A well mixed hash of a world column, for choices that must come out the
+/// same whichever chunk asks.
+pub fn column_hash(seed: u32, x: i32, z: i32) -> u32 {
+    let mut h = seed ^ (x as u32).wrapping_mul(0x9E37_79B1) ^ (z as u32).wrapping_mul(0x85EB_CA77);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7FEB_352D);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846C_A68B);
+    h ^= h >> 16;
+    h
+}
+
+/// Which generator a world uses and how it's set up, as saved in the world
+/// directory.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TerrainConfig {
+    /// Rolling hills from 2D noise.
+    Heightmap(HeightmapConfig),
+    /// Hills bent by 3D noise into overhangs, with caves carved through them.
+    Density(DensityConfig),
+    /// Terrain split into biomes by climate noise.
+    Biomes(BiomesConfig),
+    /// Terrain read from a heightmap image.
+    ImageMap(ImageMapConfig),
+    /// Every column filled with `block` up to `height`.
+    Flat {
+        block: String,
+        height: i32,
+    },
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        TerrainConfig::Biomes(BiomesConfig::default())
+    }
+}
+
+impl TerrainConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, TerrainError> {
+        let contents = fs::read_to_string(path).map_err(TerrainError::Io)?;
+        ron::de::from_str(&contents).map_err(TerrainError::Parse)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), TerrainError> {
+        let contents = ron::ser::to_string_pretty(self, Default::default()).map_err(TerrainError::Serialize)?;
+        fs::write(path, contents).map_err(TerrainError::Io)
+    }
+
+    /// Builds the configured generator for a world with the given seed.
+    pub fn build(&self, seed: u32, registry: &BlockRegistry) -> Result<Box<TerrainGenerator>, TerrainError> {
+        Ok(match *self {
+            TerrainConfig::Heightmap(ref config) => Box::new(HeightmapGenerator::new(config, seed, registry)?),
+            TerrainConfig::Density(ref config) => Box::new(DensityGenerator::new(config, seed, registry)?),
+            TerrainConfig::Biomes(ref config) => Box::new(BiomeGenerator::new(config, seed, registry)?),
+            TerrainConfig::ImageMap(ref config) => Box::new(ImageMapGenerator::new(config, registry)?),
+            TerrainConfig::Flat { ref block, height } => Box::new(FlatGenerator {
+                voxel: block_voxel(registry, block)?,
+                height,
+            }),
+        })
+    }
+}
+
+/// Looks up a block a generator config names.
+pub fn block_voxel(registry: &BlockRegistry, name: &str) -> Result<Voxel, TerrainError> {
+    registry.voxel(name).ok_or_else(|| TerrainError::UnknownBlock(name.to_string()))
+}
+
+#[derive(Debug)]
+pub enum TerrainError {
+    Io(io::Error),
+    Parse(ron::de::Error),
+    Serialize(ron::ser::Error),
+    UnknownBlock(String),
+    NoBiomes,
+    Image(String, image::ImageError),
+}
+
+impl fmt::Display for TerrainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TerrainError::Io(ref e) => write!(f, "failed to access terrain config: {}", e),
+            TerrainError::Parse(ref e) => write!(f, "failed to parse terrain config: {}", e),
+            TerrainError::Serialize(ref e) => write!(f, "failed to write terrain config: {}", e),
+            TerrainError::UnknownBlock(ref name) => write!(f, "terrain config names unknown block {:?}", name),
+            TerrainError::NoBiomes => write!(f, "terrain config has no biomes"),
+            TerrainError::Image(ref path, ref e) => write!(f, "failed to load terrain image {:?}: {}", path, e),
+        }
+    }
+}
+
+impl Error for TerrainError {}
+
+/// Fills every column up to a fixed height.
+pub struct FlatGenerator {
+    pub voxel: Voxel,
+    pub height: i32,
+}
+
+impl TerrainGenerator for FlatGenerator {
+    fn generate(&self, index: ChunkIndex) -> ChunkData {
+        let bottom = index.y * CHUNK_SIZE as i32;
+        if bottom + CHUNK_SIZE as i32 <= self.height {
+            return ChunkData::filled(self.voxel);
+        }
+        let mut chunk_data = ChunkData::default();
+        for y in 0..CHUNK_SIZE {
+            if bottom + y as i32 >= self.height {
+                break;
+            }
+            for z in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    chunk_data.set_voxel((x, y, z), self.voxel);
+                }
+            }
+        }
+        chunk_data
+    }
+
+    fn surface_layers(&self, _column: (i32, i32)) -> Option<(i32, i32)> {
+        let layer = layer_of(self.height - 1);
+        Some((layer, layer))
+    }
+}
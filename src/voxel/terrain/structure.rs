@@ -0,0 +1,156 @@
+use super::{TerrainError, block_voxel, column_hash};
+use super::super::Voxel;
+use super::super::block::BlockRegistry;
+
+/// A multi-block feature a biome scatters over its surface, as written in the
+/// terrain config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StructureDef {
+    pub shape: ShapeDef,
+    /// How many of these an average chunk of the biome gets.
+    pub per_chunk: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ShapeDef {
+    /// A trunk with a round crown of leaves on top.
+    Tree {
+        trunk: String,
+        leaves: String,
+        min_height: i32,
+        max_height: i32,
+        radius: i32,
+    },
+    /// A lump of rock half sunk into the ground.
+    Boulder {
+        block: String,
+        radius: i32,
+    },
+}
+
+/// A structure with its blocks looked up.
+#[derive(Clone, Debug)]
+pub struct Structure {
+    pub shape: Shape,
+    pub per_chunk: f32,
+}
+
+#[derive(Clone, Debug)]
+pub enum Shape {
+    Tree {
+        trunk: Voxel,
+        leaves: Voxel,
+        min_height: i32,
+        max_height: i32,
+        radius: i32,
+    },
+    Boulder {
+        block: Voxel,
+        radius: i32,
+    },
+}
+
+impl Structure {
+    pub fn new(def: &StructureDef, registry: &BlockRegistry) -> Result<Self, TerrainError> {
+        let shape = match def.shape {
+            ShapeDef::Tree { ref trunk, ref leaves, min_height, max_height, radius } => Shape::Tree {
+                trunk: block_voxel(registry, trunk)?,
+                leaves: block_voxel(registry, leaves)?,
+                min_height,
+                max_height: max_height.max(min_height),
+                radius,
+            },
+            ShapeDef::Boulder { ref block, radius } => Shape::Boulder {
+                block: block_voxel(registry, block)?,
+                radius,
+            },
+        };
+        Ok(Structure {
+            shape,
+            per_chunk: def.per_chunk,
+        })
+    }
+
+    /// How far above the surface the structure can reach.
+    pub fn reach(&self) -> i32 {
+        match self.shape {
+            Shape::Tree { max_height, radius, .. } => max_height + radius,
+            Shape::Boulder { radius, .. } => radius,
+        }
+    }
+
+    /// The blocks of one instance standing on the surface block at `base`,
+    /// in world coordinates.
+    pub fn place(&self, base: (i32, i32, i32), rng: &mut FeatureRng) -> Vec<((i32, i32, i32), Voxel)> {
+        let (x, y, z) = base;
+        let mut blocks = Vec::new();
+        match self.shape {
+            Shape::Tree { trunk, leaves, min_height, max_height, radius } => {
+                let height = rng.range(min_height, max_height + 1);
+                for dy in 1..(height + 1) {
+                    blocks.push(((x, y + dy, z), trunk));
+                }
+                let top = y + height;
+                for dx in -radius..(radius + 1) {
+                    for dy in -radius..(radius + 1) {
+                        for dz in -radius..(radius + 1) {
+                            let inside = dx * dx + dy * dy + dz * dz <= radius * radius;
+                            let on_trunk = dx == 0 && dz == 0 && dy <= 0;
+                            // ragged edges so every crown isn't the same ball
+                            let trimmed = dx * dx + dy * dy + dz * dz == radius * radius && rng.chance(0.5);
+                            if inside && !on_trunk && !trimmed {
+                                blocks.push(((x + dx, top + dy, z + dz), leaves));
+                            }
+                        }
+                    }
+                }
+            },
+            Shape::Boulder { block, radius } => {
+                let r = radius as f32 + rng.next_f32() * 0.5;
+                for dx in -radius..(radius + 1) {
+                    for dy in -radius..(radius + 1) {
+                        for dz in -radius..(radius + 1) {
+                            let d = (dx * dx + dy * dy + dz * dz) as f32;
+                            if d <= r * r {
+                                blocks.push(((x + dx, y + dy, z + dz), block));
+                            }
+                        }
+                    }
+                }
+            },
+        }
+        blocks
+    }
+}
+
+/// A small deterministic random source for placing features, so the same
+/// seed and chunk always grow the same structures.
+pub struct FeatureRng(u32);
+
+impl FeatureRng {
+    pub fn new(seed: u32, chunk: (i32, i32, i32)) -> Self {
+        FeatureRng(column_hash(seed ^ (chunk.1 as u32).wrapping_mul(0xC2B2_AE3D), chunk.0, chunk.2))
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9);
+        column_hash(self.0, 0, 0)
+    }
+
+    /// Uniform in `0..1`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// Uniform in `low..high`.
+    pub fn range(&mut self, low: i32, high: i32) -> i32 {
+        if high <= low {
+            return low;
+        }
+        low + (self.next_u32() % (high - low) as u32) as i32
+    }
+
+    pub fn chance(&mut self, p: f32) -> bool {
+        self.next_f32() < p
+    }
+}
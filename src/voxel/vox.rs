@@ -0,0 +1,776 @@
+//! Reading and writing MagicaVoxel `.vox` files.
+//!
+//! A file is `b"VOX "`, a version (`150`) and a `MAIN` chunk whose children
+//! hold the models and scene. Every chunk is
+//!
+//! ```text
+//! id             [u8; 4]
+//! content bytes  i32
+//! children bytes i32
+//! content
+//! children
+//! ```
+//!
+//! Models are a `SIZE` chunk followed by an `XYZI` chunk listing
+//! `(x, y, z, colour index)` bytes. `RGBA` holds the palette, entry `i` being
+//! colour index `i + 1`. Newer files place models with a scene graph of
+//! transform (`nTRN`), group (`nGRP`) and shape (`nSHP`) nodes; files without
+//! one have a single model at the origin.
+//!
+//! MagicaVoxel is z-up, so its `(x, y, z)` is our `(x, z, -y)`.
+
+use super::{ChunkData, Voxel, AIR, world_to_chunk};
+use super::block::BlockRegistry;
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use fnv::FnvHashMap;
+
+pub const VOX_MAGIC: &'static [u8; 4] = b"VOX ";
+pub const VOX_VERSION: i32 = 150;
+
+/// The largest a model can be along each axis.
+pub const VOX_MAX_SIZE: i32 = 256;
+
+#[derive(Debug)]
+pub enum VoxError {
+    Io(io::Error),
+    BadMagic,
+    /// The file ends early or a chunk doesn't parse.
+    Corrupt(&'static str),
+    /// An export names more distinct blocks than the 255 palette colours.
+    TooManyBlocks,
+}
+
+impl fmt::Display for VoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VoxError::Io(ref e) => write!(f, "vox i/o error: {}", e),
+            VoxError::BadMagic => write!(f, "not a vox file"),
+            VoxError::Corrupt(what) => write!(f, "corrupt vox file: {}", what),
+            VoxError::TooManyBlocks => write!(f, "more than 255 different blocks to export"),
+        }
+    }
+}
+
+impl Error for VoxError {}
+
+impl From<io::Error> for VoxError {
+    fn from(e: io::Error) -> VoxError {
+        VoxError::Io(e)
+    }
+}
+
+/// One model, in MagicaVoxel's coordinates.
+#[derive(Clone, Debug, Default)]
+pub struct VoxModel {
+    pub size: (i32, i32, i32),
+    /// `(x, y, z, colour index)`
+    pub voxels: Vec<(u8, u8, u8, u8)>,
+}
+
+/// A rotation made of axis swaps and flips plus a translation, as stored on
+/// transform nodes. Applies to MagicaVoxel coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxTransform {
+    pub rotation: [[i32; 3]; 3],
+    pub translation: (i32, i32, i32),
+}
+
+impl Default for VoxTransform {
+    fn default() -> Self {
+        VoxTransform {
+            rotation: [[1, 0, 0], [0, 1, 0], [0, 0, 1]],
+            translation: (0, 0, 0),
+        }
+    }
+}
+
+impl VoxTransform {
+    /// Decodes the packed rotation byte: bits 0-1 and 2-3 give the column of
+    /// the non-zero entry in rows one and two, bits 4-6 the signs of the rows.
+    pub fn rotation_from_byte(byte: u8) -> [[i32; 3]; 3] {
+        let first = (byte & 0b11) as usize;
+        let second = ((byte >> 2) & 0b11) as usize;
+        // the third row takes whichever column is left
+        let third = 3usize.saturating_sub(first + second);
+        let mut rotation = [[0; 3]; 3];
+        for (row, &column) in [first, second, third].iter().enumerate() {
+            let sign = if byte & (1 << (4 + row)) != 0 { -1 } else { 1 };
+            rotation[row][column.min(2)] = sign;
+        }
+        rotation
+    }
+
+    fn apply(&self, p: (i32, i32, i32)) -> (i32, i32, i32) {
+        let r = &self.rotation;
+        let t = self.translation;
+        (
+            r[0][0] * p.0 + r[0][1] * p.1 + r[0][2] * p.2 + t.0,
+            r[1][0] * p.0 + r[1][1] * p.1 + r[1][2] * p.2 + t.1,
+            r[2][0] * p.0 + r[2][1] * p.1 + r[2][2] * p.2 + t.2,
+        )
+    }
+
+    /// This transform applied after `child`.
+    fn then(&self, child: &VoxTransform) -> VoxTransform {
+        let mut rotation = [[0; 3]; 3];
+        for i in 0..3 {
+            for j in 0..3 {
+                rotation[i][j] = (0..3).map(|k| self.rotation[i][k] * child.rotation[k][j]).sum();
+            }
+        }
+        let zero = VoxTransform { rotation: self.rotation, translation: (0, 0, 0) };
+        let t = zero.apply(child.translation);
+        VoxTransform {
+            rotation,
+            translation: (t.0 + self.translation.0, t.1 + self.translation.1, t.2 + self.translation.2),
+        }
+    }
+}
+
+/// A model placed in the scene.
+#[derive(Clone, Copy, Debug)]
+pub struct VoxInstance {
+    pub model: usize,
+    pub transform: VoxTransform,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Transform { child: i32, transform: VoxTransform },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<i32> },
+}
+
+/// The contents of a `.vox` file.
+#[derive(Clone, Debug)]
+pub struct VoxFile {
+    pub models: Vec<VoxModel>,
+    /// RGBA colours; index 0 is unused, as colour index 0 means empty.
+    pub palette: Vec<[u8; 4]>,
+    /// Where each model sits in the scene. Every model at the origin for
+    /// files without a scene graph.
+    pub instances: Vec<VoxInstance>,
+}
+
+impl VoxFile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<VoxFile, VoxError> {
+        VoxFile::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<VoxFile, VoxError> {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        if contents.len() < 8 || &contents[0..4] != VOX_MAGIC {
+            return Err(VoxError::BadMagic);
+        }
+
+        let mut main = Cursor::new(&contents[8..]);
+        let (id, _, children) = main.chunk()?;
+        if &id != b"MAIN" {
+            return Err(VoxError::Corrupt("missing MAIN chunk"));
+        }
+
+        let mut models = Vec::new();
+        let mut palette = None;
+        let mut nodes = FnvHashMap::default();
+        let mut size = None;
+        let mut chunks = Cursor::new(children);
+        while !chunks.is_empty() {
+            let (id, content, _) = chunks.chunk()?;
+            let mut content = Cursor::new(content);
+            match &id {
+                b"SIZE" => size = Some((content.i32()?, content.i32()?, content.i32()?)),
+                b"XYZI" => {
+                    let size = size.take().ok_or(VoxError::Corrupt("XYZI without SIZE"))?;
+                    let count = content.i32()?.max(0) as usize;
+                    let bytes = content.bytes(count * 4)?;
+                    models.push(VoxModel {
+                        size,
+                        voxels: bytes.chunks(4).map(|v| (v[0], v[1], v[2], v[3])).collect(),
+                    });
+                },
+                b"RGBA" => {
+                    let bytes = content.bytes(256 * 4)?;
+                    let mut colours = vec![[0; 4]];
+                    colours.extend(bytes.chunks(4).take(255).map(|c| [c[0], c[1], c[2], c[3]]));
+                    palette = Some(colours);
+                },
+                b"nTRN" => {
+                    let id = content.i32()?;
+                    content.dict()?;
+                    let child = content.i32()?;
+                    content.i32()?; // reserved
+                    content.i32()?; // layer
+                    let frames = content.i32()?;
+                    let mut transform = VoxTransform::default();
+                    if frames > 0 {
+                        let frame = content.dict()?;
+                        if let Some(r) = frame.get("_r").and_then(|r| r.parse().ok()) {
+                            transform.rotation = VoxTransform::rotation_from_byte(r);
+                        }
+                        if let Some(t) = frame.get("_t") {
+                            let t: Vec<i32> = t.split_whitespace().filter_map(|v| v.parse().ok()).collect();
+                            if t.len() == 3 {
+                                transform.translation = (t[0], t[1], t[2]);
+                            }
+                        }
+                    }
+                    nodes.insert(id, Node::Transform { child, transform });
+                },
+                b"nGRP" => {
+                    let id = content.i32()?;
+                    content.dict()?;
+                    let count = content.i32()?.max(0);
+                    let mut children = Vec::with_capacity(count as usize);
+                    for _ in 0..count {
+                        children.push(content.i32()?);
+                    }
+                    nodes.insert(id, Node::Group { children });
+                },
+                b"nSHP" => {
+                    let id = content.i32()?;
+                    content.dict()?;
+                    let count = content.i32()?.max(0);
+                    let mut shape_models = Vec::with_capacity(count as usize);
+                    for _ in 0..count {
+                        shape_models.push(content.i32()?);
+                        content.dict()?;
+                    }
+                    nodes.insert(id, Node::Shape { models: shape_models });
+                },
+                // materials, layers, cameras and the rest don't affect voxels
+                _ => {},
+            }
+        }
+
+        let mut instances = Vec::new();
+        if nodes.contains_key(&0) {
+            collect_instances(&nodes, 0, VoxTransform::default(), 0, &mut instances);
+        } else {
+            instances.extend((0..models.len()).map(|model| VoxInstance {
+                model,
+                transform: VoxTransform::default(),
+            }));
+        }
+        instances.retain(|instance| instance.model < models.len());
+
+        Ok(VoxFile {
+            models,
+            palette: palette.unwrap_or_else(grey_palette),
+            instances,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), VoxError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the file with a scene graph of one transform per instance.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), VoxError> {
+        let mut children = Vec::new();
+        for model in self.models.iter() {
+            let mut size = Vec::new();
+            put_i32(&mut size, model.size.0);
+            put_i32(&mut size, model.size.1);
+            put_i32(&mut size, model.size.2);
+            put_chunk(&mut children, b"SIZE", &size);
+
+            let mut xyzi = Vec::with_capacity(4 + model.voxels.len() * 4);
+            put_i32(&mut xyzi, model.voxels.len() as i32);
+            for &(x, y, z, c) in model.voxels.iter() {
+                xyzi.extend_from_slice(&[x, y, z, c]);
+            }
+            put_chunk(&mut children, b"XYZI", &xyzi);
+        }
+
+        // root transform 0 -> group 1 -> (transform, shape) pairs from 2 up
+        let mut root = Vec::new();
+        put_i32(&mut root, 0);
+        put_i32(&mut root, 0);
+        put_i32(&mut root, 1);
+        put_i32(&mut root, -1);
+        put_i32(&mut root, -1);
+        put_i32(&mut root, 1);
+        put_i32(&mut root, 0);
+        put_chunk(&mut children, b"nTRN", &root);
+
+        let mut group = Vec::new();
+        put_i32(&mut group, 1);
+        put_i32(&mut group, 0);
+        put_i32(&mut group, self.instances.len() as i32);
+        for i in 0..self.instances.len() {
+            put_i32(&mut group, 2 + 2 * i as i32);
+        }
+        put_chunk(&mut children, b"nGRP", &group);
+
+        for (i, instance) in self.instances.iter().enumerate() {
+            let id = 2 + 2 * i as i32;
+            let t = instance.transform.translation;
+            let mut node = Vec::new();
+            put_i32(&mut node, id);
+            put_i32(&mut node, 0);
+            put_i32(&mut node, id + 1);
+            put_i32(&mut node, -1);
+            put_i32(&mut node, 0);
+            put_i32(&mut node, 1);
+            let translation = format!("{} {} {}", t.0, t.1, t.2);
+            let mut frame = vec![("_t", translation.as_str())];
+            let rotation = rotation_to_byte(&instance.transform.rotation).map(|r| r.to_string());
+            if let Some(ref r) = rotation {
+                frame.push(("_r", r.as_str()));
+            }
+            put_dict(&mut node, &frame);
+            put_chunk(&mut children, b"nTRN", &node);
+
+            let mut shape = Vec::new();
+            put_i32(&mut shape, id + 1);
+            put_i32(&mut shape, 0);
+            put_i32(&mut shape, 1);
+            put_i32(&mut shape, instance.model as i32);
+            put_i32(&mut shape, 0);
+            put_chunk(&mut children, b"nSHP", &shape);
+        }
+
+        let mut rgba = Vec::with_capacity(256 * 4);
+        for i in 1..257 {
+            rgba.extend_from_slice(&self.palette.get(i).cloned().unwrap_or([0, 0, 0, 255]));
+        }
+        put_chunk(&mut children, b"RGBA", &rgba);
+
+        writer.write_all(VOX_MAGIC)?;
+        writer.write_all(&VOX_VERSION.to_le_bytes())?;
+        writer.write_all(b"MAIN")?;
+        writer.write_all(&0i32.to_le_bytes())?;
+        writer.write_all(&(children.len() as i32).to_le_bytes())?;
+        writer.write_all(&children)?;
+        Ok(())
+    }
+
+    /// Every voxel of every instance, in our world coordinates relative to
+    /// the scene origin, with its colour index.
+    pub fn voxels(&self) -> Vec<((i32, i32, i32), u8)> {
+        let mut voxels = Vec::new();
+        for instance in self.instances.iter() {
+            let model = &self.models[instance.model];
+            // models rotate and translate about their centre
+            let centre = (model.size.0 / 2, model.size.1 / 2, model.size.2 / 2);
+            for &(x, y, z, c) in model.voxels.iter() {
+                let local = (x as i32 - centre.0, y as i32 - centre.1, z as i32 - centre.2);
+                voxels.push((from_vox(instance.transform.apply(local)), c));
+            }
+        }
+        voxels
+    }
+
+    /// Calls `place` for each of the file's voxels, with the scene origin at
+    /// `origin`. `place` can write into loaded chunks, queue blocks for
+    /// unloaded ones, or fill new `ChunkData`.
+    pub fn place<F>(&self, mapping: &VoxPaletteMap, origin: (i32, i32, i32), mut place: F)
+    where F: FnMut((i32, i32, i32), Voxel)
+    {
+        for ((x, y, z), c) in self.voxels() {
+            let voxel = mapping.voxel(c);
+            if voxel != AIR {
+                place((origin.0 + x, origin.1 + y, origin.2 + z), voxel);
+            }
+        }
+    }
+
+    /// The file's voxels as chunks, everything else in them air.
+    pub fn to_chunks(&self, mapping: &VoxPaletteMap, origin: (i32, i32, i32)) -> FnvHashMap<(i32, i32, i32), ChunkData> {
+        let mut chunks: FnvHashMap<(i32, i32, i32), ChunkData> = FnvHashMap::default();
+        self.place(mapping, origin, |position, voxel| {
+            let (index, local) = world_to_chunk(position);
+            chunks.entry(index).or_insert_with(ChunkData::default).set_voxel(local, voxel);
+        });
+        chunks
+    }
+
+    /// Exports the voxels in the box from `min` to `max` inclusive, coloured
+    /// by the block registry and split into as many models as the size limit
+    /// needs.
+    ///
+    /// `lookup` resolves a world voxel coordinate to its voxel, `None` for
+    /// unloaded space, which exports as empty. `VoxelWorld::get_voxel` or a
+    /// map of chunks both work.
+    pub fn from_world<F>(
+        registry: &BlockRegistry,
+        min: (i32, i32, i32),
+        max: (i32, i32, i32),
+        mut lookup: F,
+    ) -> Result<VoxFile, VoxError>
+    where F: FnMut((i32, i32, i32)) -> Option<Voxel>
+    {
+        let mut palette = vec![[0; 4]];
+        let mut colour_of: FnvHashMap<Voxel, u8> = FnvHashMap::default();
+        // the box in MagicaVoxel coordinates
+        let vmin = to_vox((min.0, min.1, max.2));
+        let vmax = to_vox((max.0, max.1, min.2));
+
+        let mut models = Vec::new();
+        let mut instances = Vec::new();
+        let step = VOX_MAX_SIZE as usize;
+        for tx in (vmin.0..(vmax.0 + 1)).step_by(step) {
+            for ty in (vmin.1..(vmax.1 + 1)).step_by(step) {
+                for tz in (vmin.2..(vmax.2 + 1)).step_by(step) {
+                    let size = (
+                        (vmax.0 - tx + 1).min(VOX_MAX_SIZE),
+                        (vmax.1 - ty + 1).min(VOX_MAX_SIZE),
+                        (vmax.2 - tz + 1).min(VOX_MAX_SIZE),
+                    );
+                    let mut model = VoxModel { size, voxels: Vec::new() };
+                    for x in 0..size.0 {
+                        for y in 0..size.1 {
+                            for z in 0..size.2 {
+                                let position = from_vox((tx + x, ty + y, tz + z));
+                                let voxel = match lookup(position) {
+                                    Some(voxel) if voxel != AIR => voxel,
+                                    _ => continue,
+                                };
+                                let colour = match colour_of.get(&voxel).cloned() {
+                                    Some(colour) => colour,
+                                    None => {
+                                        if palette.len() > 255 {
+                                            return Err(VoxError::TooManyBlocks);
+                                        }
+                                        let c = registry.get(voxel.id).map(|def| def.color).unwrap_or([1., 1., 1., 1.]);
+                                        palette.push([
+                                            (c[0] * 255.) as u8,
+                                            (c[1] * 255.) as u8,
+                                            (c[2] * 255.) as u8,
+                                            (c[3] * 255.) as u8,
+                                        ]);
+                                        let colour = (palette.len() - 1) as u8;
+                                        colour_of.insert(voxel, colour);
+                                        colour
+                                    },
+                                };
+                                model.voxels.push((x as u8, y as u8, z as u8, colour));
+                            }
+                        }
+                    }
+                    if model.voxels.is_empty() {
+                        continue;
+                    }
+                    instances.push(VoxInstance {
+                        model: models.len(),
+                        transform: VoxTransform {
+                            translation: (tx + size.0 / 2, ty + size.1 / 2, tz + size.2 / 2),
+                            ..VoxTransform::default()
+                        },
+                    });
+                    models.push(model);
+                }
+            }
+        }
+
+        Ok(VoxFile { models, palette, instances })
+    }
+}
+
+/// Which block each palette colour becomes on import.
+///
+/// Starts by matching each colour to the block whose registry colour is
+/// closest; individual colours can then be pointed at specific blocks.
+#[derive(Clone, Debug)]
+pub struct VoxPaletteMap {
+    voxels: Vec<Voxel>,
+}
+
+impl VoxPaletteMap {
+    pub fn from_registry(registry: &BlockRegistry, palette: &[[u8; 4]]) -> Self {
+        let blocks: Vec<(Voxel, [f32; 4])> = registry.iter()
+            .map(|def| (Voxel::new(def.id), def.color))
+            .filter(|&(voxel, _)| voxel != AIR)
+            .collect();
+        let mut voxels = vec![AIR; 256];
+        for (i, colour) in palette.iter().enumerate().skip(1).take(255) {
+            let distance = |c: &[f32; 4]| {
+                (0..4).map(|k| {
+                    let d = c[k] * 255. - colour[k] as f32;
+                    d * d
+                }).sum::<f32>()
+            };
+            voxels[i] = blocks.iter()
+                .min_by(|a, b| distance(&a.1).partial_cmp(&distance(&b.1)).unwrap_or(::std::cmp::Ordering::Equal))
+                .map(|&(voxel, _)| voxel)
+                .unwrap_or(AIR);
+        }
+        VoxPaletteMap { voxels }
+    }
+
+    pub fn set(&mut self, colour: u8, voxel: Voxel) {
+        self.voxels[colour as usize] = voxel;
+    }
+
+    #[inline]
+    pub fn voxel(&self, colour: u8) -> Voxel {
+        if colour == 0 { AIR } else { self.voxels[colour as usize] }
+    }
+}
+
+#[inline]
+fn from_vox(p: (i32, i32, i32)) -> (i32, i32, i32) {
+    (p.0, p.2, -p.1)
+}
+
+#[inline]
+fn to_vox(p: (i32, i32, i32)) -> (i32, i32, i32) {
+    (p.0, -p.2, p.1)
+}
+
+/// MagicaVoxel's own default palette isn't bundled, so files without an
+/// `RGBA` chunk get a grey ramp instead.
+fn grey_palette() -> Vec<[u8; 4]> {
+    (0..256).map(|i| [i as u8, i as u8, i as u8, 255]).collect()
+}
+
+fn rotation_to_byte(rotation: &[[i32; 3]; 3]) -> Option<u8> {
+    if *rotation == VoxTransform::default().rotation {
+        return None;
+    }
+    let column = |row: &[i32; 3]| row.iter().position(|&v| v != 0).unwrap_or(0) as u8;
+    let mut byte = column(&rotation[0]) | (column(&rotation[1]) << 2);
+    for (row, values) in rotation.iter().enumerate() {
+        if values.iter().any(|&v| v < 0) {
+            byte |= 1 << (4 + row);
+        }
+    }
+    Some(byte)
+}
+
+fn collect_instances(
+    nodes: &FnvHashMap<i32, Node>,
+    id: i32,
+    transform: VoxTransform,
+    depth: usize,
+    instances: &mut Vec<VoxInstance>,
+) {
+    // a malformed graph could loop forever
+    if depth > 64 {
+        return;
+    }
+    match nodes.get(&id) {
+        Some(&Node::Transform { child, transform: ref local }) => {
+            collect_instances(nodes, child, transform.then(local), depth + 1, instances);
+        },
+        Some(&Node::Group { ref children }) => {
+            for &child in children.iter() {
+                collect_instances(nodes, child, transform, depth + 1, instances);
+            }
+        },
+        Some(&Node::Shape { ref models }) => {
+            instances.extend(models.iter().filter(|&&model| model >= 0).map(|&model| VoxInstance {
+                model: model as usize,
+                transform,
+            }));
+        },
+        None => {},
+    }
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Cursor { bytes }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], VoxError> {
+        if self.bytes.len() < n {
+            return Err(VoxError::Corrupt("unexpected end of chunk"));
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn i32(&mut self) -> Result<i32, VoxError> {
+        let b = self.bytes(4)?;
+        Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn string(&mut self) -> Result<String, VoxError> {
+        let len = self.i32()?.max(0) as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    fn dict(&mut self) -> Result<FnvHashMap<String, String>, VoxError> {
+        let count = self.i32()?.max(0);
+        let mut dict = FnvHashMap::default();
+        for _ in 0..count {
+            let key = self.string()?;
+            let value = self.string()?;
+            dict.insert(key, value);
+        }
+        Ok(dict)
+    }
+
+    /// A chunk's id, content and children.
+    fn chunk(&mut self) -> Result<([u8; 4], &'a [u8], &'a [u8]), VoxError> {
+        let id = self.bytes(4)?;
+        let content = self.i32()?.max(0) as usize;
+        let children = self.i32()?.max(0) as usize;
+        Ok(([id[0], id[1], id[2], id[3]], self.bytes(content)?, self.bytes(children)?))
+    }
+}
+
+fn put_i32(out: &mut Vec<u8>, v: i32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_dict(out: &mut Vec<u8>, entries: &[(&str, &str)]) {
+    put_i32(out, entries.len() as i32);
+    for &(key, value) in entries.iter() {
+        put_i32(out, key.len() as i32);
+        out.extend_from_slice(key.as_bytes());
+        put_i32(out, value.len() as i32);
+        out.extend_from_slice(value.as_bytes());
+    }
+}
+
+fn put_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    out.extend_from_slice(id);
+    put_i32(out, content.len() as i32);
+    put_i32(out, 0);
+    out.extend_from_slice(content);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> VoxFile {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/vox").join(name);
+        VoxFile::load(&path).unwrap_or_else(|e| panic!("failed to load {:?}: {}", path, e))
+    }
+
+    fn round_trip(file: &VoxFile) -> VoxFile {
+        let mut bytes = Vec::new();
+        file.write_to(&mut bytes).unwrap();
+        VoxFile::read_from(&mut &bytes[..]).unwrap()
+    }
+
+    fn sorted_voxels(file: &VoxFile) -> Vec<((i32, i32, i32), u8)> {
+        let mut voxels = file.voxels();
+        voxels.sort();
+        voxels
+    }
+
+    fn assert_same(a: &VoxFile, b: &VoxFile) {
+        assert_eq!(a.models.len(), b.models.len());
+        for (a, b) in a.models.iter().zip(b.models.iter()) {
+            assert_eq!(a.size, b.size);
+            assert_eq!(a.voxels, b.voxels);
+        }
+        assert_eq!(a.palette, b.palette);
+        assert_eq!(sorted_voxels(a), sorted_voxels(b));
+    }
+
+    #[test]
+    fn single_voxel() {
+        let file = fixture("single.vox");
+        assert_eq!(file.models.len(), 1);
+        assert_eq!(file.models[0].size, (1, 1, 1));
+        assert_eq!(file.models[0].voxels, vec![(0, 0, 0, 1)]);
+        assert_eq!(file.palette[1], [200, 30, 40, 255]);
+        assert_eq!(file.voxels(), vec![((0, 0, 0), 1)]);
+        assert_same(&file, &round_trip(&file));
+    }
+
+    #[test]
+    fn multi_model_scene() {
+        let file = fixture("multi.vox");
+        assert_eq!(file.models.len(), 2);
+        assert_eq!(file.models[0].size, (2, 3, 1));
+        assert_eq!(file.models[1].size, (1, 1, 4));
+        assert_eq!(file.instances.len(), 2);
+        assert_eq!(file.instances[1].transform.rotation, [[0, 1, 0], [-1, 0, 0], [0, 0, 1]]);
+
+        let voxels = sorted_voxels(&file);
+        assert_eq!(voxels.len(), 5);
+        // model 0 is centred on (-5, 0, 2), so its corner voxel sits one back along x and y
+        assert!(voxels.contains(&((-6, 2, 1), 1)));
+        // model 1 is turned a quarter and centred on (4, 6, -1)
+        assert!(voxels.contains(&((4, -3, -6), 3)));
+        assert!(voxels.contains(&((4, 0, -6), 3)));
+
+        assert_same(&file, &round_trip(&file));
+    }
+
+    #[test]
+    fn palette_edges() {
+        let file = fixture("palette_edge.vox");
+        assert_eq!(file.palette.len(), 256);
+        assert_eq!(file.palette[1], [255, 255, 255, 255]);
+        assert_eq!(file.palette[254], [1, 2, 3, 4]);
+        assert_eq!(file.palette[255], [250, 128, 0, 77]);
+        assert_eq!(file.models[0].voxels, vec![(0, 0, 0, 1), (1, 0, 0, 255), (2, 0, 0, 254)]);
+        assert_same(&file, &round_trip(&file));
+    }
+
+    #[test]
+    fn places_into_chunks() {
+        let file = fixture("single.vox");
+        let mut mapping = VoxPaletteMap::from_registry(&BlockRegistry::default(), &file.palette);
+        mapping.set(1, Voxel::new(1));
+        let chunks = file.to_chunks(&mapping, (-1, 40, 3));
+        assert_eq!(chunks.len(), 1);
+        let (index, local) = world_to_chunk((-1, 40, 3));
+        assert_eq!(chunks[&index].get_voxel(local), Voxel::new(1));
+    }
+
+    #[test]
+    fn exports_chunks() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/blocks.ron");
+        let registry = BlockRegistry::load(&path).unwrap();
+        let (stone, sand) = (registry.voxel("stone").unwrap(), registry.voxel("sand").unwrap());
+        let mut chunks: FnvHashMap<(i32, i32, i32), ChunkData> = FnvHashMap::default();
+        let placed = [((-1, 0, 0), stone), ((0, 0, 0), sand), ((300, 2, -5), stone)];
+        for &(position, voxel) in placed.iter() {
+            let (index, local) = world_to_chunk(position);
+            chunks.entry(index).or_insert_with(ChunkData::default).set_voxel(local, voxel);
+        }
+        let lookup = |position| {
+            let (index, local) = world_to_chunk(position);
+            chunks.get(&index).map(|data| data.get_voxel(local))
+        };
+
+        // wider than one model can hold
+        let file = VoxFile::from_world(&registry, (-1, 0, -5), (300, 2, 0), lookup).unwrap();
+        assert_eq!(file.models.len(), 2);
+        assert_eq!(file.palette.len(), 3);
+
+        let file = round_trip(&file);
+        let mapping = VoxPaletteMap::from_registry(&registry, &file.palette);
+        let mut read = Vec::new();
+        file.place(&mapping, (0, 0, 0), |position, voxel| read.push((position, voxel)));
+        read.sort_by_key(|&(position, _)| position);
+        let mut expected = placed.to_vec();
+        expected.sort_by_key(|&(position, _)| position);
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        match VoxFile::read_from(&mut &b"PK\x03\x04 not a vox file"[..]) {
+            Err(VoxError::BadMagic) => {},
+            other => panic!("expected BadMagic, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -1,103 +1,135 @@
 use std::ops::Deref;
+use std::sync::Arc;
 use super::{
     ChunkData,
     VoxelWorld,
+    WorldLight,
+    ChunkLight,
     Voxel,
-    CHUNK_SIZE,
+    VoxelFace,
     Side,
+    world_to_chunk,
 };
 use specs::{
     Storage,
     storage::MaskedStorage,
 };
-use shred::ReadExpect;
+use fnv::FnvHashMap;
 
-pub struct WorldSlice<'a, 'b: 'a, T: 'b> {
-    chunk_datas: &'a Storage<'b, ChunkData, T>,
-    voxel_world: &'a ReadExpect<'b, VoxelWorld>,
+/// Somewhere meshing can read a chunk and its surroundings from. Voxel
+/// coordinates are relative to the chunk's origin.
+pub trait VoxelSource {
+    /// A chunk of the neighbourhood by its offset from the origin chunk, each
+    /// coordinate in `-1..=1`.
+    fn chunk(&self, offset: (i32, i32, i32)) -> Option<&ChunkData>;
 
-    /// chunk index representing the origin for this slice
+    fn get_voxel(&self, index: (i32, i32, i32)) -> Option<Voxel>;
+
+    #[inline(always)]
+    fn get_voxel_face(&self, index: (i32, i32, i32), side: Side) -> Option<VoxelFace> {
+        self.get_voxel(index).map(|voxel| voxel.face(side))
+    }
+
+    /// Light at a voxel, sky light in the high nibble and block light in the
+    /// low. Sources without lighting return `None` everywhere, and meshes
+    /// built from them are fully lit.
+    #[inline(always)]
+    fn get_light(&self, _index: (i32, i32, i32)) -> Option<u8> {
+        None
+    }
+}
+
+/// A view of one chunk among a plain map of chunks, for meshing outside the
+/// ECS world, such as exports.
+pub struct ChunkMapSlice<'a> {
+    chunks: &'a FnvHashMap<(i32, i32, i32), ChunkData>,
     origin: (i32, i32, i32),
+}
 
-    /// the Moore neighborhood of chunk datas for immediate access
-    moore_neighborhood: [[[Option<&'a ChunkData>; 3]; 3]; 3],
+impl<'a> ChunkMapSlice<'a> {
+    pub fn new(chunks: &'a FnvHashMap<(i32, i32, i32), ChunkData>, origin: (i32, i32, i32)) -> Self {
+        ChunkMapSlice { chunks, origin }
+    }
 }
 
-impl<'a, 'b: 'a, T: 'b> WorldSlice<'a, 'b, T>
-where T: Deref<Target=MaskedStorage<ChunkData>> {
-    pub fn new(chunk_datas: &'a Storage<'b, ChunkData, T>,
-               voxel_world: &'a ReadExpect<'b, VoxelWorld>,
-               origin: (i32, i32, i32)) -> WorldSlice<'a, 'b, T>
-    {
-        // Produce the Moore neighborhood of the origin chunk
-        let mut moore_neighborhood: [[[Option<&'a ChunkData>; 3]; 3]; 3] = [[[None; 3]; 3]; 3];
+impl<'a> VoxelSource for ChunkMapSlice<'a> {
+    #[inline]
+    fn chunk(&self, offset: (i32, i32, i32)) -> Option<&ChunkData> {
+        self.chunks.get(&(self.origin.0 + offset.0, self.origin.1 + offset.1, self.origin.2 + offset.2))
+    }
+
+    #[inline(always)]
+    fn get_voxel(&self, index: (i32, i32, i32)) -> Option<Voxel> {
+        let (offset, local) = world_to_chunk(index);
+        self.chunk(offset).map(|chunk_data| chunk_data.get_voxel(local))
+    }
+}
 
-        let (origin_x, origin_y, origin_z) = origin;
+/// An owned copy of a chunk and the chunks around it, with their light, so
+/// it can be meshed on another thread while the world moves on.
+pub struct ChunkSnapshot {
+    /// The Moore neighbourhood, indexed by offset from the origin chunk
+    /// plus one.
+    chunks: [[[Option<ChunkData>; 3]; 3]; 3],
+    lights: [[[Option<Arc<ChunkLight>>; 3]; 3]; 3],
+}
 
+impl ChunkSnapshot {
+    pub fn new<'e, T>(
+        chunk_datas: &Storage<'e, ChunkData, T>,
+        voxel_world: &VoxelWorld,
+        light: &WorldLight,
+        origin: (i32, i32, i32),
+    ) -> Self
+    where T: Deref<Target=MaskedStorage<ChunkData>>
+    {
+        let mut snapshot = ChunkSnapshot {
+            chunks: Default::default(),
+            lights: Default::default(),
+        };
         for x in 0..3 {
             for y in 0..3 {
                 for z in 0..3 {
-                    let chunk_data = voxel_world.get_entity((origin_x + (x as i32 - 1), origin_y + (y as i32 - 1), origin_z + (z as i32 - 1)))
-                        .and_then(|entity| {
-                            chunk_datas.get(entity)
-                        });
-                    moore_neighborhood[x][y][z] = chunk_data;
+                    let index = (origin.0 + x as i32 - 1, origin.1 + y as i32 - 1, origin.2 + z as i32 - 1);
+                    snapshot.chunks[x][y][z] = voxel_world.get_entity(index)
+                        .and_then(|entity| chunk_datas.get(entity))
+                        .cloned();
+                    snapshot.lights[x][y][z] = light.snapshot(index);
                 }
             }
         }
-
-        WorldSlice {
-            chunk_datas,
-            voxel_world,
-            origin,
-            moore_neighborhood,
-        }
+        snapshot
     }
+}
 
-    #[inline(always)]
-    pub fn get_voxel(&self, index: (i32, i32, i32)) -> Option<Voxel> {
-        let origin_offset = (
-            adjust_origin_offset(index.0) / CHUNK_SIZE as i32,
-            adjust_origin_offset(index.1) / CHUNK_SIZE as i32,
-            adjust_origin_offset(index.2) / CHUNK_SIZE as i32,
-        );
-        let global_index = (origin_offset.0 + self.origin.0, origin_offset.1 + self.origin.1, origin_offset.2 + self.origin.2);
-        let chunk_index = (
-            adjust(index.0) % CHUNK_SIZE,
-            adjust(index.1) % CHUNK_SIZE,
-            adjust(index.2) % CHUNK_SIZE,
-        );
+#[inline(always)]
+fn in_moore(offset: (i32, i32, i32)) -> bool {
+    (offset.0 >= -1 && offset.0 <= 1) && (offset.1 >= -1 && offset.1 <= 1) && (offset.2 >= -1 && offset.2 <= 1)
+}
 
-        let chunk_data: Option<&ChunkData> = if self.chunk_is_in_moore(origin_offset) {
-            let (o_x, o_y, o_z) = origin_offset;
-            self.moore_neighborhood[(o_x + 1) as usize][(o_y + 1) as usize][(o_z + 1) as usize]
-        } else {
-            self.voxel_world.get_entity(global_index)
-            .and_then(|entity| {
-                self.chunk_datas.get(entity)
-            })
-        };
-        chunk_data.map(|&cd| cd.get_voxel(chunk_index))
+impl VoxelSource for ChunkSnapshot {
+    #[inline]
+    fn chunk(&self, offset: (i32, i32, i32)) -> Option<&ChunkData> {
+        if !in_moore(offset) {
+            return None;
+        }
+        self.chunks[(offset.0 + 1) as usize][(offset.1 + 1) as usize][(offset.2 + 1) as usize].as_ref()
     }
 
     #[inline(always)]
-    pub fn get_voxel_face(&self, index: (i32, i32, i32), _side: Side) -> Option<Voxel> {
-        self.get_voxel(index)
+    fn get_voxel(&self, index: (i32, i32, i32)) -> Option<Voxel> {
+        let (offset, local) = world_to_chunk(index);
+        self.chunk(offset).map(|chunk_data| chunk_data.get_voxel(local))
     }
 
     #[inline(always)]
-    fn chunk_is_in_moore(&self, index: (i32, i32, i32)) -> bool {
-        let (x, y, z) = index;
-        (x >= -1 && x <= 1) && (y >= -1 && y <= 1) && (z >= -1 && z <= 1)
+    fn get_light(&self, index: (i32, i32, i32)) -> Option<u8> {
+        let (offset, local) = world_to_chunk(index);
+        if !in_moore(offset) {
+            return None;
+        }
+        self.lights[(offset.0 + 1) as usize][(offset.1 + 1) as usize][(offset.2 + 1) as usize]
+            .as_ref()
+            .map(|light| light.get(local))
     }
 }
-
-#[inline(always)]
-fn adjust(v: i32) -> usize {
-    if v < 0 { (v + CHUNK_SIZE as i32) as usize } else { v as usize }
-}
-
-#[inline(always)]
-fn adjust_origin_offset(v: i32) -> i32 {
-    if v < 0 { v - CHUNK_SIZE as i32 } else { v }
-}